@@ -1,12 +1,14 @@
-#![feature(test)]
-
-extern crate test;
-
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 
 use crossbeam::queue::SegQueue;
 use futures::{channel::oneshot, executor::block_on, future, stream::StreamExt as _, task::Poll};
-use std::{sync::Arc, thread};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Waker},
+    thread,
+};
 
 pub fn polling_benchmark(c: &mut Criterion) {
     {
@@ -56,7 +58,7 @@ pub fn polling_benchmark(c: &mut Criterion) {
             let txs = txs.clone();
 
             thread::spawn(move || {
-                while let Ok((n, tx)) = txs.pop() {
+                while let Some((n, tx)) = txs.pop() {
                     let _ = tx.send(n);
                 }
             });
@@ -102,7 +104,7 @@ pub fn polling_benchmark(c: &mut Criterion) {
             let txs = txs.clone();
 
             thread::spawn(move || {
-                while let Ok((n, tx)) = txs.pop() {
+                while let Some((n, tx)) = txs.pop() {
                     let _ = tx.send(n);
                 }
             });
@@ -128,5 +130,242 @@ pub fn polling_benchmark(c: &mut Criterion) {
     }
 }
 
-criterion_group!(unordered, polling_benchmark);
+/// A leaf future which behaves like most I/O resources and channels: it keeps
+/// the last waker it was polled with, and only replaces it if the new waker
+/// wouldn't wake the same task.
+struct Rewake {
+    waker: Option<Waker>,
+    remaining: usize,
+    clones: usize,
+}
+
+impl Future for Rewake {
+    type Output = usize;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.remaining == 0 {
+            return Poll::Ready(self.clones);
+        }
+
+        self.remaining -= 1;
+
+        match &self.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => (),
+            _ => {
+                self.waker = Some(cx.waker().clone());
+                self.clones += 1;
+            }
+        }
+
+        if let Some(waker) = &self.waker {
+            waker.wake_by_ref();
+        }
+
+        Poll::Pending
+    }
+}
+
+pub fn will_wake_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("will_wake # futures");
+
+    for i in [10, 100, 1000, 10000].iter() {
+        group.bench_with_input(BenchmarkId::new("unicycle", i), i, |b, i| {
+            b.iter(|| unicycle(*i, 16))
+        });
+        group.bench_with_input(BenchmarkId::new("futures-rs", i), i, |b, i| {
+            b.iter(|| futures_rs(*i, 16))
+        });
+    }
+
+    fn rewake(polls: usize) -> Rewake {
+        Rewake {
+            waker: None,
+            remaining: polls,
+            clones: 0,
+        }
+    }
+
+    fn unicycle(num: usize, polls: usize) -> usize {
+        let mut futures = unicycle::FuturesUnordered::new();
+
+        for _ in 0..num {
+            futures.push(rewake(polls));
+        }
+
+        block_on(async move {
+            let mut clones = 0;
+
+            while let Some(n) = futures.next().await {
+                clones += n;
+            }
+
+            clones
+        })
+    }
+
+    fn futures_rs(num: usize, polls: usize) -> usize {
+        let mut futures = futures::stream::FuturesUnordered::new();
+
+        for _ in 0..num {
+            futures.push(rewake(polls));
+        }
+
+        block_on(async move {
+            let mut clones = 0;
+
+            while let Some(n) = futures.next().await {
+                clones += n;
+            }
+
+            clones
+        })
+    }
+}
+
+/// Number of bits in the sets used by the wake density benchmarks.
+const CAPACITY: usize = 1 << 20;
+
+/// Evenly spread out `woken` indexes across the capacity of a set.
+fn spread(capacity: usize, woken: usize) -> impl Iterator<Item = usize> {
+    (0..capacity).step_by(capacity / woken)
+}
+
+pub fn bitset_benchmark(c: &mut Criterion) {
+    use unicycle::allocator::Global;
+    use unicycle::bitset::{Flat, Layered, RawBitSet};
+
+    let mut group = c.benchmark_group("bitset drain # woken of 1M");
+
+    for i in [1, 16, 256, 4096, 65536].iter() {
+        group.bench_with_input(BenchmarkId::new("flat", i), i, |b, i| {
+            let mut set = Flat::new();
+            set.reserve(CAPACITY, &Global);
+            b.iter(|| drain(&mut set, *i));
+            // Safety: The set has only been grown through the global allocator.
+            unsafe { set.free(&Global) };
+        });
+        group.bench_with_input(BenchmarkId::new("layered", i), i, |b, i| {
+            let mut set = Layered::new();
+            set.reserve(CAPACITY, &Global);
+            b.iter(|| drain(&mut set, *i));
+            // Safety: The set has only been grown through the global allocator.
+            unsafe { set.free(&Global) };
+        });
+        group.bench_with_input(BenchmarkId::new("hibitset", i), i, |b, i| {
+            let mut set = hibitset::AtomicBitSet::new();
+            b.iter(|| hibitset(&mut set, *i))
+        });
+    }
+
+    fn drain<B>(set: &mut B, woken: usize) -> usize
+    where
+        B: RawBitSet,
+    {
+        for index in spread(CAPACITY, woken) {
+            set.set_shared(index);
+        }
+
+        set.drain().fold(0, usize::wrapping_add)
+    }
+
+    fn hibitset(set: &mut hibitset::AtomicBitSet, woken: usize) -> usize {
+        use hibitset::DrainableBitSet as _;
+
+        for index in spread(CAPACITY, woken) {
+            set.add_atomic(index as u32);
+        }
+
+        set.drain()
+            .fold(0, |sum, index| sum.wrapping_add(index as usize))
+    }
+}
+
+/// The waker of a [Parked] future, shared with the benchmark.
+type Slot = Arc<Mutex<Option<Waker>>>;
+
+/// A leaf future which never completes, and stores its waker in a slot shared
+/// with the benchmark so that it can be woken on demand.
+struct Parked {
+    slot: Slot,
+}
+
+impl Future for Parked {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.lock().unwrap();
+
+        if !matches!(&*slot, Some(waker) if waker.will_wake(cx.waker())) {
+            *slot = Some(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+}
+
+pub fn sparse_wake_benchmark(c: &mut Criterion) {
+    use unicycle::bitset::{Flat, Layered, RawBitSet};
+
+    const CHILDREN: usize = 100_000;
+
+    let mut group = c.benchmark_group("sparse wakes # woken of 100k");
+
+    for i in [1, 16, 256, 4096].iter() {
+        group.bench_with_input(BenchmarkId::new("flat", i), i, |b, i| {
+            let (mut futures, slots) = setup::<Flat>();
+            b.iter(|| cycle(&mut futures, &slots, *i))
+        });
+        group.bench_with_input(BenchmarkId::new("layered", i), i, |b, i| {
+            let (mut futures, slots) = setup::<Layered>();
+            b.iter(|| cycle(&mut futures, &slots, *i))
+        });
+    }
+
+    type Set<B> = unicycle::FuturesUnordered<Parked, unicycle::lock::DefaultLock, B>;
+
+    fn setup<B>() -> (Set<B>, Vec<Slot>)
+    where
+        B: RawBitSet,
+    {
+        let mut futures = Set::<B>::with_bit_set();
+        let mut slots = Vec::new();
+
+        for _ in 0..CHILDREN {
+            let slot = Arc::new(Mutex::new(None));
+            futures.push(Parked { slot: slot.clone() });
+            slots.push(slot);
+        }
+
+        // Poll every child once so that they register their wakers.
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        while futures.poll_next_unpin(&mut cx).is_ready() {}
+
+        (futures, slots)
+    }
+
+    fn cycle<B>(futures: &mut Set<B>, slots: &[Slot], woken: usize)
+    where
+        B: RawBitSet,
+    {
+        for index in spread(CHILDREN, woken) {
+            if let Some(waker) = &*slots[index].lock().unwrap() {
+                waker.wake_by_ref();
+            }
+        }
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(futures.poll_next_unpin(&mut cx).is_pending());
+    }
+}
+
+criterion_group!(
+    unordered,
+    polling_benchmark,
+    will_wake_benchmark,
+    bitset_benchmark,
+    sparse_wake_benchmark
+);
 criterion_main!(unordered);
@@ -0,0 +1,23 @@
+//! Run sequences of operations against the reference model in
+//! `tests/model`, with:
+//!
+//! ```text
+//! cargo +nightly fuzz run unordered
+//! ```
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/model/mod.rs"]
+mod model;
+
+fuzz_target!(|input: (u8, Vec<model::Op>)| {
+    let (target, ops) = input;
+
+    match target % 3 {
+        0 => model::futures(&ops),
+        1 => model::streams(&ops),
+        _ => model::pin_slab(target / 3, &ops),
+    }
+});
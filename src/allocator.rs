@@ -0,0 +1,406 @@
+//! Allocator support.
+//!
+//! Storage is allocated through a minimal version of the allocator API, which
+//! is implemented for the [Global] allocator. The `allocator-api2` feature
+//! implements it for every allocator from the [allocator-api2] crate as well,
+//! so that those can be passed to the `new_in` constructors. Enabling the
+//! feature only adds implementations, so the names exported from here are
+//! the same either way.
+//!
+//! This covers the slots of the [PinSlab], its table of slots and the heap of
+//! vacant keys used by [Reuse::Lowest], the wake sets including their [bit
+//! sets] and wake order records, the waker cells, as well as the per-child
+//! records kept for spinner detection, statistics and tracing.
+//!
+//! [allocator-api2]: https://docs.rs/allocator-api2
+//! [PinSlab]: crate::pin_slab::PinSlab
+//! [bit sets]: crate::bitset::RawBitSet
+//! [Reuse::Lowest]: crate::pin_slab::Reuse::Lowest
+
+use alloc::alloc::handle_alloc_error;
+use core::alloc::Layout;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use core::{cmp, mem, slice};
+
+/// The error returned when an allocation fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError;
+
+/// The subset of the allocator API which we use.
+///
+/// With the `allocator-api2` feature this is implemented for every
+/// [allocator-api2] allocator.
+///
+/// # Safety
+///
+/// Implementors must uphold the same contract as the unstable
+/// `core::alloc::Allocator` trait.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::alloc::Layout;
+/// use std::ptr::NonNull;
+/// use unicycle::allocator::{AllocError, Allocator, Global};
+/// use unicycle::bitset::{Flat, RawBitSet};
+///
+/// struct Forward;
+///
+/// unsafe impl Allocator for Forward {
+///     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+///         Global.allocate(layout)
+///     }
+///
+///     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+///         Global.deallocate(ptr, layout)
+///     }
+/// }
+///
+/// let mut set = Flat::new();
+/// set.set(42, &Forward);
+/// assert!(set.test(42));
+///
+/// // Safety: The set has only been grown through `Forward`.
+/// unsafe { set.free(&Forward) };
+/// ```
+///
+/// [allocator-api2]: https://docs.rs/allocator-api2
+pub unsafe trait Allocator {
+    /// Allocate a block of memory fitting the given layout.
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>;
+
+    /// Deallocate the memory referenced by `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by this allocator with the given
+    /// layout.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+/// The global allocator.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Global;
+
+unsafe impl Allocator for Global {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = if layout.size() == 0 {
+            // Safety: alignments are never zero.
+            unsafe { NonNull::new_unchecked(layout.align() as *mut u8) }
+        } else {
+            // Safety: the layout has a non-zero size.
+            NonNull::new(unsafe { alloc::alloc::alloc(layout) }).ok_or(AllocError)?
+        };
+
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            alloc::alloc::dealloc(ptr.as_ptr(), layout);
+        }
+    }
+}
+
+// Safety: This forwards to an allocator which upholds the same contract.
+#[cfg(feature = "allocator-api2")]
+unsafe impl<A> Allocator for A
+where
+    A: allocator_api2::alloc::Allocator,
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        allocator_api2::alloc::Allocator::allocate(self, layout).map_err(|_| AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        allocator_api2::alloc::Allocator::deallocate(self, ptr, layout)
+    }
+}
+
+/// Allocate memory for the given layout, aborting if the allocation fails.
+fn allocate<A>(layout: Layout, alloc: &A) -> NonNull<u8>
+where
+    A: Allocator,
+{
+    match alloc.allocate(layout) {
+        Ok(ptr) => ptr.cast(),
+        Err(..) => handle_alloc_error(layout),
+    }
+}
+
+/// Move a value into memory allocated by the given allocator.
+pub(crate) fn new_box<T, A>(value: T, alloc: &A) -> NonNull<T>
+where
+    A: Allocator,
+{
+    let ptr = allocate(Layout::new::<T>(), alloc).cast::<T>();
+
+    // Safety: the memory was just allocated with the layout of `T`.
+    unsafe {
+        ptr.as_ptr().write(value);
+    }
+
+    ptr
+}
+
+/// Drop a value allocated through [new_box] and free its memory.
+///
+/// # Safety
+///
+/// `ptr` must have been allocated through [new_box] with an equivalent
+/// allocator, and must not be used again.
+pub(crate) unsafe fn drop_box<T, A>(ptr: NonNull<T>, alloc: &A)
+where
+    A: Allocator,
+{
+    ptr::drop_in_place(ptr.as_ptr());
+    alloc.deallocate(ptr.cast(), Layout::new::<T>());
+}
+
+/// Allocate a slice of `len` values, where each value is constructed by calling
+/// `f` with its index.
+///
+/// If `f` panics, the values which have been constructed so far are dropped
+/// and the memory is freed.
+pub(crate) fn new_slice<T, A>(len: usize, mut f: impl FnMut(usize) -> T, alloc: &A) -> NonNull<T>
+where
+    A: Allocator,
+{
+    let layout = Layout::array::<T>(len).expect("slice too large");
+
+    let mut guard = SliceGuard {
+        ptr: allocate(layout, alloc).cast::<T>(),
+        init: 0,
+        layout,
+        alloc,
+    };
+
+    while guard.init < len {
+        // Safety: the memory was allocated for `len` values.
+        unsafe {
+            guard.ptr.as_ptr().add(guard.init).write(f(guard.init));
+        }
+
+        guard.init += 1;
+    }
+
+    let ptr = guard.ptr;
+    mem::forget(guard);
+    ptr
+}
+
+/// Drop a slice allocated through [new_slice] and free its memory.
+///
+/// # Safety
+///
+/// `ptr` must have been allocated through [new_slice] with an equivalent
+/// allocator and the same `len`, and must not be used again.
+pub(crate) unsafe fn drop_slice<T, A>(ptr: NonNull<T>, len: usize, alloc: &A)
+where
+    A: Allocator,
+{
+    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(ptr.as_ptr(), len));
+    // NB: the layout was successfully computed when the slice was allocated.
+    alloc.deallocate(ptr.cast(), Layout::array::<T>(len).unwrap_unchecked());
+}
+
+/// Cleans up a partially initialized slice if constructing a value panics.
+struct SliceGuard<'a, T, A>
+where
+    A: Allocator,
+{
+    ptr: NonNull<T>,
+    init: usize,
+    layout: Layout,
+    alloc: &'a A,
+}
+
+impl<T, A> Drop for SliceGuard<'_, T, A>
+where
+    A: Allocator,
+{
+    fn drop(&mut self) {
+        // Safety: the first `init` values have been initialized, and the
+        // memory was allocated with `layout`.
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr.as_ptr(), self.init));
+            self.alloc.deallocate(self.ptr.cast(), self.layout);
+        }
+    }
+}
+
+/// A growable array whose memory is allocated through an allocator passed in
+/// by the caller, for structures which keep their allocator elsewhere or share
+/// it between several arrays.
+///
+/// Since the array doesn't know its allocator, it doesn't free its memory when
+/// dropped. Owners must call [RawVec::free] with the allocator that every other
+/// operation has been performed with.
+pub(crate) struct RawVec<T> {
+    ptr: NonNull<T>,
+    len: usize,
+    cap: usize,
+}
+
+// Safety: The array owns its values.
+unsafe impl<T> Send for RawVec<T> where T: Send {}
+unsafe impl<T> Sync for RawVec<T> where T: Sync {}
+
+impl<T> RawVec<T> {
+    /// Construct a new empty array, without allocating.
+    pub(crate) const fn new() -> Self {
+        Self {
+            ptr: NonNull::dangling(),
+            len: 0,
+            cap: 0,
+        }
+    }
+
+    /// Append a value, growing the array if needed.
+    pub(crate) fn push<A>(&mut self, value: T, alloc: &A)
+    where
+        A: Allocator,
+    {
+        if self.len == self.cap {
+            self.reallocate(cmp::max(self.cap * 2, 4), alloc);
+        }
+
+        // Safety: We just made sure there's room for another value.
+        unsafe {
+            self.ptr.as_ptr().add(self.len).write(value);
+        }
+
+        self.len += 1;
+    }
+
+    /// Extend the array to `len` values, constructing each new value by calling
+    /// `f`. Unlike [RawVec::push] this allocates exactly what's needed.
+    pub(crate) fn extend_to<A>(&mut self, len: usize, mut f: impl FnMut() -> T, alloc: &A)
+    where
+        A: Allocator,
+    {
+        if len > self.cap {
+            self.reallocate(len, alloc);
+        }
+
+        while self.len < len {
+            // Safety: We just made sure there's room for `len` values.
+            unsafe {
+                self.ptr.as_ptr().add(self.len).write(f());
+            }
+
+            self.len += 1;
+        }
+    }
+
+    /// Drop every value past `len`, keeping the memory allocated.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+
+        let tail = ptr::slice_from_raw_parts_mut(
+            // Safety: `len` is within the initialized values.
+            unsafe { self.ptr.as_ptr().add(len) },
+            self.len - len,
+        );
+
+        // NB: Update the length first, so that a panicking drop leaks values
+        // instead of dropping them twice.
+        self.len = len;
+
+        // Safety: The values past `len` are initialized and no longer
+        // reachable.
+        unsafe {
+            ptr::drop_in_place(tail);
+        }
+    }
+
+    /// Release memory which isn't used by any values.
+    pub(crate) fn shrink_to_fit<A>(&mut self, alloc: &A)
+    where
+        A: Allocator,
+    {
+        if self.cap > self.len {
+            self.reallocate(self.len, alloc);
+        }
+    }
+
+    /// Drop every value and free the memory of the array, leaving it empty.
+    ///
+    /// # Safety
+    ///
+    /// `alloc` must be equivalent to the allocator every other operation on the
+    /// array has been performed with.
+    pub(crate) unsafe fn free<A>(&mut self, alloc: &A)
+    where
+        A: Allocator,
+    {
+        self.truncate(0);
+        self.deallocate(alloc);
+        *self = Self::new();
+    }
+
+    /// Move the values into a new allocation of `cap` values.
+    fn reallocate<A>(&mut self, cap: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        debug_assert!(cap >= self.len);
+
+        let ptr = if cap == 0 {
+            NonNull::dangling()
+        } else {
+            allocate(Layout::array::<T>(cap).expect("capacity overflow"), alloc).cast()
+        };
+
+        // Safety: The new allocation has room for every value, and the values
+        // are moved out of the old one before it's freed.
+        unsafe {
+            ptr::copy_nonoverlapping(self.ptr.as_ptr(), ptr.as_ptr(), self.len);
+            self.deallocate(alloc);
+        }
+
+        self.ptr = ptr;
+        self.cap = cap;
+    }
+
+    /// Deallocate the current memory of the array without touching its values.
+    unsafe fn deallocate<A>(&mut self, alloc: &A)
+    where
+        A: Allocator,
+    {
+        if self.cap != 0 {
+            // NB: the layout was successfully computed when it was allocated.
+            let layout = Layout::array::<T>(self.cap).unwrap_unchecked();
+            alloc.deallocate(self.ptr.cast(), layout);
+        }
+    }
+}
+
+impl<T> Default for RawVec<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deref for RawVec<T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        // Safety: The first `len` values are initialized.
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for RawVec<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The first `len` values are initialized, and we have exclusive
+        // access to them.
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
@@ -0,0 +1,681 @@
+//! Bit set backends used to keep track of which children of an [Unordered]
+//! set have been woken.
+//!
+//! Which backend is used is picked through a type parameter of [Unordered],
+//! which defaults to [DefaultBitSet]. Like [lock backends], this means that a
+//! single binary can use different backends for different sets.
+//!
+//! The following backends are provided:
+//! * [Flat] - a single layer of words. Setting bits and growing the set is
+//!   cheap, but draining it visits every word regardless of how many bits are
+//!   set.
+//! * [Layered] - a hierarchical bit set, where every layer summarizes which
+//!   words in the layer below are non-empty. Draining skips over empty
+//!   regions, which suits sets with many children where only a few are woken
+//!   per cycle.
+//!
+//! Bit sets don't own an allocator. Instead, every operation which allocates
+//! is passed the [Allocator] of the unordered set they belong to, and their
+//! storage is released through [RawBitSet::free].
+//!
+//! # Examples
+//!
+//! ```rust
+//! use unicycle::bitset::Flat;
+//! use unicycle::lock::DefaultLock;
+//! use unicycle::FuturesUnordered;
+//!
+//! # #[tokio::main] async fn main() {
+//! let mut futures = FuturesUnordered::<_, DefaultLock, Flat>::with_bit_set();
+//!
+//! futures.push(async { 42 });
+//! assert_eq!(Some(42), futures.next().await);
+//! # }
+//! ```
+//!
+//! [Unordered]: crate::Unordered
+//! [lock backends]: crate::lock
+
+use crate::allocator::{Allocator, RawVec};
+use crate::sync::{AtomicUsize, Ordering};
+use core::{mem, slice};
+
+/// The bit set backend used by default.
+///
+/// This is [Layered] if the `std` feature is enabled, and [Flat] otherwise or
+/// when building under loom.
+#[cfg(all(feature = "std", not(loom)))]
+pub type DefaultBitSet = Layered;
+
+/// The bit set backend used by default.
+///
+/// This is [Layered] if the `std` feature is enabled, and [Flat] otherwise or
+/// when building under loom.
+#[cfg(any(not(feature = "std"), loom))]
+pub type DefaultBitSet = Flat;
+
+/// A growable bit set which can be used by an [Unordered] set to keep track of
+/// woken children.
+///
+/// Bits are set concurrently through [set_shared][RawBitSet::set_shared] by
+/// wakers on any thread, and might be read concurrently through
+/// [test][RawBitSet::test] and [iter][RawBitSet::iter]. All other operations
+/// are performed by the [Unordered] set once it has exclusive access.
+///
+/// Storage must only be allocated through the allocator passed to
+/// [reserve][RawBitSet::reserve] and [set][RawBitSet::set], which is always
+/// the same one for a given set. It is not released when the set is dropped,
+/// instead the owner calls [free][RawBitSet::free].
+///
+/// [Unordered]: crate::Unordered
+pub trait RawBitSet: 'static + Send + Sync {
+    /// Iterator returned by [drain][RawBitSet::drain].
+    type Drain<'a>: Iterator<Item = usize>
+    where
+        Self: 'a;
+
+    /// Iterator returned by [iter][RawBitSet::iter].
+    type Iter<'a>: Iterator<Item = usize>
+    where
+        Self: 'a;
+
+    /// Construct a new, empty bit set.
+    fn new() -> Self;
+
+    /// Get the number of bits the set can hold without growing.
+    fn capacity(&self) -> usize;
+
+    /// Make sure that the set can hold at least `cap` bits.
+    fn reserve<A>(&mut self, cap: usize, alloc: &A)
+    where
+        A: Allocator;
+
+    /// Shrink the set to hold `cap` bits, clearing every bit past it and
+    /// releasing storage which is no longer needed.
+    ///
+    /// The capacity of the set might remain larger than `cap`.
+    fn truncate<A>(&mut self, cap: usize, alloc: &A)
+    where
+        A: Allocator;
+
+    /// Set the given bit, growing the set if needed.
+    ///
+    /// Returns `true` if the bit was not already set.
+    fn set<A>(&mut self, index: usize, alloc: &A) -> bool
+    where
+        A: Allocator;
+
+    /// Set the given bit through a shared reference, which might happen
+    /// concurrently with other calls to this method.
+    ///
+    /// Returns `true` if the bit was not already set. When racing, exactly one
+    /// of the callers setting the same bit observes this.
+    ///
+    /// Callers make sure that the bit is within the capacity of the set, so
+    /// implementations may panic if it's not.
+    fn set_shared(&self, index: usize) -> bool;
+
+    /// Test if the given bit is set.
+    ///
+    /// This only loads the bit, so it might happen concurrently with
+    /// [set_shared][RawBitSet::set_shared].
+    fn test(&self, index: usize) -> bool;
+
+    /// Clear the given bit, returning `true` if it was set.
+    fn take(&mut self, index: usize) -> bool;
+
+    /// Test if no bits are set.
+    fn is_empty(&mut self) -> bool;
+
+    /// Iterate over the index of every set bit in ascending order, without
+    /// clearing them.
+    ///
+    /// Like [test][RawBitSet::test] this only loads bits, so bits which are
+    /// set concurrently might or might not be yielded.
+    fn iter(&self) -> Self::Iter<'_>;
+
+    /// Drain the set, yielding the index of every set bit in ascending order.
+    ///
+    /// Each bit must be cleared as it is yielded, so if the iterator is
+    /// dropped early the remaining bits are left in the set.
+    fn drain(&mut self) -> Self::Drain<'_>;
+
+    /// Release the storage of the set, leaving it empty with no capacity.
+    ///
+    /// # Safety
+    ///
+    /// `alloc` must be equivalent to the allocator which has been passed to
+    /// every other method of this set.
+    unsafe fn free<A>(&mut self, alloc: &A)
+    where
+        A: Allocator;
+}
+
+const BITS: usize = mem::size_of::<usize>() * 8;
+
+/// Access a word through exclusive access.
+#[cfg(not(loom))]
+fn with_mut<R>(word: &mut AtomicUsize, f: impl FnOnce(&mut usize) -> R) -> R {
+    f(word.get_mut())
+}
+
+/// Access a word through exclusive access.
+#[cfg(loom)]
+fn with_mut<R>(word: &mut AtomicUsize, f: impl FnOnce(&mut usize) -> R) -> R {
+    word.with_mut(f)
+}
+
+/// Grow a layer of words to hold at least `len` words.
+fn extend<A>(words: &mut RawVec<AtomicUsize>, len: usize, alloc: &A)
+where
+    A: Allocator,
+{
+    if words.len() < len {
+        words.extend_to(len, || AtomicUsize::new(0), alloc);
+    }
+}
+
+/// Shrink a layer of words to `len` words.
+fn shrink<A>(words: &mut RawVec<AtomicUsize>, len: usize, alloc: &A)
+where
+    A: Allocator,
+{
+    if words.len() > len {
+        words.truncate(len);
+        words.shrink_to_fit(alloc);
+    }
+}
+
+/// Clear every bit past `cap` in the word which holds it.
+fn clear_tail(words: &mut [AtomicUsize], cap: usize) {
+    let bits = cap % BITS;
+
+    if bits != 0 {
+        if let Some(word) = words.get_mut(cap / BITS) {
+            with_mut(word, |w| *w &= !(!0 << bits));
+        }
+    }
+}
+
+/// A flat bit set, where each bit is stored in a single layer of words.
+///
+/// Its words are built from the atomics picked by the `portable-atomic`
+/// feature.
+pub struct Flat {
+    words: RawVec<AtomicUsize>,
+}
+
+impl RawBitSet for Flat {
+    type Drain<'a> = FlatDrain<'a>;
+    type Iter<'a> = FlatIter<'a>;
+
+    fn new() -> Self {
+        Self {
+            words: RawVec::new(),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.words.len() * BITS
+    }
+
+    fn reserve<A>(&mut self, cap: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        extend(&mut self.words, cap.div_ceil(BITS), alloc);
+    }
+
+    fn truncate<A>(&mut self, cap: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        shrink(&mut self.words, cap.div_ceil(BITS), alloc);
+        clear_tail(&mut self.words, cap);
+    }
+
+    fn set<A>(&mut self, index: usize, alloc: &A) -> bool
+    where
+        A: Allocator,
+    {
+        if index >= self.capacity() {
+            self.reserve(index + 1, alloc);
+        }
+
+        with_mut(&mut self.words[index / BITS], |w| {
+            let mask = 1 << (index % BITS);
+            let unset = *w & mask == 0;
+            *w |= mask;
+            unset
+        })
+    }
+
+    fn set_shared(&self, index: usize) -> bool {
+        let mask = 1 << (index % BITS);
+        // Ordering: We rely on external synchronization when reading the set.
+        self.words[index / BITS].fetch_or(mask, Ordering::Relaxed) & mask == 0
+    }
+
+    fn test(&self, index: usize) -> bool {
+        let Some(word) = self.words.get(index / BITS) else {
+            return false;
+        };
+
+        // Ordering: We rely on external synchronization when reading the set.
+        word.load(Ordering::Relaxed) & (1 << (index % BITS)) != 0
+    }
+
+    fn take(&mut self, index: usize) -> bool {
+        let Some(word) = self.words.get_mut(index / BITS) else {
+            return false;
+        };
+
+        with_mut(word, |w| {
+            let mask = 1 << (index % BITS);
+            let set = *w & mask != 0;
+            *w &= !mask;
+            set
+        })
+    }
+
+    fn is_empty(&mut self) -> bool {
+        self.words.iter_mut().all(|w| with_mut(w, |w| *w == 0))
+    }
+
+    fn iter(&self) -> FlatIter<'_> {
+        FlatIter {
+            words: self.words.iter(),
+            index: 0,
+            word: 0,
+        }
+    }
+
+    fn drain(&mut self) -> FlatDrain<'_> {
+        FlatDrain {
+            words: &mut self.words,
+            index: 0,
+        }
+    }
+
+    unsafe fn free<A>(&mut self, alloc: &A)
+    where
+        A: Allocator,
+    {
+        self.words.free(alloc);
+    }
+}
+
+/// A draining iterator over a [Flat] bit set.
+pub struct FlatDrain<'a> {
+    words: &'a mut [AtomicUsize],
+    index: usize,
+}
+
+impl Iterator for FlatDrain<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(word) = self.words.get_mut(self.index) {
+            let trail = with_mut(word, |word| {
+                if *word == 0 {
+                    return None;
+                }
+
+                let trail = word.trailing_zeros() as usize;
+                *word &= !(1 << trail);
+                Some(trail)
+            });
+
+            match trail {
+                Some(trail) => return Some(self.index * BITS + trail),
+                None => self.index += 1,
+            }
+        }
+
+        None
+    }
+}
+
+/// An iterator over the set bits of a [Flat] bit set.
+pub struct FlatIter<'a> {
+    words: slice::Iter<'a, AtomicUsize>,
+    /// The number of words loaded so far.
+    index: usize,
+    /// The bits of the last loaded word which have not been yielded yet.
+    word: usize,
+}
+
+impl Iterator for FlatIter<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.word == 0 {
+            // Ordering: We rely on external synchronization when reading the
+            // set.
+            self.word = self.words.next()?.load(Ordering::Relaxed);
+            self.index += 1;
+        }
+
+        let trail = self.word.trailing_zeros() as usize;
+        self.word &= !(1 << trail);
+        Some((self.index - 1) * BITS + trail)
+    }
+}
+
+/// A hierarchical bit set.
+///
+/// The bottom layer holds one bit per index, and every layer above it holds
+/// one bit per word in the layer below, which is set if that word is
+/// non-empty. The top layer is a single word. Searching for set bits can
+/// therefore skip over up to `BITS` empty words at a time in each layer.
+pub struct Layered {
+    layers: RawVec<RawVec<AtomicUsize>>,
+}
+
+impl Layered {
+    /// Find the first set bit at or after `index`.
+    fn next_set(&self, index: usize) -> Option<usize> {
+        let mut level = 0;
+        let mut position = index;
+
+        loop {
+            let layer = self.layers.get(level)?;
+
+            // Ordering: We rely on external synchronization when reading the
+            // set.
+            let word = match layer.get(position / BITS) {
+                Some(word) => word.load(Ordering::Relaxed) & (!0 << (position % BITS)),
+                None => 0,
+            };
+
+            if word != 0 {
+                let found = (position / BITS) * BITS + word.trailing_zeros() as usize;
+
+                if level == 0 {
+                    return Some(found);
+                }
+
+                // Continue searching in the non-empty word of the layer below.
+                level -= 1;
+                position = found * BITS;
+                continue;
+            }
+
+            // The rest of the word is empty, so continue searching from the
+            // next word through the layer above.
+            level += 1;
+            position = position / BITS + 1;
+        }
+    }
+}
+
+impl RawBitSet for Layered {
+    type Drain<'a> = LayeredDrain<'a>;
+    type Iter<'a> = LayeredIter<'a>;
+
+    fn new() -> Self {
+        Self {
+            layers: RawVec::new(),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.layers.first().map_or(0, |layer| layer.len() * BITS)
+    }
+
+    fn reserve<A>(&mut self, cap: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        if cap <= self.capacity() {
+            return;
+        }
+
+        let mut words = cap.div_ceil(BITS);
+        let mut level = 0;
+
+        loop {
+            if level == self.layers.len() {
+                self.layers.push(RawVec::new(), alloc);
+                extend(&mut self.layers[level], words, alloc);
+
+                // Summarize the previous top layer in the new one.
+                if let [.., below, layer] = &mut self.layers[..] {
+                    for (n, word) in below.iter_mut().enumerate() {
+                        if with_mut(word, |w| *w != 0) {
+                            with_mut(&mut layer[n / BITS], |w| *w |= 1 << (n % BITS));
+                        }
+                    }
+                }
+            } else {
+                extend(&mut self.layers[level], words, alloc);
+            }
+
+            if words == 1 {
+                break;
+            }
+
+            words = words.div_ceil(BITS);
+            level += 1;
+        }
+    }
+
+    fn truncate<A>(&mut self, cap: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        if cap >= self.capacity() {
+            return;
+        }
+
+        if cap == 0 {
+            // Safety: The caller passes the same allocator to every method.
+            unsafe { self.free(alloc) };
+            return;
+        }
+
+        let mut words = cap.div_ceil(BITS);
+        let mut level = 0;
+
+        shrink(&mut self.layers[0], words, alloc);
+        clear_tail(&mut self.layers[0], cap);
+
+        while words > 1 {
+            let below = words;
+            words = words.div_ceil(BITS);
+            level += 1;
+
+            let [.., below_layer, layer] = &mut self.layers[..=level] else {
+                unreachable!();
+            };
+
+            shrink(layer, words, alloc);
+
+            // Summarize the last word again, since the words it covers might
+            // have been cleared or removed.
+            let last = words - 1;
+            let mut summary = 0;
+
+            for n in last * BITS..below {
+                if with_mut(&mut below_layer[n], |w| *w != 0) {
+                    summary |= 1 << (n % BITS);
+                }
+            }
+
+            with_mut(&mut layer[last], |w| *w = summary);
+        }
+
+        // The layer we stopped at has a single word, so it's the new top.
+        for layer in self.layers[level + 1..].iter_mut() {
+            // Safety: The caller passes the same allocator to every method.
+            unsafe { layer.free(alloc) };
+        }
+
+        self.layers.truncate(level + 1);
+    }
+
+    fn set<A>(&mut self, index: usize, alloc: &A) -> bool
+    where
+        A: Allocator,
+    {
+        if index >= self.capacity() {
+            self.reserve(index + 1, alloc);
+        }
+
+        let mut position = index;
+        let mut unset = false;
+
+        for (level, layer) in self.layers.iter_mut().enumerate() {
+            let mask = 1 << (position % BITS);
+
+            let previous = with_mut(&mut layer[position / BITS], |w| {
+                let previous = *w;
+                *w |= mask;
+                previous
+            });
+
+            if level == 0 {
+                unset = previous & mask == 0;
+            }
+
+            // The layers above already know about a non-empty word.
+            if previous != 0 {
+                break;
+            }
+
+            position /= BITS;
+        }
+
+        unset
+    }
+
+    fn set_shared(&self, index: usize) -> bool {
+        let mut position = index;
+        let mut unset = false;
+
+        for (level, layer) in self.layers.iter().enumerate() {
+            let mask = 1 << (position % BITS);
+
+            // Ordering: We rely on external synchronization when reading the
+            // set. If the word was non-empty, whoever set the first bit in it
+            // takes care of the layers above before the set is read.
+            let previous = layer[position / BITS].fetch_or(mask, Ordering::Relaxed);
+
+            if level == 0 {
+                unset = previous & mask == 0;
+            }
+
+            if previous != 0 {
+                break;
+            }
+
+            position /= BITS;
+        }
+
+        unset
+    }
+
+    fn test(&self, index: usize) -> bool {
+        let Some(word) = self
+            .layers
+            .first()
+            .and_then(|layer| layer.get(index / BITS))
+        else {
+            return false;
+        };
+
+        // Ordering: We rely on external synchronization when reading the set.
+        word.load(Ordering::Relaxed) & (1 << (index % BITS)) != 0
+    }
+
+    fn take(&mut self, index: usize) -> bool {
+        if !self.test(index) {
+            return false;
+        }
+
+        // Only clear the summary of a word once it is empty.
+        let mut position = index;
+
+        for layer in self.layers.iter_mut() {
+            let empty = with_mut(&mut layer[position / BITS], |w| {
+                *w &= !(1 << (position % BITS));
+                *w == 0
+            });
+
+            if !empty {
+                break;
+            }
+
+            position /= BITS;
+        }
+
+        true
+    }
+
+    fn is_empty(&mut self) -> bool {
+        match self.layers.last_mut() {
+            Some(top) => with_mut(&mut top[0], |w| *w == 0),
+            None => true,
+        }
+    }
+
+    fn iter(&self) -> LayeredIter<'_> {
+        LayeredIter {
+            set: self,
+            index: 0,
+        }
+    }
+
+    fn drain(&mut self) -> LayeredDrain<'_> {
+        LayeredDrain {
+            set: self,
+            index: 0,
+        }
+    }
+
+    unsafe fn free<A>(&mut self, alloc: &A)
+    where
+        A: Allocator,
+    {
+        for layer in self.layers.iter_mut() {
+            layer.free(alloc);
+        }
+
+        self.layers.free(alloc);
+    }
+}
+
+/// A draining iterator over a [Layered] bit set.
+pub struct LayeredDrain<'a> {
+    set: &'a mut Layered,
+    index: usize,
+}
+
+impl Iterator for LayeredDrain<'_> {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.set.next_set(self.index)?;
+        self.set.take(index);
+        self.index = index + 1;
+        Some(index)
+    }
+}
+
+/// An iterator over the set bits of a [Layered] bit set.
+pub struct LayeredIter<'a> {
+    set: &'a Layered,
+    index: usize,
+}
+
+impl Iterator for LayeredIter<'_> {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.set.next_set(self.index)?;
+        self.index = index + 1;
+        Some(index)
+    }
+}
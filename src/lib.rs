@@ -17,16 +17,41 @@
 //! But we aim to provide a stronger guarantee of fairness (see below), and
 //! better memory locality for the futures being pollled.
 //!
+//! Collections which are driven on a single thread can use [LocalUnordered]
+//! instead, which records wakeups from its own thread without atomics or locks.
+//!
 //! **Note:** This project is experimental. It involves some amount of unsafe and
 //! possibly bad assumptions which needs to be either vetted or removed before you
 //! should consider putting it in production.
 //!
 //! ## Features
 //!
-//! * `parking-lot` - To enable locking using the [parking_lot] crate (default).
+//! * `std` - Enable support for the standard library (default). Without it
+//!   unicycle only depends on `alloc`, and can be used in `no_std` environments
+//!   such as embedded executors. All other features except `futures-rs` imply
+//!   `std`.
+//! * `parking-lot` - To enable locking using the [parking_lot] crate by default
+//!   (default). See the `lock` module for how to pick a lock backend per set.
+//! * `lock-api` - Allow any raw lock from the [lock_api] crate to be used as a
+//!   lock backend.
 //! * `futures-rs` - Enable the used of the Stream type from [futures-rs].
 //!   This is required to get access to [StreamsUnordered] and
 //!   [IndexedStreamsUnordered] since these wrap over [futures-rs] types. (default)
+//! * `portable-atomic` - Use [portable-atomic] for all atomics and reference
+//!   counting, to support targets without native atomic compare-and-swap such
+//!   as `thumbv6m-none-eabi`.
+//! * `critical-section` - Enables `portable-atomic` and its [critical-section]
+//!   based fallback for targets without native atomic compare-and-swap.
+//! * `stats` - Collect per-child and aggregate scheduling statistics, available
+//!   through `Unordered::stats` and `Unordered::child_stats`.
+//! * `tracing` - Poll each child inside of a [tracing] span carrying its index,
+//!   and emit an event at the end of each polling cycle.
+//! * `testing` - Enable the `testing` module, with manually controlled children
+//!   and a harness which steps an unordered set one poll at a time while
+//!   recording which children were polled.
+//! * `allocator-api2` - Allow a custom [allocator-api2] allocator to be used for
+//!   the storage of children and wake sets, through `PinSlab::new_in` and the
+//!   `new_in` and `with_lock_in` constructors of each unordered set.
 //!
 //! ## Examples
 //!
@@ -131,16 +156,26 @@
 //! task is then polled _once_ in order. If the task is [Ready], its result is
 //! yielded. After we receive control again, we continue draining the alternate
 //! set in this manner, until it is empty. When this is done we yield once, then
-//! we start the cycle over again.
+//! we start the cycle over again. Which kind of bit set is used can be picked
+//! through the [bitset] module.
+//!
+//! Tasks are drained in index order by default. With [WakeOrder::Fifo] they are
+//! instead drained in the order in which they were woken, which is recorded in
+//! a bounded queue next to each bit set. The bit set still makes sure that each
+//! task is polled at most once per cycle.
 //!
-//! [BitSet]: https://docs.rs/uniset/latest/uniset/struct.BitSet.html
+//! [allocator-api2]: https://docs.rs/allocator-api2
+//! [BitSet]: crate::bitset::RawBitSet
+//! [critical-section]: https://docs.rs/critical-section
 //! [futures crate]: https://docs.rs/futures/latest/futures
 //! [futures-rs]: https://crates.io/crates/futures
 //! [futures-rs]: https://docs.rs/futures/latest/futures/stream/struct.FuturesUnordered.html
 //! [FuturesUnordered]: https://docs.rs/unicycle/latest/unicycle/type.FuturesUnordered.html
 //! [IndexedStreamsUnordered]: https://docs.rs/unicycle/latest/unicycle/type.IndexedStreamsUnordered.html
 //! [limiting the amount FuturesUnordered is allowed to spin]: https://github.com/rust-lang/futures-rs/pull/2049
+//! [lock_api]: https://docs.rs/lock_api
 //! [parking_lot]: https://crates.io/crates/parking_lot
+//! [portable-atomic]: https://docs.rs/portable-atomic
 //! [pin API]: https://doc.rust-lang.org/std/pin/index.html
 //! [Ready]: https://doc.rust-lang.org/std/task/enum.Poll.html
 //! [reported by Jon Gjengset]: https://github.com/rust-lang/futures-rs/issues/2047
@@ -148,28 +183,59 @@
 //! [slab]: https://github.com/carllerche/slab
 //! [spin abnormally]: https://github.com/udoprog/unicycle/blob/master/tests/spinning_futures_unordered.rs
 //! [StreamsUnordered]: https://docs.rs/unicycle/latest/unicycle/type.StreamsUnordered.html
+//! [tracing]: https://docs.rs/tracing
 
 #![deny(missing_docs)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 #![deny(rustdoc::broken_intra_doc_links)]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+extern crate alloc;
+
+use self::allocator::{Allocator, Global, RawVec};
+use self::bitset::{DefaultBitSet, RawBitSet};
+use self::lock::{DefaultLock, RawLock};
 use self::pin_slab::PinSlab;
-use self::wake_set::{SharedWakeSet, WakeSet};
-use self::waker::SharedWaker;
-#[cfg(feature = "futures-rs")]
-use futures_core::{FusedStream, Stream};
-use std::{
+use self::spin::{SelfWake, Spin};
+use self::stats::{Stats, WakeEntry, WakeStats};
+use self::sync::{Arc, AtomicBool};
+use self::trace::Trace;
+use self::wake_set::{SharedWakeSet, WakeSet, Woken};
+use self::waker::{SharedWaker, WakeIndex, WakerCells};
+use alloc::vec::Vec;
+use core::{
     future::Future,
     iter, marker, mem,
-    pin::Pin,
+    pin::{pin, Pin},
     ptr,
-    sync::Arc,
     task::{Context, Poll},
 };
-use uniset::BitSet;
+#[cfg(feature = "futures-rs")]
+use futures_core::{FusedStream, Stream};
 
-mod lock;
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub use self::local::{LocalFuturesUnordered, LocalUnordered};
+#[cfg(all(feature = "std", feature = "futures-rs"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "std", feature = "futures-rs"))))]
+pub use self::local::{LocalIndexedStreamsUnordered, LocalStreamsUnordered};
+#[cfg(feature = "stats")]
+#[cfg_attr(docsrs, doc(cfg(feature = "stats")))]
+pub use self::stats::{ChildStats, SetStats};
+
+pub mod allocator;
+pub mod bitset;
+#[cfg(feature = "std")]
+mod local;
+pub mod lock;
 pub mod pin_slab;
+mod spin;
+mod stats;
+mod sync;
+#[cfg(feature = "testing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub mod testing;
+mod trace;
 mod wake_set;
 mod waker;
 
@@ -206,25 +272,86 @@ macro_rules! ready {
 ///     println!("done!");
 /// }
 /// ```
-pub type FuturesUnordered<T> = Unordered<T, Futures>;
+pub type FuturesUnordered<T, L = DefaultLock, B = DefaultBitSet, A = Global> =
+    Unordered<T, Futures, L, B, A>;
 
 /// Data that is shared across all sub-tasks.
-struct Shared {
+struct Shared<L, B, A>
+where
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
+{
     /// The currently registered parent waker.
-    waker: SharedWaker,
+    waker: SharedWaker<L>,
     /// The currently registered wake set.
-    wake_set: SharedWakeSet,
+    wake_set: SharedWakeSet<L, B>,
+    /// Wake statistics for child tasks.
+    wake_stats: WakeStats,
+    /// Detection of child tasks which wake themselves while being polled.
+    self_wake: SelfWake,
+    /// Per-index waker cells, used by cloned wakers.
+    cells: WakerCells<Self>,
+    /// The allocator used for wake sets and waker cells.
+    alloc: A,
 }
 
-impl Shared {
+impl<L, B, A> Shared<L, B, A>
+where
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
+{
     /// Construct new shared data.
-    fn new() -> Self {
+    fn new(alloc: A) -> Self {
         Self {
             waker: SharedWaker::new(),
-            wake_set: SharedWakeSet::new(),
+            wake_set: SharedWakeSet::new(&alloc),
+            wake_stats: WakeStats::new(),
+            self_wake: SelfWake::new(),
+            cells: WakerCells::new(),
+            alloc,
+        }
+    }
+
+    /// Wake the child task at the given index, given the pending flag of its
+    /// waker cell, and notify the parent waker.
+    fn wake(&self, index: usize, pending: &AtomicBool) {
+        self.self_wake.wake(index);
+        self.wake_set.wake(index, pending);
+        self.waker.wake_by_ref();
+    }
+
+    /// Move wakeups which couldn't be registered in the active wake set into
+    /// the given set.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the unordered set which owns the shared data,
+    /// and not while it's reserving or compacting waker cells.
+    unsafe fn take_pending(&self, set: &mut Woken<B>) {
+        if self.wake_set.take_pending() {
+            self.cells.take_pending(|index| set.set(index, &self.alloc));
         }
     }
 
+    /// Poll with a waker that references the cell for the given index, while
+    /// keeping track of whether the child wakes itself.
+    ///
+    /// # Safety
+    ///
+    /// The cell for the index must have been reserved, and cells must not be
+    /// reserved concurrently.
+    unsafe fn poll_with_ref<F, R>(&self, index: usize, f: F) -> R
+    where
+        F: FnOnce(&mut Context<'_>) -> R,
+    {
+        self.self_wake.enter(index);
+        let result = self.cells.poll_with_ref(index, f);
+        self.self_wake.leave();
+        result
+    }
+
     /// Swap the active wake set with the alternate one.
     /// Also makes sure that the capacity of the active bitset is updated if the
     /// alternate one has.
@@ -236,15 +363,18 @@ impl Shared {
     unsafe fn poll_swap_active<'a>(
         &self,
         cx: &Context<'_>,
-        alternate: &mut *mut WakeSet,
-    ) -> Poll<(bool, &'a mut BitSet)> {
+        alternate: &mut *mut WakeSet<L, B>,
+    ) -> Poll<(bool, &'a mut Woken<B>)> {
         let non_empty = {
-            let alternate = (**alternate).as_mut_set();
+            let alternate = WakeSet::as_mut_set(*alternate);
             let non_empty = !alternate.is_empty();
 
             // We always force a swap if the capacity has changed, because then
             // we expect subtasks to poll the swapped in set since they were
             // newly added.
+            //
+            // NB: Pending wakeups are left for the next swap, since children
+            // in this set might already have been polled in this cycle.
             if non_empty {
                 return Poll::Ready((true, alternate));
             }
@@ -258,9 +388,31 @@ impl Shared {
         };
 
         let wake_set = self.swap_active(alternate);
+        self.take_pending(wake_set);
         Poll::Ready((non_empty, wake_set))
     }
 
+    /// Poll an unordered collection which is empty.
+    ///
+    /// If `wait` is set, the parent waker is registered so that it can be
+    /// woken once more work is added and we return `Poll::Pending`. Otherwise
+    /// the collection has terminated.
+    ///
+    /// # Safety
+    ///
+    /// Caller must be assured that they are the only one who is attempting to
+    /// swap out the parent waker.
+    unsafe fn poll_empty<T>(&self, cx: &Context<'_>, wait: bool) -> Poll<Option<T>> {
+        if !wait {
+            return Poll::Ready(None);
+        }
+
+        // Note: if the swap fails the waker has already been woken, so we will
+        // be polled again either way.
+        self.waker.swap(cx.waker());
+        Poll::Pending
+    }
+
     /// Perform the actual swap of the active sets.
     /// This is safe, because we perform the appropriate locking while swapping
     /// the sets.
@@ -269,7 +421,7 @@ impl Shared {
     ///
     /// We must ensure that we have unique access to the alternate set being
     /// swapped.
-    unsafe fn swap_active<'a>(&self, alternate: &mut *mut WakeSet) -> &'a mut BitSet {
+    unsafe fn swap_active<'a>(&self, alternate: &mut *mut WakeSet<L, B>) -> &'a mut Woken<B> {
         // Unlock. At this position, if someone adds an element to the wake set
         // they are also bound to call wake, which will cause us to wake up.
         //
@@ -299,7 +451,35 @@ impl Shared {
 
         // Safety: While this is live we must _not_ mess with
         // `alternate` in any way.
-        (**alternate).as_mut_set()
+        WakeSet::as_mut_set(*alternate)
+    }
+}
+
+impl<L, B, A> WakeIndex for Shared<L, B, A>
+where
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
+{
+    fn wake_index(&self, index: usize, stats: &WakeEntry, pending: &AtomicBool) {
+        self.wake_stats.wake(stats);
+        self.wake(index, pending);
+    }
+}
+
+impl<L, B, A> Drop for Shared<L, B, A>
+where
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
+{
+    fn drop(&mut self) {
+        // Safety: We hold the last reference to the shared data, so there are
+        // no wakers left which might access the wake set or the waker cells.
+        unsafe {
+            self.wake_set.free(&self.alloc);
+            self.cells.free(&self.alloc);
+        }
     }
 }
 
@@ -325,6 +505,29 @@ pub struct Futures(());
 
 impl Sentinel for Futures {}
 
+/// The order in which woken children of an [Unordered] set are polled, see
+/// [Unordered::set_wake_order].
+///
+/// Regardless of the order, each child is polled at most once per polling
+/// cycle.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum WakeOrder {
+    /// Poll woken children in ascending index order. This is the default.
+    #[default]
+    Index,
+    /// Poll woken children in the order in which they were woken.
+    ///
+    /// Up to `capacity` children are recorded per polling cycle, in the order
+    /// of their first wakeup. Repeated wakeups of a child which hasn't been
+    /// polled yet don't take up more room. Children which didn't fit are
+    /// polled in index order after the recorded ones.
+    Fifo {
+        /// The number of children recorded per polling cycle.
+        capacity: usize,
+    },
+}
+
 /// A container for an unordered collection of [Future]s or [Stream]s.
 ///
 /// You should use one of the following type aliases to construct it:
@@ -332,6 +535,16 @@ impl Sentinel for Futures {}
 /// * [StreamsUnordered]
 /// * [IndexedStreamsUnordered]
 ///
+/// The lock `L` and the bit set `B` used to record wakeups can be picked
+/// through the `with_lock` and `with_bit_set` constructors, see the [lock] and
+/// [bitset] modules.
+///
+/// With the `allocator-api2` feature, the storage of child tasks and wake sets
+/// can be allocated through a custom allocator `A` by using the `new_in` and
+/// `with_lock_in` constructors. Wakers keep the allocator alive and may use it
+/// from any thread, so it must be `Send + Sync + 'static`. See the
+/// [allocator] module for which allocations this covers.
+///
 /// # Examples
 ///
 /// ```rust,no_run
@@ -353,48 +566,81 @@ impl Sentinel for Futures {}
 ///     println!("done!");
 /// }
 /// ```
-pub struct Unordered<T, S>
+pub struct Unordered<T, S, L = DefaultLock, B = DefaultBitSet, A = Global>
 where
     S: Sentinel,
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
 {
     /// Slab of futures being polled.
     /// They need to be pinned on the heap, since the slab might grow to
     /// accomodate more futures.
-    slab: PinSlab<T>,
+    slab: PinSlab<T, A>,
     /// Shared parent waker.
     /// Includes the current wake target. Each time we poll, we swap back and
     /// forth between this and `alternate`.
-    shared: Arc<Shared>,
+    shared: Arc<Shared<L, B, A>>,
     /// Alternate wake set, used for growing the existing set when futures are
     /// added. This is then swapped out with the active set to receive polls.
-    alternate: *mut WakeSet,
+    alternate: *mut WakeSet<L, B>,
+    /// Set once [Unordered::close] has been called. A closed set refuses new
+    /// tasks and terminates once it has been drained.
+    closed: bool,
+    /// When set, an empty collection waits for more work instead of
+    /// terminating. See [Unordered::set_persistent].
+    persistent: bool,
+    /// Scheduling statistics, only collected with the `stats` feature.
+    stats: Stats,
+    /// Per-child spans, only collected with the `tracing` feature.
+    trace: Trace,
+    /// Tracking of child tasks which continuously wake themselves.
+    spin: Spin,
+    /// The order in which woken tasks are polled.
+    wake_order: WakeOrder,
     /// Marker for the sentinel.
     _marker: marker::PhantomData<S>,
 }
 
 // Safety: Unordered is ultimately a container of `T`, and is `Send` only if `T`
 // themselves are `Send`.
-unsafe impl<T, S> Send for Unordered<T, S>
+unsafe impl<T, S, L, B, A> Send for Unordered<T, S, L, B, A>
 where
     T: Send,
     S: Sentinel,
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator + Send + Sync,
 {
 }
 
 // Safety: Unordered is ultimately a container of `T`, and is `Sync` only if `T`
 // themselves are `Sync`.
-unsafe impl<T, S> Sync for Unordered<T, S>
+unsafe impl<T, S, L, B, A> Sync for Unordered<T, S, L, B, A>
 where
     T: Sync,
     S: Sentinel,
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator + Send + Sync,
 {
 }
 
-impl<T, S> Unpin for Unordered<T, S> where S: Sentinel {}
+impl<T, S, L, B, A> Unpin for Unordered<T, S, L, B, A>
+where
+    S: Sentinel,
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
+{
+}
 
-impl<T, S> Unordered<T, S>
+impl<T, S, L, B, A> Unordered<T, S, L, B, A>
 where
     S: Sentinel,
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
     Self: PollNext,
 {
     /// Creates a future that resolves to the next item in the unordered set.
@@ -443,6 +689,107 @@ where
             }
         }
     }
+
+    /// [Close][Unordered::close] the collection and drive the remaining tasks
+    /// until they have all completed, or until `deadline` resolves.
+    ///
+    /// Any tasks which are still running once the deadline resolves are
+    /// dropped, and their indexes are returned in ascending order. Items
+    /// produced while draining are discarded, if you need them use
+    /// [close][Unordered::close] and drive the collection yourself.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use tokio::time;
+    /// use std::time::Duration;
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut futures = FuturesUnordered::new();
+    ///
+    ///     futures.push(time::sleep(Duration::from_millis(10)));
+    ///     let slow = futures.push(time::sleep(Duration::from_secs(60)));
+    ///
+    ///     let dropped = futures.shutdown(time::sleep(Duration::from_millis(100))).await;
+    ///     assert_eq!(vec![slow], dropped);
+    ///     assert!(futures.is_empty());
+    /// }
+    /// ```
+    pub async fn shutdown<D>(&mut self, deadline: D) -> Vec<usize>
+    where
+        D: Future,
+    {
+        self.close();
+
+        let deadline = pin!(deadline);
+
+        let elapsed = Drain {
+            unordered: &mut *self,
+            deadline,
+        }
+        .await;
+
+        if !elapsed {
+            return Vec::new();
+        }
+
+        let dropped: Vec<usize> = self.slab.keys().collect();
+        self.slab.clear();
+        self.trace.clear();
+        self.stats.clear();
+        self.spin.clear();
+
+        for &index in &dropped {
+            // Safety: Cells are reserved for every child, and we have
+            // exclusive access to the unordered set.
+            let entry = unsafe { self.shared.cells.stats(index) };
+            self.shared.wake_stats.reset(entry);
+        }
+
+        // Safety: We have exclusive access to the alternate set, and we own the
+        // shared wake set.
+        unsafe {
+            // NB: Pending wakeups are taken so that they're discarded along
+            // with the rest.
+            let alternate = WakeSet::as_mut_set(self.alternate);
+            self.shared.take_pending(alternate);
+            alternate.clear();
+            self.shared.wake_set.clear();
+        }
+
+        return dropped;
+
+        struct Drain<'a, T, D> {
+            unordered: &'a mut T,
+            deadline: Pin<&'a mut D>,
+        }
+
+        impl<T, D> Future for Drain<'_, T, D>
+        where
+            T: Unpin + PollNext,
+            D: Future,
+        {
+            type Output = bool;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                if self.deadline.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(true);
+                }
+
+                match ready!(Pin::new(&mut *self.unordered).poll_next(cx)) {
+                    Some(..) => {
+                        // Yield between items so that we get to check the
+                        // deadline again.
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                    None => Poll::Ready(false),
+                }
+            }
+        }
+    }
 }
 
 impl<T> FuturesUnordered<T> {
@@ -459,7 +806,102 @@ impl<T> FuturesUnordered<T> {
     /// futures.push(async { 42 });
     /// ```
     pub fn new() -> Self {
-        Self::new_internal()
+        Self::new_internal(Global)
+    }
+}
+
+impl<T, L> FuturesUnordered<T, L>
+where
+    L: RawLock,
+{
+    /// Construct a new, empty [FuturesUnordered] using a custom lock backend.
+    ///
+    /// See the [lock] module for the available backends.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::FuturesUnordered;
+    /// use unicycle::lock::AtomicLock;
+    ///
+    /// let mut futures = FuturesUnordered::<_, AtomicLock>::with_lock();
+    /// assert!(futures.is_empty());
+    ///
+    /// futures.push(async { 42 });
+    /// ```
+    pub fn with_lock() -> Self {
+        Self::new_internal(Global)
+    }
+}
+
+impl<T, L, B> FuturesUnordered<T, L, B>
+where
+    L: RawLock,
+    B: RawBitSet,
+{
+    /// Construct a new, empty [FuturesUnordered] using custom lock and bit set
+    /// backends.
+    ///
+    /// See the [bitset] module for the available bit set backends.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::bitset::Flat;
+    /// use unicycle::lock::DefaultLock;
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::<_, DefaultLock, Flat>::with_bit_set();
+    /// assert!(futures.is_empty());
+    ///
+    /// futures.push(async { 42 });
+    /// ```
+    pub fn with_bit_set() -> Self {
+        Self::new_internal(Global)
+    }
+}
+
+#[cfg(feature = "allocator-api2")]
+#[cfg_attr(docsrs, doc(cfg(feature = "allocator-api2")))]
+impl<T, A> FuturesUnordered<T, DefaultLock, DefaultBitSet, A>
+where
+    A: Allocator + Clone + Send + Sync + 'static,
+{
+    /// Construct a new, empty [FuturesUnordered] which allocates its storage
+    /// through the given allocator.
+    ///
+    /// See [Unordered] for which allocations are affected.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use allocator_api2::alloc::Global;
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut set = FuturesUnordered::new_in(Global);
+    /// assert!(set.is_empty());
+    ///
+    /// set.push(async { 42 });
+    /// ```
+    pub fn new_in(alloc: A) -> Self {
+        Self::new_internal(alloc)
+    }
+}
+
+#[cfg(feature = "allocator-api2")]
+#[cfg_attr(docsrs, doc(cfg(feature = "allocator-api2")))]
+impl<T, L, B, A> FuturesUnordered<T, L, B, A>
+where
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator + Clone + Send + Sync + 'static,
+{
+    /// Construct a new, empty [FuturesUnordered] using custom lock and bit set
+    /// backends, which allocates its storage through the given allocator.
+    ///
+    /// See the [lock] and [bitset] modules for the available backends.
+    pub fn with_lock_in(alloc: A) -> Self {
+        Self::new_internal(alloc)
     }
 }
 
@@ -481,9 +923,12 @@ pub trait PollNext {
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>>;
 }
 
-impl<T> PollNext for FuturesUnordered<T>
+impl<T, L, B, A> PollNext for FuturesUnordered<T, L, B, A>
 where
     T: Future,
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
 {
     type Item = T::Output;
 
@@ -493,19 +938,33 @@ where
             ref mut slab,
             ref shared,
             ref mut alternate,
+            ref mut stats,
+            ref mut trace,
+            ref mut spin,
+            closed,
+            persistent,
             ..
         } = *self.as_mut();
 
         if slab.is_empty() {
-            // Nothing to poll, nothing to add. End the stream since we don't have work to do.
-            return Poll::Ready(None);
+            // Nothing to poll, nothing to add. End the stream since we don't
+            // have work to do, unless we're persistent in which case we wait
+            // for more work.
+            // Safety: We have exclusive access to Unordered.
+            return unsafe { shared.poll_empty(cx, persistent && !closed) };
         }
 
         // Safety: We have exclusive access to Unordered, which is the only
         // implementation that is trying to swap the wake sets.
         let (non_empty, wake_last) = ready!(unsafe { shared.poll_swap_active(cx, alternate) });
 
+        if !non_empty {
+            stats.swap();
+        }
+
         for index in wake_last.drain() {
+            trace.drain();
+
             // NB: Since we defer pollables a little, a future might
             // have been polled and subsequently removed from the slab.
             // So we don't treat this as an error here.
@@ -514,23 +973,40 @@ where
             // future much.
             let fut = match slab.get_pin_mut(index) {
                 Some(fut) => fut,
-                None => continue,
+                None => {
+                    stats.spurious();
+                    continue;
+                }
             };
 
             // Construct a new lightweight waker only capable of waking by
             // reference, with referential access to `shared`.
-            let result = self::waker::poll_with_ref(shared, index, move |cx| fut.poll(cx));
+            let result = stats.poll(index, || {
+                trace.poll(index, || {
+                    // Safety: The cell for the index was reserved when the task
+                    // was pushed, and cells can't be reserved while we are
+                    // polling.
+                    unsafe { shared.poll_with_ref(index, move |cx| fut.poll(cx)) }
+                })
+            });
+
+            spin.record(index, shared.self_wake.woken(), &shared.alloc);
 
             if let Poll::Ready(result) = result {
                 let removed = slab.remove(index);
                 debug_assert!(removed);
+                trace.remove(index);
                 cx.waker().wake_by_ref();
                 return Poll::Ready(Some(result));
             }
         }
 
+        stats.end_cycle();
+        trace.end_cycle();
+
         if slab.is_empty() {
-            return Poll::Ready(None);
+            // Safety: We have exclusive access to Unordered.
+            return unsafe { shared.poll_empty(cx, persistent && !closed) };
         }
 
         // We need to wake again to take care of the alternate set that was
@@ -543,16 +1019,31 @@ where
     }
 }
 
-impl<T, S> Unordered<T, S>
+impl<T, S, L, B, A> Unordered<T, S, L, B, A>
 where
     S: Sentinel,
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
 {
     #[inline(always)]
-    fn new_internal() -> Self {
+    fn new_internal(alloc: A) -> Self
+    where
+        A: Clone + Send + Sync + 'static,
+    {
+        let shared = Arc::new(Shared::new(alloc.clone()));
+        let alternate = WakeSet::locked().into_raw(&shared.alloc);
+
         Self {
-            slab: PinSlab::new(),
-            shared: Arc::new(Shared::new()),
-            alternate: Box::into_raw(Box::new(WakeSet::locked())),
+            slab: PinSlab::with_growth_and_alloc(pin_slab::Growth::default(), alloc),
+            shared,
+            alternate,
+            closed: false,
+            persistent: false,
+            stats: Stats::new(),
+            trace: Trace::new(),
+            spin: Spin::new(),
+            wake_order: WakeOrder::Index,
             _marker: marker::PhantomData,
         }
     }
@@ -590,14 +1081,107 @@ where
     /// futures.push(async { 42 });
     /// assert!(!futures.is_empty());
     /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the collection has been [closed][Unordered::close]. Use
+    /// [try_push][Unordered::try_push] to handle this gracefully.
     pub fn push(&mut self, future: T) -> usize {
+        match self.try_push(future) {
+            Ok(index) => index,
+            Err(..) => panic!("cannot push to a closed unordered set"),
+        }
+    }
+
+    /// Try to push the given future or stream to [Unordered] and return its
+    /// task index.
+    ///
+    /// This behaves like [push][Unordered::push], except that if the collection
+    /// has been [closed][Unordered::close] the value is handed back as an
+    /// error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::ready;
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::new();
+    /// assert!(futures.try_push(ready(42)).is_ok());
+    ///
+    /// futures.close();
+    /// assert!(futures.try_push(ready(43)).is_err());
+    /// ```
+    pub fn try_push(&mut self, future: T) -> Result<usize, T> {
+        self.try_push_with_span(future, self::trace::current())
+    }
+
+    /// Push the given future or stream to [Unordered] and return its task
+    /// index, polling it inside of the given [tracing span].
+    ///
+    /// Every poll of the task is wrapped in a span carrying its index, which is
+    /// parented to `span`. When using [push][Unordered::push] the span which is
+    /// current when pushing is used instead.
+    ///
+    /// [tracing span]: https://docs.rs/tracing/latest/tracing/struct.Span.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::new();
+    /// let span = tracing::info_span!("request", id = 42);
+    /// futures.push_with_span(async { 42 }, span);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the collection has been [closed][Unordered::close].
+    #[cfg(feature = "tracing")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "tracing")))]
+    pub fn push_with_span(&mut self, future: T, span: tracing::Span) -> usize {
+        match self.try_push_with_span(future, span) {
+            Ok(index) => index,
+            Err(..) => panic!("cannot push to a closed unordered set"),
+        }
+    }
+
+    fn try_push_with_span(&mut self, future: T, span: self::trace::Span) -> Result<usize, T> {
+        if self.closed {
+            return Err(future);
+        }
+
         let index = self.slab.insert(future);
 
+        // Safety: We have exclusive access to the unordered set, which owns
+        // the shared data.
+        unsafe {
+            self.shared
+                .cells
+                .reserve(Arc::as_ptr(&self.shared), index, &self.shared.alloc);
+        }
+
+        self.trace.insert(index, span, &self.shared.alloc);
+        self.stats.insert(index, &self.shared.alloc);
+        self.spin.insert(index);
+
+        // Safety: The cell was just reserved, and we have exclusive access to
+        // the unordered set.
+        let entry = unsafe { self.shared.cells.stats(index) };
+        self.shared.wake_stats.reset(entry);
+
+        // A persistent collection might be parked waiting for more work, so we
+        // need to wake it up.
+        if self.persistent {
+            self.shared.waker.wake_by_ref();
+        }
+
         let (old, new) = {
             // Safety: At this point we know we have exclusive access to the set.
-            let set = unsafe { (*self.alternate).as_mut_set() };
+            let set = unsafe { WakeSet::as_mut_set(self.alternate) };
             let old = set.capacity();
-            set.set(index);
+            set.set(index, &self.shared.alloc);
             let new = set.capacity();
             (old, new)
         };
@@ -605,7 +1189,7 @@ where
         // Fast Path: Did not grow the alternate set, so no need to grow the
         // active set either.
         if new <= old {
-            return index;
+            return Ok(index);
         }
 
         // Slow Path: Swap out the active set and grow it to accomodate the same
@@ -616,77 +1200,689 @@ where
         // futures.
         // Safety: We have unique access to the alternate set being modified.
         unsafe {
-            self.shared.swap_active(&mut self.alternate).reserve(new);
+            self.shared
+                .swap_active(&mut self.alternate)
+                .reserve(new, &self.shared.alloc);
         }
 
-        index
+        self.stats.swap();
+
+        Ok(index)
     }
 
-    /// Get a pinned mutable reference to the stream or future at the given
-    /// index.
+    /// Close the collection.
+    ///
+    /// A closed collection refuses new tasks, but keeps driving the ones it
+    /// already has until they complete. Once a closed collection is empty it
+    /// is considered terminated.
     ///
     /// # Examples
     ///
     /// ```rust
     /// use unicycle::FuturesUnordered;
-    /// use futures::future::poll_fn;
-    /// use std::future::Future as _;
     ///
     /// #[tokio::main]
     /// async fn main() {
     ///     let mut futures = FuturesUnordered::new();
-    ///     let index = futures.push(async { 42 });
-    ///
-    ///     let result = poll_fn(|cx| {
-    ///         futures.get_pin_mut(index).expect("expected future").poll(cx)
-    ///     }).await;
+    ///     futures.push(async { 42 });
+    ///     futures.close();
     ///
-    ///     assert_eq!(result, 42);
+    ///     assert!(futures.is_closed());
+    ///     assert_eq!(Some(42), futures.next().await);
+    ///     assert_eq!(None, futures.next().await);
     /// }
     /// ```
-    pub fn get_pin_mut(&mut self, index: usize) -> Option<Pin<&mut T>> {
-        self.slab.get_pin_mut(index)
+    pub fn close(&mut self) {
+        self.closed = true;
+
+        // Wake up a persistent collection which is waiting for more work, so
+        // that it gets the chance to terminate.
+        if self.persistent {
+            self.shared.waker.wake_by_ref();
+        }
     }
 
-    /// Get a mutable reference to the stream or future at the given index.
-    /// Requires that the stores stream or future is [Unpin].
+    /// Configure whether the collection is persistent or not.
+    ///
+    /// By default an empty collection terminates, which means that
+    /// [next][Unordered::next] returns `None` as soon as there are no more
+    /// tasks. A persistent collection instead waits until more tasks are
+    /// pushed to it, which allows a single loop to drive it indefinitely.
+    ///
+    /// A persistent collection still terminates once it has been
+    /// [closed][Unordered::close] and is empty.
     ///
     /// # Examples
     ///
     /// ```rust
+    /// use std::future::ready;
     /// use unicycle::FuturesUnordered;
-    /// use futures::future::{ready, poll_fn};
-    /// use std::{pin::Pin, future::Future as _};
     ///
     /// #[tokio::main]
     /// async fn main() {
     ///     let mut futures = FuturesUnordered::new();
-    ///     let index = futures.push(ready(42));
+    ///     futures.set_persistent(true);
     ///
-    ///     let result = poll_fn(|cx| {
-    ///         Pin::new(futures.get_mut(index).expect("expected future")).poll(cx)
-    ///     }).await;
+    ///     // An empty persistent collection waits for more work.
+    ///     assert!(futures::poll!(Box::pin(futures.next())).is_pending());
     ///
-    ///     assert_eq!(result, 42);
+    ///     futures.push(ready(42));
+    ///     assert_eq!(Some(42), futures.next().await);
+    ///     assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    ///
+    ///     futures.close();
+    ///     assert_eq!(None, futures.next().await);
     /// }
     /// ```
-    pub fn get_mut(&mut self, index: usize) -> Option<&mut T>
-    where
-        T: Unpin,
-    {
-        self.slab.get_mut(index)
+    pub fn set_persistent(&mut self, persistent: bool) {
+        self.persistent = persistent;
     }
-}
 
-impl<T> Default for Unordered<T, Futures> {
-    fn default() -> Self {
-        Self::new()
+    /// Test if the collection is [persistent][Unordered::set_persistent].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::Ready;
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::<Ready<()>>::new();
+    /// assert!(!futures.is_persistent());
+    /// futures.set_persistent(true);
+    /// assert!(futures.is_persistent());
+    /// ```
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
     }
-}
 
-impl<T, S> Drop for Unordered<T, S>
+    /// Test if the collection has been [closed][Unordered::close].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::Ready;
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::<Ready<()>>::new();
+    /// assert!(!futures.is_closed());
+    /// futures.close();
+    /// assert!(futures.is_closed());
+    /// ```
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Enable detection of _spinners_, which are child tasks that wake
+    /// themselves while they are being polled for more than `threshold`
+    /// consecutive polling cycles.
+    ///
+    /// Such tasks are still only polled once per cycle, but they force every
+    /// cycle to run, which can waste a lot of CPU. Detected spinners can be
+    /// queried through [spinners][Unordered::spinners].
+    ///
+    /// Passing `None` disables detection, which is the default.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::Ready;
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::<Ready<()>>::new();
+    /// assert_eq!(None, futures.spin_threshold());
+    /// futures.set_spin_threshold(Some(16));
+    /// assert_eq!(Some(16), futures.spin_threshold());
+    /// ```
+    pub fn set_spin_threshold(&mut self, threshold: Option<usize>) {
+        self.spin.set_threshold(threshold, &self.shared.alloc);
+    }
+
+    /// Get the currently configured
+    /// [spinner threshold][Unordered::set_spin_threshold].
+    pub fn spin_threshold(&self) -> Option<usize> {
+        self.spin.threshold()
+    }
+
+    /// Configure how the storage for child tasks grows, see
+    /// [Growth][pin_slab::Growth].
+    ///
+    /// # Panics
+    ///
+    /// Panics if any storage has been allocated, which happens once the first
+    /// task has been pushed and lasts until the collection is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::Ready;
+    /// use unicycle::pin_slab::Growth;
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::<Ready<()>>::new();
+    /// assert_eq!(Growth::default(), futures.slot_growth());
+    /// futures.set_slot_growth(Growth::fixed(1024));
+    /// assert_eq!(Growth::fixed(1024), futures.slot_growth());
+    /// ```
+    pub fn set_slot_growth(&mut self, growth: pin_slab::Growth) {
+        self.slab.set_growth(growth);
+    }
+
+    /// Get the currently configured
+    /// [slot growth policy][Unordered::set_slot_growth].
+    pub fn slot_growth(&self) -> pin_slab::Growth {
+        self.slab.growth()
+    }
+
+    /// Configure the order in which the indexes of removed or completed child
+    /// tasks are reused, see [Reuse][pin_slab::Reuse].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::{self, Ready};
+    /// use unicycle::pin_slab::Reuse;
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::<Ready<()>>::new();
+    /// futures.set_key_reuse(Reuse::Lowest);
+    /// assert_eq!(Reuse::Lowest, futures.key_reuse());
+    ///
+    /// for _ in 0..4 {
+    ///     futures.push(future::ready(()));
+    /// }
+    ///
+    /// assert!(futures.try_remove(2).is_some());
+    /// assert!(futures.try_remove(1).is_some());
+    /// assert_eq!(1, futures.push(future::ready(())));
+    /// ```
+    pub fn set_key_reuse(&mut self, reuse: pin_slab::Reuse) {
+        self.slab.set_reuse(reuse);
+    }
+
+    /// Get the currently configured [index reuse policy][Unordered::set_key_reuse].
+    pub fn key_reuse(&self) -> pin_slab::Reuse {
+        self.slab.reuse()
+    }
+
+    /// Configure the order in which woken child tasks are polled, see
+    /// [WakeOrder].
+    ///
+    /// In [WakeOrder::Fifo] mode each wakeup which newly sets the bit of a
+    /// child is also recorded in a bounded lock-free queue next to the wake
+    /// set, which comes at a small cost for every wakeup. Tasks which are
+    /// woken while the order is being changed are still polled, but possibly
+    /// in index order.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::Ready;
+    /// use unicycle::{FuturesUnordered, WakeOrder};
+    ///
+    /// let mut futures = FuturesUnordered::<Ready<()>>::new();
+    /// assert_eq!(WakeOrder::Index, futures.wake_order());
+    /// futures.set_wake_order(WakeOrder::Fifo { capacity: 1024 });
+    /// assert_eq!(WakeOrder::Fifo { capacity: 1024 }, futures.wake_order());
+    /// ```
+    pub fn set_wake_order(&mut self, order: WakeOrder) {
+        if self.wake_order == order {
+            return;
+        }
+
+        let capacity = match order {
+            WakeOrder::Index => 0,
+            WakeOrder::Fifo { capacity } => capacity,
+        };
+
+        // Safety: We have exclusive access to the alternate set, and swapping
+        // gives us exclusive access to what used to be the active set. Both
+        // keep their bits, so no wakeups are lost.
+        unsafe {
+            let alloc = &self.shared.alloc;
+            WakeSet::as_mut_set(self.alternate).set_order_capacity(capacity, alloc);
+            self.shared
+                .swap_active(&mut self.alternate)
+                .set_order_capacity(capacity, alloc);
+        }
+
+        self.stats.swap();
+        self.wake_order = order;
+    }
+
+    /// Get the currently configured [wake order][Unordered::set_wake_order].
+    pub fn wake_order(&self) -> WakeOrder {
+        self.wake_order
+    }
+
+    /// List the indexes of child tasks which have woken themselves while being
+    /// polled for more consecutive polling cycles than the configured
+    /// [threshold][Unordered::set_spin_threshold].
+    ///
+    /// Returns an empty list if spinner detection is disabled.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::Future;
+    /// use std::pin::Pin;
+    /// use std::task::{Context, Poll};
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// struct Spinner;
+    ///
+    /// impl Future for Spinner {
+    ///     type Output = ();
+    ///
+    ///     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+    ///         cx.waker().wake_by_ref();
+    ///         Poll::Pending
+    ///     }
+    /// }
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut futures = FuturesUnordered::new();
+    ///     futures.set_spin_threshold(Some(2));
+    ///     let index = futures.push(Spinner);
+    ///
+    ///     for _ in 0..4 {
+    ///         assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    ///     }
+    ///
+    ///     assert_eq!(vec![index], futures.spinners());
+    ///
+    ///     // Spinners can be evicted.
+    ///     futures.try_remove(index);
+    ///     assert!(futures.spinners().is_empty());
+    /// }
+    /// ```
+    pub fn spinners(&self) -> Vec<usize> {
+        self.spin
+            .spinners()
+            .filter(|index| self.slab.contains(*index))
+            .collect()
+    }
+
+    /// Get the number of consecutive polling cycles in which the child task at
+    /// the given index has woken itself while being polled.
+    ///
+    /// Returns `None` if there is no task associated with the index. Always
+    /// returns `Some(0)` for existing tasks if spinner detection is disabled.
+    pub fn spin_count(&self, index: usize) -> Option<usize> {
+        if !self.slab.contains(index) {
+            return None;
+        }
+
+        Some(self.spin.count(index))
+    }
+
+    /// List the indexes of child tasks which have been woken but not yet
+    /// polled, in ascending order.
+    ///
+    /// Together with the indexes of all child tasks, this can be used to tell
+    /// apart children which were never woken from children which were woken
+    /// but are still waiting to be polled, for example when debugging a stall.
+    /// Wakeups of removed tasks are not included.
+    ///
+    /// This only reads the wake sets, without blocking wakers or changing
+    /// which tasks are woken. Wakeups which happen concurrently might not be
+    /// included.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::{self, Pending};
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut futures = FuturesUnordered::<Pending<()>>::new();
+    ///     let index = futures.push(future::pending());
+    ///
+    ///     // Newly pushed tasks are woken, so that they are polled once.
+    ///     assert_eq!(vec![index], futures.pending_wakes());
+    ///
+    ///     assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    ///     assert!(futures.pending_wakes().is_empty());
+    /// }
+    /// ```
+    pub fn pending_wakes(&self) -> Vec<usize> {
+        let mut woken = Vec::new();
+
+        // Safety: Wakers never touch the alternate set, and we're not swapping
+        // out the wake sets.
+        unsafe {
+            woken.extend(WakeSet::as_set(self.alternate).iter());
+            self.shared
+                .wake_set
+                .for_each_woken(|index| woken.push(index));
+            self.shared
+                .cells
+                .for_each_pending(|index| woken.push(index));
+        }
+
+        woken.sort_unstable();
+        woken.dedup();
+        woken.retain(|index| self.slab.contains(*index));
+        woken
+    }
+
+    /// Test if the child task at the given index has been woken but not yet
+    /// polled.
+    ///
+    /// Returns `false` if there is no task associated with the index. See
+    /// [pending_wakes][Unordered::pending_wakes] for details.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::{self, Pending};
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::<Pending<()>>::new();
+    /// let index = futures.push(future::pending());
+    /// assert!(futures.is_woken(index));
+    /// assert!(!futures.is_woken(index + 1));
+    /// ```
+    pub fn is_woken(&self, index: usize) -> bool {
+        if !self.slab.contains(index) {
+            return false;
+        }
+
+        // Safety: Wakers never touch the alternate set, and we're not swapping
+        // out the wake sets.
+        unsafe {
+            WakeSet::as_set(self.alternate).test(index)
+                || self.shared.wake_set.is_woken(index)
+                || self.shared.cells.is_pending(index)
+        }
+    }
+
+    /// Get a snapshot of the aggregate scheduling statistics for this
+    /// collection.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::ready;
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut futures = FuturesUnordered::new();
+    ///     futures.push(ready(1));
+    ///     futures.push(ready(2));
+    ///
+    ///     while let Some(_) = futures.next().await {}
+    ///
+    ///     let stats = futures.stats();
+    ///     assert_eq!(2, stats.polled);
+    /// }
+    /// ```
+    #[cfg(feature = "stats")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "stats")))]
+    pub fn stats(&self) -> SetStats {
+        self.stats.set_stats()
+    }
+
+    /// Get scheduling statistics for the child task at the given index.
+    ///
+    /// Returns `None` if there is no task associated with the index.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::new();
+    /// let index = futures.push(async { 42 });
+    ///
+    /// let stats = futures.child_stats(index).expect("child to exist");
+    /// assert_eq!(0, stats.polls);
+    /// assert!(futures.child_stats(index + 1).is_none());
+    /// ```
+    #[cfg(feature = "stats")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "stats")))]
+    pub fn child_stats(&self, index: usize) -> Option<ChildStats> {
+        if !self.slab.contains(index) {
+            return None;
+        }
+
+        // Safety: Cells are reserved for every child, and reserving them
+        // requires exclusive access to the unordered set.
+        let entry = unsafe { self.shared.cells.stats(index) };
+        Some(
+            self.stats
+                .child_stats(index, &self.shared.wake_stats, entry),
+        )
+    }
+
+    /// Remove the stream or future at the given index and return it.
+    ///
+    /// Returns `None` if there was no task associated with the index. Since
+    /// this moves the task out of the collection, it requires that it is
+    /// [Unpin].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::{ready, Ready};
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::<Ready<u32>>::new();
+    /// let index = futures.push(ready(42));
+    /// assert!(futures.try_remove(index).is_some());
+    /// assert!(futures.try_remove(index).is_none());
+    /// assert!(futures.is_empty());
+    /// ```
+    pub fn try_remove(&mut self, index: usize) -> Option<T>
+    where
+        T: Unpin,
+    {
+        let task = self.slab.try_remove(index)?;
+        self.trace.remove(index);
+        Some(task)
+    }
+
+    /// Remove the stream or future at the given index, calling the given
+    /// closure with it pinned before it's dropped.
+    ///
+    /// Returns the output of the closure, or `None` if there was no task
+    /// associated with the index.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use tokio::time::{sleep_until, Instant};
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut futures = FuturesUnordered::new();
+    ///
+    ///     let deadline = Instant::now() + Duration::from_secs(1);
+    ///     let index = futures.push(sleep_until(deadline));
+    ///
+    ///     assert_eq!(Some(deadline), futures.remove_with(index, |sleep| sleep.deadline()));
+    ///     assert!(futures.is_empty());
+    /// }
+    /// ```
+    pub fn remove_with<F, R>(&mut self, index: usize, f: F) -> Option<R>
+    where
+        F: FnOnce(Pin<&mut T>) -> R,
+    {
+        let output = self.slab.remove_with(index, f)?;
+        self.trace.remove(index);
+        Some(output)
+    }
+
+    /// Move child tasks into the lowest free indexes, so that they occupy the
+    /// indexes `0..len`, and release the storage which is no longer needed.
+    ///
+    /// After many tasks have completed the remaining ones might be scattered
+    /// across a large range of indexes, which every polling cycle and every
+    /// push has to cover. Once the collection has been compacted, `f` is
+    /// called with the old and the new index of every task which was moved,
+    /// so that any side tables keyed by index can be updated.
+    ///
+    /// Wakeups which are pending for a moved task are moved along with it.
+    /// Wakers handed out before compaction remain safe to use. Those of moved
+    /// tasks wake them at their new index, while those of tasks which were
+    /// removed before compaction do nothing.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::{self, Pending};
+    /// use unicycle::FuturesUnordered;
+    ///
+    /// let mut futures = FuturesUnordered::<Pending<()>>::new();
+    ///
+    /// for _ in 0..4 {
+    ///     futures.push(future::pending());
+    /// }
+    ///
+    /// assert!(futures.try_remove(0).is_some());
+    /// assert!(futures.try_remove(1).is_some());
+    ///
+    /// let mut moved = Vec::new();
+    /// futures.compact(|old, new| moved.push((old, new)));
+    ///
+    /// assert_eq!(vec![(3, 0), (2, 1)], moved);
+    /// assert!(futures.get_mut(1).is_some());
+    /// assert!(futures.get_mut(2).is_none());
+    /// ```
+    pub fn compact<F>(&mut self, mut f: F)
+    where
+        T: Unpin,
+        F: FnMut(usize, usize),
+    {
+        let Self {
+            ref mut slab,
+            ref shared,
+            alternate,
+            ref mut stats,
+            ref mut trace,
+            ref mut spin,
+            ..
+        } = *self;
+
+        let mut moved = RawVec::new();
+
+        slab.compact(|old, new| {
+            stats.relocate(old, new, &shared.alloc);
+            trace.relocate(old, new, &shared.alloc);
+            spin.relocate(old, new);
+            moved.push((old, new), &shared.alloc);
+            f(old, new);
+        });
+
+        // Safety: Cells are reserved for every child, and we have exclusive
+        // access to the unordered set and its alternate set.
+        let woken = unsafe {
+            // NB: Pending wakeups are recorded in the cells of their old
+            // indexes, so they have to be taken before the cells are compacted.
+            shared.take_pending(WakeSet::as_mut_set(alternate));
+
+            shared.cells.compact(
+                Arc::as_ptr(shared),
+                slab.len(),
+                &moved,
+                |old, new| shared.wake_stats.relocate(old, new),
+                &shared.alloc,
+            );
+
+            let woken = shared.wake_set.compact(
+                WakeSet::as_mut_set(alternate),
+                &moved,
+                shared.cells.capacity(),
+                &shared.alloc,
+            );
+
+            moved.free(&shared.alloc);
+            woken
+        };
+
+        // The collection might be parked waiting for the wakeups we just
+        // moved.
+        if woken {
+            shared.waker.wake_by_ref();
+        }
+    }
+
+    /// Get a pinned mutable reference to the stream or future at the given
+    /// index.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::FuturesUnordered;
+    /// use futures::future::poll_fn;
+    /// use std::future::Future as _;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut futures = FuturesUnordered::new();
+    ///     let index = futures.push(async { 42 });
+    ///
+    ///     let result = poll_fn(|cx| {
+    ///         futures.get_pin_mut(index).expect("expected future").poll(cx)
+    ///     }).await;
+    ///
+    ///     assert_eq!(result, 42);
+    /// }
+    /// ```
+    pub fn get_pin_mut(&mut self, index: usize) -> Option<Pin<&mut T>> {
+        self.slab.get_pin_mut(index)
+    }
+
+    /// Get a mutable reference to the stream or future at the given index.
+    /// Requires that the stores stream or future is [Unpin].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::FuturesUnordered;
+    /// use futures::future::{ready, poll_fn};
+    /// use std::{pin::Pin, future::Future as _};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut futures = FuturesUnordered::new();
+    ///     let index = futures.push(ready(42));
+    ///
+    ///     let result = poll_fn(|cx| {
+    ///         Pin::new(futures.get_mut(index).expect("expected future")).poll(cx)
+    ///     }).await;
+    ///
+    ///     assert_eq!(result, 42);
+    /// }
+    /// ```
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T>
+    where
+        T: Unpin,
+    {
+        self.slab.get_mut(index)
+    }
+}
+
+impl<T, L> Default for Unordered<T, Futures, L>
+where
+    L: RawLock,
+{
+    fn default() -> Self {
+        Self::with_lock()
+    }
+}
+
+impl<T, S, L, B, A> Drop for Unordered<T, S, L, B, A>
 where
     S: Sentinel,
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
 {
     fn drop(&mut self) {
         // Cancel all child futures in an attempt to prevent them from
@@ -704,14 +1900,25 @@ where
         // calling WakeSet::lock_exclusive. We are also the _only_ one
         // swapping `wake_alternative`, so we know that can't happen here.
         unsafe {
-            drop(Box::from_raw(self.alternate));
+            WakeSet::drop_raw(self.alternate, &self.shared.alloc);
+        }
+
+        // Safety: The per-child diagnostics are only ever grown with the
+        // allocator of the shared data.
+        unsafe {
+            self.stats.free(&self.shared.alloc);
+            self.trace.free(&self.shared.alloc);
+            self.spin.free(&self.shared.alloc);
         }
     }
 }
 
-impl<T, S> iter::Extend<T> for Unordered<T, S>
+impl<T, S, L, B, A> iter::Extend<T> for Unordered<T, S, L, B, A>
 where
     S: Sentinel,
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
 {
     fn extend<I>(&mut self, iter: I)
     where
@@ -723,13 +1930,14 @@ where
     }
 }
 
-impl<T> iter::FromIterator<T> for FuturesUnordered<T>
+impl<T, L> iter::FromIterator<T> for FuturesUnordered<T, L>
 where
     T: Future,
+    L: RawLock,
 {
     #[inline]
     fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
-        let mut futures = FuturesUnordered::new();
+        let mut futures = FuturesUnordered::with_lock();
         futures.extend(iter);
         futures
     }
@@ -792,7 +2000,7 @@ cfg_futures_rs! {
     ///     }
     /// }
     /// ```
-    pub type StreamsUnordered<T> = Unordered<T, Streams>;
+    pub type StreamsUnordered<T, L = DefaultLock, B = DefaultBitSet, A = Global> = Unordered<T, Streams, L, B, A>;
 
     /// A container for an unordered collection of [Stream]s, which also yields the
     /// index that produced the next item.
@@ -825,7 +2033,8 @@ cfg_futures_rs! {
     ///     }
     /// }
     /// ```
-    pub type IndexedStreamsUnordered<T> = Unordered<T, IndexedStreams>;
+    pub type IndexedStreamsUnordered<T, L = DefaultLock, B = DefaultBitSet, A = Global> =
+        Unordered<T, IndexedStreams, L, B, A>;
 
     impl<T> StreamsUnordered<T> {
         /// Construct a new, empty [StreamsUnordered].
@@ -854,7 +2063,77 @@ cfg_futures_rs! {
         /// }
         /// ```
         pub fn new() -> Self {
-            Self::new_internal()
+            Self::new_internal(Global)
+        }
+    }
+
+    impl<T, L> StreamsUnordered<T, L>
+    where
+        L: RawLock,
+    {
+        /// Construct a new, empty [StreamsUnordered] using a custom lock backend.
+        ///
+        /// See the [lock] module for the available backends.
+        pub fn with_lock() -> Self {
+            Self::new_internal(Global)
+        }
+    }
+
+    impl<T, L, B> StreamsUnordered<T, L, B>
+    where
+        L: RawLock,
+        B: RawBitSet,
+    {
+        /// Construct a new, empty [StreamsUnordered] using custom lock and bit set
+        /// backends.
+        ///
+        /// See the [bitset] module for the available bit set backends.
+        pub fn with_bit_set() -> Self {
+            Self::new_internal(Global)
+        }
+    }
+
+    #[cfg(feature = "allocator-api2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "allocator-api2")))]
+    impl<T, A> StreamsUnordered<T, DefaultLock, DefaultBitSet, A>
+    where
+        A: Allocator + Clone + Send + Sync + 'static,
+    {
+        /// Construct a new, empty [StreamsUnordered] which allocates its storage
+        /// through the given allocator.
+        ///
+        /// See [Unordered] for which allocations are affected.
+        ///
+        /// # Examples
+        ///
+        /// ```rust
+        /// use allocator_api2::alloc::Global;
+        /// use unicycle::StreamsUnordered;
+        ///
+        /// let mut set = StreamsUnordered::new_in(Global);
+        /// assert!(set.is_empty());
+        ///
+        /// set.push(tokio_stream::iter(vec![1, 2]));
+        /// ```
+        pub fn new_in(alloc: A) -> Self {
+            Self::new_internal(alloc)
+        }
+    }
+
+    #[cfg(feature = "allocator-api2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "allocator-api2")))]
+    impl<T, L, B, A> StreamsUnordered<T, L, B, A>
+    where
+        L: RawLock,
+        B: RawBitSet,
+        A: Allocator + Clone + Send + Sync + 'static,
+    {
+        /// Construct a new, empty [StreamsUnordered] using custom lock and bit set
+        /// backends, which allocates its storage through the given allocator.
+        ///
+        /// See the [lock] and [bitset] modules for the available backends.
+        pub fn with_lock_in(alloc: A) -> Self {
+            Self::new_internal(alloc)
         }
     }
 
@@ -899,14 +2178,87 @@ cfg_futures_rs! {
         /// }
         /// ```
         pub fn new() -> Self {
-            Self::new_internal()
+            Self::new_internal(Global)
+        }
+    }
+
+    impl<T, L> IndexedStreamsUnordered<T, L>
+    where
+        L: RawLock,
+    {
+        /// Construct a new, empty [IndexedStreamsUnordered] using a custom lock backend.
+        ///
+        /// See the [lock] module for the available backends.
+        pub fn with_lock() -> Self {
+            Self::new_internal(Global)
+        }
+    }
+
+    impl<T, L, B> IndexedStreamsUnordered<T, L, B>
+    where
+        L: RawLock,
+        B: RawBitSet,
+    {
+        /// Construct a new, empty [IndexedStreamsUnordered] using custom lock and bit set
+        /// backends.
+        ///
+        /// See the [bitset] module for the available bit set backends.
+        pub fn with_bit_set() -> Self {
+            Self::new_internal(Global)
+        }
+    }
+
+    #[cfg(feature = "allocator-api2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "allocator-api2")))]
+    impl<T, A> IndexedStreamsUnordered<T, DefaultLock, DefaultBitSet, A>
+    where
+        A: Allocator + Clone + Send + Sync + 'static,
+    {
+        /// Construct a new, empty [IndexedStreamsUnordered] which allocates its storage
+        /// through the given allocator.
+        ///
+        /// See [Unordered] for which allocations are affected.
+        ///
+        /// # Examples
+        ///
+        /// ```rust
+        /// use allocator_api2::alloc::Global;
+        /// use unicycle::IndexedStreamsUnordered;
+        ///
+        /// let mut set = IndexedStreamsUnordered::new_in(Global);
+        /// assert!(set.is_empty());
+        ///
+        /// set.push(tokio_stream::iter(vec![1, 2]));
+        /// ```
+        pub fn new_in(alloc: A) -> Self {
+            Self::new_internal(alloc)
+        }
+    }
+
+    #[cfg(feature = "allocator-api2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "allocator-api2")))]
+    impl<T, L, B, A> IndexedStreamsUnordered<T, L, B, A>
+    where
+        L: RawLock,
+        B: RawBitSet,
+        A: Allocator + Clone + Send + Sync + 'static,
+    {
+        /// Construct a new, empty [IndexedStreamsUnordered] using custom lock and bit set
+        /// backends, which allocates its storage through the given allocator.
+        ///
+        /// See the [lock] and [bitset] modules for the available backends.
+        pub fn with_lock_in(alloc: A) -> Self {
+            Self::new_internal(alloc)
         }
     }
 
     /// Provide `Stream` implementation through `PollNext`.
-    impl<T, S> Stream for Unordered<T, S>
+    impl<T, S, L, B, A> Stream for Unordered<T, S, L, B, A>
     where
         S: Sentinel,
+        L: RawLock,
+        B: RawBitSet,
+        A: Allocator,
         Self: PollNext,
     {
         type Item = <Self as PollNext>::Item;
@@ -916,15 +2268,25 @@ cfg_futures_rs! {
         }
     }
 
-    impl<T, S> FusedStream for Unordered<T, S> where S: Sentinel, Self: PollNext, {
+    impl<T, S, L, B, A> FusedStream for Unordered<T, S, L, B, A>
+    where
+        S: Sentinel,
+        L: RawLock,
+        B: RawBitSet,
+        A: Allocator,
+        Self: PollNext,
+    {
         fn is_terminated(&self) -> bool {
-            self.is_empty()
+            self.closed && self.is_empty()
         }
     }
 
-    impl<T> PollNext for StreamsUnordered<T>
+    impl<T, L, B, A> PollNext for StreamsUnordered<T, L, B, A>
     where
         T: Stream,
+        L: RawLock,
+        B: RawBitSet,
+        A: Allocator,
     {
         type Item = T::Item;
 
@@ -933,19 +2295,33 @@ cfg_futures_rs! {
                 ref mut slab,
                 ref shared,
                 ref mut alternate,
+                ref mut stats,
+                ref mut trace,
+                ref mut spin,
+                closed,
+                persistent,
                 ..
             } = *self.as_mut();
 
             if slab.is_empty() {
-                // Nothing to poll, nothing to add. End the stream since we don't have work to do.
-                return Poll::Ready(None);
+                // Nothing to poll, nothing to add. End the stream since we don't
+                // have work to do, unless we're persistent in which case we wait
+                // for more work.
+                // Safety: We have exclusive access to Unordered.
+                return unsafe { shared.poll_empty(cx, persistent && !closed) };
             }
 
             // Safety: We have exclusive access to Unordered, which is the only
             // implementation that is trying to swap the wake sets.
             let (non_empty, wake_last) = ready!(unsafe { shared.poll_swap_active(cx, alternate) });
 
+            if !non_empty {
+                stats.swap();
+            }
+
             for index in wake_last.drain() {
+                trace.drain();
+
                 // NB: Since we defer pollables a little, a future might
                 // have been polled and subsequently removed from the slab.
                 // So we don't treat this as an error here.
@@ -954,32 +2330,52 @@ cfg_futures_rs! {
                 // future much.
                 let stream = match slab.get_pin_mut(index) {
                     Some(stream) => stream,
-                    None => continue,
+                    None => {
+                        stats.spurious();
+                        continue;
+                    }
                 };
 
                 // Construct a new lightweight waker only capable of waking by
                 // reference, with referential access to `shared`.
-                let result = self::waker::poll_with_ref(shared, index, move |cx| stream.poll_next(cx));
+                let result = stats.poll(index, || {
+                    trace.poll(index, || {
+                        // Safety: The cell for the index was reserved when the
+                        // task was pushed, and cells can't be reserved while we
+                        // are polling.
+                        unsafe { shared.poll_with_ref(index, move |cx| stream.poll_next(cx)) }
+                    })
+                });
+
+                spin.record(index, shared.self_wake.woken(), &shared.alloc);
 
                 if let Poll::Ready(result) = result {
                     match result {
                         Some(value) => {
                             cx.waker().wake_by_ref();
-                            shared.wake_set.wake(index);
+                            // Safety: The cell for the index was reserved
+                            // when the stream was pushed, and cells can't be
+                            // reserved while we are polling.
+                            shared.wake_set.wake(index, unsafe { shared.cells.pending(index) });
                             return Poll::Ready(Some(value));
                         }
                         None => {
                             let removed = slab.remove(index);
                             debug_assert!(removed);
+                            trace.remove(index);
                         }
                     }
                 }
             }
 
+            stats.end_cycle();
+            trace.end_cycle();
+
             // We have successfully polled the last snapshot.
             // Yield and make sure that we are polled again.
             if slab.is_empty() {
-                return Poll::Ready(None);
+                // Safety: We have exclusive access to Unordered.
+                return unsafe { shared.poll_empty(cx, persistent && !closed) };
             }
 
             // We need to wake again to take care of the alternate set that was
@@ -992,9 +2388,12 @@ cfg_futures_rs! {
         }
     }
 
-    impl<T> PollNext for IndexedStreamsUnordered<T>
+    impl<T, L, B, A> PollNext for IndexedStreamsUnordered<T, L, B, A>
     where
         T: Stream,
+        L: RawLock,
+        B: RawBitSet,
+        A: Allocator,
     {
         type Item = (usize, Option<T::Item>);
 
@@ -1003,19 +2402,33 @@ cfg_futures_rs! {
                 ref mut slab,
                 ref shared,
                 ref mut alternate,
+                ref mut stats,
+                ref mut trace,
+                ref mut spin,
+                closed,
+                persistent,
                 ..
             } = *self.as_mut();
 
             if slab.is_empty() {
-                // Nothing to poll, nothing to add. End the stream since we don't have work to do.
-                return Poll::Ready(None);
+                // Nothing to poll, nothing to add. End the stream since we don't
+                // have work to do, unless we're persistent in which case we wait
+                // for more work.
+                // Safety: We have exclusive access to Unordered.
+                return unsafe { shared.poll_empty(cx, persistent && !closed) };
             }
 
             // Safety: We have exclusive access to Unordered, which is the only
             // implementation that is trying to swap the wake sets.
             let (non_empty, wake_last) = ready!(unsafe { shared.poll_swap_active(cx, alternate) });
 
+            if !non_empty {
+                stats.swap();
+            }
+
             for index in wake_last.drain() {
+                trace.drain();
+
                 // NB: Since we defer pollables a little, a future might
                 // have been polled and subsequently removed from the slab.
                 // So we don't treat this as an error here.
@@ -1024,34 +2437,54 @@ cfg_futures_rs! {
                 // future much.
                 let stream = match slab.get_pin_mut(index) {
                     Some(stream) => stream,
-                    None => continue,
+                    None => {
+                        stats.spurious();
+                        continue;
+                    }
                 };
 
                 // Construct a new lightweight waker only capable of waking by
                 // reference, with referential access to `shared`.
-                let result = self::waker::poll_with_ref(shared, index, move |cx| stream.poll_next(cx));
+                let result = stats.poll(index, || {
+                    trace.poll(index, || {
+                        // Safety: The cell for the index was reserved when the
+                        // task was pushed, and cells can't be reserved while we
+                        // are polling.
+                        unsafe { shared.poll_with_ref(index, move |cx| stream.poll_next(cx)) }
+                    })
+                });
+
+                spin.record(index, shared.self_wake.woken(), &shared.alloc);
 
                 if let Poll::Ready(result) = result {
                     match result {
                         Some(value) => {
                             cx.waker().wake_by_ref();
-                            shared.wake_set.wake(index);
+                            // Safety: The cell for the index was reserved
+                            // when the stream was pushed, and cells can't be
+                            // reserved while we are polling.
+                            shared.wake_set.wake(index, unsafe { shared.cells.pending(index) });
                             return Poll::Ready(Some((index, Some(value))));
                         }
                         None => {
                             cx.waker().wake_by_ref();
                             let removed = slab.remove(index);
                             debug_assert!(removed);
+                            trace.remove(index);
                             return Poll::Ready(Some((index, None)));
                         }
                     }
                 }
             }
 
+            stats.end_cycle();
+            trace.end_cycle();
+
             // We have successfully polled the last snapshot.
             // Yield and make sure that we are polled again.
             if slab.is_empty() {
-                return Poll::Ready(None);
+                // Safety: We have exclusive access to Unordered.
+                return unsafe { shared.poll_empty(cx, persistent && !closed) };
             }
 
             // We need to wake again to take care of the alternate set that was
@@ -1064,13 +2497,14 @@ cfg_futures_rs! {
         }
     }
 
-    impl<T> iter::FromIterator<T> for StreamsUnordered<T>
+    impl<T, L> iter::FromIterator<T> for StreamsUnordered<T, L>
     where
         T: Stream,
+        L: RawLock,
     {
         #[inline]
         fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
-            let mut streams = StreamsUnordered::new();
+            let mut streams = StreamsUnordered::with_lock();
             streams.extend(iter);
             streams
         }
@@ -0,0 +1,829 @@
+//! A single-threaded variant of [Unordered].
+//!
+//! [LocalUnordered] drives its children with the same fairness guarantees as
+//! [Unordered], but since wakeups are expected to happen on the thread which
+//! owns the collection it can record them without any atomics or locks.
+//! Wakeups are recorded in a [DefaultBitSet] through exclusive access and the
+//! parent waker is stored in a [RefCell].
+//!
+//! [Waker] is required to be both `Send` and `Sync`, so we still need to deal
+//! with wakers which escape to other threads:
+//! * The shared state is reference counted through an [Arc], so wakers can be
+//!   cloned and dropped on any thread. The waker used while polling borrows it
+//!   instead, so this only costs anything for wakers which are cloned.
+//! * Wakers used on another thread record their wakeups in a side list behind a
+//!   [Mutex] and wake the parent waker. The collection moves them into its wake
+//!   set the next time it's polled.
+//!
+//! [Arc]: std::sync::Arc
+//! [Unordered]: crate::Unordered
+
+use crate::allocator::Global;
+use crate::bitset::DefaultBitSet;
+use crate::pin_slab::{Growth, PinSlab, Reuse};
+use crate::stats::WakeEntry;
+use crate::sync::{Arc, AtomicBool, Ordering};
+use crate::wake_set::{self, Woken};
+use crate::waker::{WakeIndex, WakerCells};
+use crate::{Futures, PollNext, Sentinel, WakeOrder};
+#[cfg(feature = "futures-rs")]
+use crate::{IndexedStreams, Streams};
+#[cfg(feature = "futures-rs")]
+use futures_core::{FusedStream, Stream};
+use std::{
+    cell::RefCell,
+    future::Future,
+    iter, marker, mem,
+    pin::Pin,
+    sync::{Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+
+std::thread_local! {
+    static THREAD: u8 = const { 0 };
+}
+
+/// Get a value which uniquely identifies the current thread among all threads
+/// which are currently alive.
+///
+/// We use the address of a thread local, since it's cheap to access and does
+/// not involve any atomics.
+fn current_thread() -> usize {
+    THREAD.with(|thread| thread as *const u8 as usize)
+}
+
+/// A container for an unordered collection of [Future]s, which is driven on a
+/// single thread.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::rc::Rc;
+/// use unicycle::LocalFuturesUnordered;
+///
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() {
+///     let local = Rc::new(42);
+///     let mut futures = LocalFuturesUnordered::new();
+///
+///     futures.push({
+///         let local = local.clone();
+///         async move { *local }
+///     });
+///
+///     assert_eq!(Some(42), futures.next().await);
+/// }
+/// ```
+pub type LocalFuturesUnordered<T> = LocalUnordered<T, Futures>;
+
+/// A container for an unordered collection of [Stream]s, which is driven on a
+/// single thread.
+#[cfg(feature = "futures-rs")]
+#[cfg_attr(docsrs, doc(cfg(feature = "futures-rs")))]
+pub type LocalStreamsUnordered<T> = LocalUnordered<T, Streams>;
+
+/// A container for an unordered collection of [Stream]s, which is driven on a
+/// single thread and also yields the index that produced the next item.
+#[cfg(feature = "futures-rs")]
+#[cfg_attr(docsrs, doc(cfg(feature = "futures-rs")))]
+pub type LocalIndexedStreamsUnordered<T> = LocalUnordered<T, IndexedStreams>;
+
+/// Data that is shared across all sub-tasks.
+///
+/// Only `thread`, `remote` and `remote_woken` are accessed from other threads. The
+/// shared data might be dropped on another thread by the last waker, which is
+/// fine since all of its fields can be sent across threads.
+struct Shared {
+    /// The thread which owns the collection.
+    thread: usize,
+    /// The currently registered parent waker.
+    waker: RefCell<Option<Waker>>,
+    /// The set of indexes which have been woken since it was last swapped out.
+    active: RefCell<Woken<DefaultBitSet>>,
+    /// Per-index waker cells, used by cloned wakers.
+    cells: WakerCells<Self>,
+    /// Wakeups from other threads.
+    remote: Mutex<Remote>,
+    /// Set if `remote` has wakeups which have not been drained yet.
+    remote_woken: AtomicBool,
+}
+
+/// Wakeups from other threads, which can't touch the wake set directly.
+struct Remote {
+    /// Indexes which have been woken.
+    woken: Vec<usize>,
+    /// A copy of the parent waker, so that it can be woken from other threads.
+    waker: Option<Waker>,
+}
+
+impl Shared {
+    /// Construct new shared data.
+    fn new() -> Self {
+        Self {
+            thread: current_thread(),
+            waker: RefCell::new(None),
+            active: RefCell::new(Woken::new()),
+            cells: WakerCells::new(),
+            remote: Mutex::new(Remote {
+                woken: Vec::new(),
+                waker: None,
+            }),
+            remote_woken: AtomicBool::new(false),
+        }
+    }
+
+    /// Test if we are on the thread which owns the shared data.
+    fn is_local(&self) -> bool {
+        self.thread == current_thread()
+    }
+
+    /// Lock the wakeups from other threads.
+    fn remote(&self) -> MutexGuard<'_, Remote> {
+        self.remote.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wake the child task at the given index, and notify the parent waker.
+    ///
+    /// Must only be called on the thread which owns the shared data.
+    fn wake(&self, index: usize) {
+        self.active.borrow_mut().set(index, &Global);
+        self.wake_parent();
+    }
+
+    /// Record a wakeup from another thread, and notify the parent waker.
+    fn wake_remote(&self, index: usize) {
+        let waker = {
+            let mut remote = self.remote();
+            remote.woken.push(index);
+            self.remote_woken.store(true, Ordering::Release);
+            remote.waker.clone()
+        };
+
+        // NB: We don't know what the parent waker does, so it's woken outside
+        // of the lock.
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Move wakeups from other threads into the active wake set.
+    fn drain_remote(&self) {
+        if !self.remote_woken.load(Ordering::Acquire) {
+            return;
+        }
+
+        let mut remote = self.remote();
+        self.remote_woken.store(false, Ordering::Relaxed);
+        let mut active = self.active.borrow_mut();
+
+        for index in remote.woken.drain(..) {
+            active.set(index, &Global);
+        }
+    }
+
+    /// Move the wakeups of children which were moved by compaction, and
+    /// truncate the wake sets to the cells which remain.
+    ///
+    /// Returns `true` if any wakeups were moved.
+    fn compact(&self, alternate: &mut Woken<DefaultBitSet>, moved: &[(usize, usize)]) -> bool {
+        self.drain_remote();
+
+        // Safety: Cells are only reserved by the collection which owns the
+        // shared data, which is the only one calling this.
+        let cap = unsafe { self.cells.capacity() };
+        let mut active = self.active.borrow_mut();
+        let woken = wake_set::compact(&mut active, alternate, moved, &Global);
+        active.truncate(cap, &Global);
+        alternate.truncate(cap, &Global);
+        woken
+    }
+
+    /// Notify the parent waker.
+    fn wake_parent(&self) {
+        if let Ok(waker) = self.waker.try_borrow() {
+            if let Some(waker) = &*waker {
+                waker.wake_by_ref();
+            }
+        }
+    }
+
+    /// Register the parent waker.
+    fn register(&self, waker: &Waker) {
+        let mut current = self.waker.borrow_mut();
+
+        if !matches!(&*current, Some(current) if current.will_wake(waker)) {
+            *current = Some(waker.clone());
+            self.remote().waker = Some(waker.clone());
+        }
+    }
+
+    /// Poll a collection which is empty.
+    ///
+    /// If `wait` is set, the parent waker is registered so that it can be
+    /// woken once more work is added and we return `Poll::Pending`. Otherwise
+    /// the collection has terminated.
+    fn poll_empty<T>(&self, cx: &Context<'_>, wait: bool) -> Poll<Option<T>> {
+        if !wait {
+            return Poll::Ready(None);
+        }
+
+        self.register(cx.waker());
+        Poll::Pending
+    }
+
+    /// Swap the active wake set with the alternate one, unless the alternate
+    /// set still has indexes left to drain from a previous poll.
+    ///
+    /// Returns `true` if the alternate set was non-empty and no swap took
+    /// place.
+    fn swap_active(&self, cx: &Context<'_>, alternate: &mut Woken<DefaultBitSet>) -> bool {
+        if !alternate.is_empty() {
+            return true;
+        }
+
+        // Note: We must register the waker before we swap the set.
+        self.register(cx.waker());
+        self.drain_remote();
+        mem::swap(&mut *self.active.borrow_mut(), alternate);
+        false
+    }
+}
+
+impl WakeIndex for Shared {
+    fn wake_index(&self, index: usize, _: &WakeEntry, _: &AtomicBool) {
+        if self.is_local() {
+            self.wake(index);
+        } else {
+            self.wake_remote(index);
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // Safety: The wake sets and waker cells of local collections are
+        // always allocated through the global allocator. At this point the
+        // shared data is being dropped, so there are no wakers left
+        // referencing the cells.
+        unsafe {
+            self.active.get_mut().free(&Global);
+            self.cells.free(&Global);
+        }
+    }
+}
+
+/// The outcome of polling a single child which is ready.
+enum Ready<T> {
+    /// The child produced an item and remains in the collection. Only streams
+    /// produce these.
+    #[cfg(feature = "futures-rs")]
+    Item(T),
+    /// The child has completed, optionally producing a final item.
+    Done(Option<T>),
+}
+
+/// A container for an unordered collection of [Future]s or [Stream]s which is
+/// driven on a single thread.
+///
+/// This behaves like [Unordered][crate::Unordered], but avoids all atomics and
+/// locking since wakeups are expected to happen on the thread which owns the
+/// collection.
+///
+/// Since [Waker] is `Send`, wakers handed to children can still escape to
+/// other threads. Such wakers keep working, but waking them takes a lock.
+///
+/// You should use one of the following type aliases to construct it:
+/// * [LocalFuturesUnordered]
+/// * [LocalStreamsUnordered]
+/// * [LocalIndexedStreamsUnordered]
+pub struct LocalUnordered<T, S>
+where
+    S: Sentinel,
+{
+    /// Slab of futures being polled.
+    slab: PinSlab<T>,
+    /// Shared data, including the active wake set.
+    shared: Arc<Shared>,
+    /// Alternate wake set, which is being drained while polling.
+    alternate: Woken<DefaultBitSet>,
+    /// Set once [LocalUnordered::close] has been called.
+    closed: bool,
+    /// When set, an empty collection waits for more work instead of
+    /// terminating.
+    persistent: bool,
+    /// The order in which woken tasks are polled.
+    wake_order: WakeOrder,
+    /// Marker for the sentinel.
+    _marker: marker::PhantomData<S>,
+}
+
+impl<T, S> Unpin for LocalUnordered<T, S> where S: Sentinel {}
+
+impl<T, S> LocalUnordered<T, S>
+where
+    S: Sentinel,
+    Self: PollNext,
+{
+    /// Creates a future that resolves to the next item in the unordered set.
+    ///
+    /// See [Unordered::next][crate::Unordered::next].
+    pub async fn next(&mut self) -> Option<<Self as PollNext>::Item> {
+        return Next(self).await;
+
+        struct Next<'a, T>(&'a mut T);
+
+        impl<T> Future for Next<'_, T>
+        where
+            T: Unpin + PollNext,
+        {
+            type Output = Option<T::Item>;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                Pin::new(&mut *self.0).poll_next(cx)
+            }
+        }
+    }
+}
+
+impl<T> LocalFuturesUnordered<T> {
+    /// Construct a new, empty [LocalFuturesUnordered].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::LocalFuturesUnordered;
+    ///
+    /// let mut futures = LocalFuturesUnordered::new();
+    /// assert!(futures.is_empty());
+    ///
+    /// futures.push(async { 42 });
+    /// ```
+    pub fn new() -> Self {
+        Self::new_internal()
+    }
+}
+
+impl<T> PollNext for LocalFuturesUnordered<T>
+where
+    T: Future,
+{
+    type Item = T::Output;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T::Output>> {
+        self.poll_children(cx, |_, fut, cx| {
+            fut.poll(cx).map(|output| Ready::Done(Some(output)))
+        })
+    }
+}
+
+impl<T, S> LocalUnordered<T, S>
+where
+    S: Sentinel,
+{
+    /// Poll woken children through `poll`, until one of them produces an item
+    /// or there are no woken children left.
+    fn poll_children<F, R>(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        mut poll: F,
+    ) -> Poll<Option<R>>
+    where
+        F: FnMut(usize, Pin<&mut T>, &mut Context<'_>) -> Poll<Ready<R>>,
+    {
+        let Self {
+            ref mut slab,
+            ref shared,
+            ref mut alternate,
+            closed,
+            persistent,
+            ..
+        } = *self.as_mut();
+
+        if slab.is_empty() {
+            return shared.poll_empty(cx, persistent && !closed);
+        }
+
+        let non_empty = shared.swap_active(cx, alternate);
+
+        for index in alternate.drain() {
+            // NB: A child might have been polled and subsequently removed from
+            // the slab, see `Unordered`.
+            let child = match slab.get_pin_mut(index) {
+                Some(child) => child,
+                None => continue,
+            };
+
+            // Safety: The cell was reserved when the child was pushed, and
+            // cells can't be reserved while we are polling.
+            let result = unsafe {
+                shared
+                    .cells
+                    .poll_with_ref(index, |cx| poll(index, child, cx))
+            };
+
+            match result {
+                Poll::Pending => {}
+                #[cfg(feature = "futures-rs")]
+                Poll::Ready(Ready::Item(item)) => {
+                    cx.waker().wake_by_ref();
+                    shared.wake(index);
+                    return Poll::Ready(Some(item));
+                }
+                Poll::Ready(Ready::Done(item)) => {
+                    let removed = slab.remove(index);
+                    debug_assert!(removed);
+
+                    if let Some(item) = item {
+                        cx.waker().wake_by_ref();
+                        return Poll::Ready(Some(item));
+                    }
+                }
+            }
+        }
+
+        if slab.is_empty() {
+            return shared.poll_empty(cx, persistent && !closed);
+        }
+
+        // We need to wake again to take care of the active set that was not
+        // swapped in.
+        if non_empty {
+            cx.waker().wake_by_ref();
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<T, S> LocalUnordered<T, S>
+where
+    S: Sentinel,
+{
+    // The shared data is deliberately not `Sync`, which keeps the collection
+    // on its own thread. The `Arc` is only there so that wakers which escape
+    // to other threads can be cloned and dropped safely.
+    #[allow(clippy::arc_with_non_send_sync)]
+    #[inline(always)]
+    fn new_internal() -> Self {
+        Self {
+            slab: PinSlab::new(),
+            shared: Arc::new(Shared::new()),
+            alternate: Woken::new(),
+            closed: false,
+            persistent: false,
+            wake_order: WakeOrder::Index,
+            _marker: marker::PhantomData,
+        }
+    }
+
+    /// Test if the collection of futures is empty.
+    pub fn is_empty(&self) -> bool {
+        self.slab.is_empty()
+    }
+
+    /// Push the given future or stream to [LocalUnordered] and return its task
+    /// index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the collection has been [closed][LocalUnordered::close].
+    pub fn push(&mut self, future: T) -> usize {
+        match self.try_push(future) {
+            Ok(index) => index,
+            Err(..) => panic!("cannot push to a closed unordered set"),
+        }
+    }
+
+    /// Try to push the given future or stream to [LocalUnordered] and return
+    /// its task index.
+    ///
+    /// If the collection has been [closed][LocalUnordered::close] the value is
+    /// handed back as an error.
+    pub fn try_push(&mut self, future: T) -> Result<usize, T> {
+        if self.closed {
+            return Err(future);
+        }
+
+        let index = self.slab.insert(future);
+
+        // Safety: We have exclusive access to the collection, which owns the
+        // shared data.
+        unsafe {
+            self.shared
+                .cells
+                .reserve(Arc::as_ptr(&self.shared), index, &Global);
+        }
+
+        self.alternate.set(index, &Global);
+
+        // A persistent collection might be parked waiting for more work, so we
+        // need to wake it up.
+        if self.persistent {
+            self.shared.wake_parent();
+        }
+
+        Ok(index)
+    }
+
+    /// Close the collection.
+    ///
+    /// See [Unordered::close][crate::Unordered::close].
+    pub fn close(&mut self) {
+        self.closed = true;
+
+        if self.persistent {
+            self.shared.wake_parent();
+        }
+    }
+
+    /// Test if the collection has been [closed][LocalUnordered::close].
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Configure whether the collection is persistent or not.
+    ///
+    /// See [Unordered::set_persistent][crate::Unordered::set_persistent].
+    pub fn set_persistent(&mut self, persistent: bool) {
+        self.persistent = persistent;
+    }
+
+    /// Test if the collection is persistent.
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /// Configure how the storage for child tasks grows.
+    ///
+    /// See [Unordered::set_slot_growth][crate::Unordered::set_slot_growth].
+    pub fn set_slot_growth(&mut self, growth: Growth) {
+        self.slab.set_growth(growth);
+    }
+
+    /// Get the currently configured slot growth policy.
+    pub fn slot_growth(&self) -> Growth {
+        self.slab.growth()
+    }
+
+    /// Configure the order in which the indexes of removed or completed child
+    /// tasks are reused.
+    ///
+    /// See [Unordered::set_key_reuse][crate::Unordered::set_key_reuse].
+    pub fn set_key_reuse(&mut self, reuse: Reuse) {
+        self.slab.set_reuse(reuse);
+    }
+
+    /// Get the currently configured index reuse policy.
+    pub fn key_reuse(&self) -> Reuse {
+        self.slab.reuse()
+    }
+
+    /// Configure the order in which woken child tasks are polled.
+    ///
+    /// See [Unordered::set_wake_order][crate::Unordered::set_wake_order].
+    pub fn set_wake_order(&mut self, order: WakeOrder) {
+        let capacity = match order {
+            WakeOrder::Index => 0,
+            WakeOrder::Fifo { capacity } => capacity,
+        };
+
+        self.alternate.set_order_capacity(capacity, &Global);
+        self.shared
+            .active
+            .borrow_mut()
+            .set_order_capacity(capacity, &Global);
+        self.wake_order = order;
+    }
+
+    /// Get the currently configured wake order.
+    pub fn wake_order(&self) -> WakeOrder {
+        self.wake_order
+    }
+
+    /// List the indexes of child tasks which have been woken but not yet
+    /// polled, in ascending order.
+    ///
+    /// See [Unordered::pending_wakes][crate::Unordered::pending_wakes].
+    pub fn pending_wakes(&self) -> Vec<usize> {
+        let mut woken = self.alternate.iter().collect::<Vec<_>>();
+        woken.extend(self.shared.active.borrow().iter());
+        woken.extend(self.shared.remote().woken.iter().copied());
+        woken.sort_unstable();
+        woken.dedup();
+        woken.retain(|index| self.slab.contains(*index));
+        woken
+    }
+
+    /// Test if the child task at the given index has been woken but not yet
+    /// polled.
+    ///
+    /// See [Unordered::is_woken][crate::Unordered::is_woken].
+    pub fn is_woken(&self, index: usize) -> bool {
+        self.slab.contains(index)
+            && (self.alternate.test(index)
+                || self.shared.active.borrow().test(index)
+                || self.shared.remote().woken.contains(&index))
+    }
+
+    /// Remove the stream or future at the given index and return it.
+    ///
+    /// See [Unordered::try_remove][crate::Unordered::try_remove].
+    pub fn try_remove(&mut self, index: usize) -> Option<T>
+    where
+        T: Unpin,
+    {
+        self.slab.try_remove(index)
+    }
+
+    /// Remove the stream or future at the given index, calling the given
+    /// closure with it pinned before it's dropped.
+    ///
+    /// See [Unordered::remove_with][crate::Unordered::remove_with].
+    pub fn remove_with<F, R>(&mut self, index: usize, f: F) -> Option<R>
+    where
+        F: FnOnce(Pin<&mut T>) -> R,
+    {
+        self.slab.remove_with(index, f)
+    }
+
+    /// Move child tasks into the lowest free indexes, and release the storage
+    /// which is no longer needed.
+    ///
+    /// See [Unordered::compact][crate::Unordered::compact].
+    pub fn compact<F>(&mut self, mut f: F)
+    where
+        T: Unpin,
+        F: FnMut(usize, usize),
+    {
+        let mut moved = Vec::new();
+
+        self.slab.compact(|old, new| {
+            moved.push((old, new));
+            f(old, new);
+        });
+
+        // Safety: Cells are reserved for every child, and we have exclusive
+        // access to the collection.
+        unsafe {
+            self.shared.cells.compact(
+                Arc::as_ptr(&self.shared),
+                self.slab.len(),
+                &moved,
+                |_, _| (),
+                &Global,
+            );
+        }
+
+        if self.shared.compact(&mut self.alternate, &moved) {
+            self.shared.wake_parent();
+        }
+    }
+
+    /// Get a pinned mutable reference to the stream or future at the given
+    /// index.
+    pub fn get_pin_mut(&mut self, index: usize) -> Option<Pin<&mut T>> {
+        self.slab.get_pin_mut(index)
+    }
+
+    /// Get a mutable reference to the stream or future at the given index.
+    /// Requires that the stores stream or future is [Unpin].
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T>
+    where
+        T: Unpin,
+    {
+        self.slab.get_mut(index)
+    }
+}
+
+impl<T, S> Drop for LocalUnordered<T, S>
+where
+    S: Sentinel,
+{
+    fn drop(&mut self) {
+        // Safety: The wake sets of local collections are always allocated
+        // through the global allocator.
+        unsafe {
+            self.alternate.free(&Global);
+        }
+    }
+}
+
+impl<T> Default for LocalFuturesUnordered<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, S> iter::Extend<T> for LocalUnordered<T, S>
+where
+    S: Sentinel,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = T>,
+    {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T> iter::FromIterator<T> for LocalFuturesUnordered<T>
+where
+    T: Future,
+{
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut futures = LocalFuturesUnordered::new();
+        futures.extend(iter);
+        futures
+    }
+}
+
+#[cfg(feature = "futures-rs")]
+impl<T> LocalStreamsUnordered<T> {
+    /// Construct a new, empty [LocalStreamsUnordered].
+    pub fn new() -> Self {
+        Self::new_internal()
+    }
+}
+
+#[cfg(feature = "futures-rs")]
+impl<T> LocalIndexedStreamsUnordered<T> {
+    /// Construct a new, empty [LocalIndexedStreamsUnordered].
+    pub fn new() -> Self {
+        Self::new_internal()
+    }
+}
+
+/// Provide `Stream` implementation through `PollNext`.
+#[cfg(feature = "futures-rs")]
+impl<T, S> Stream for LocalUnordered<T, S>
+where
+    S: Sentinel,
+    Self: PollNext,
+{
+    type Item = <Self as PollNext>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        <Self as PollNext>::poll_next(self, cx)
+    }
+}
+
+#[cfg(feature = "futures-rs")]
+impl<T, S> FusedStream for LocalUnordered<T, S>
+where
+    S: Sentinel,
+    Self: PollNext,
+{
+    fn is_terminated(&self) -> bool {
+        self.closed && self.is_empty()
+    }
+}
+
+#[cfg(feature = "futures-rs")]
+impl<T> PollNext for LocalStreamsUnordered<T>
+where
+    T: Stream,
+{
+    type Item = T::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_children(cx, |_, stream, cx| {
+            stream.poll_next(cx).map(|item| match item {
+                Some(item) => Ready::Item(item),
+                None => Ready::Done(None),
+            })
+        })
+    }
+}
+
+#[cfg(feature = "futures-rs")]
+impl<T> PollNext for LocalIndexedStreamsUnordered<T>
+where
+    T: Stream,
+{
+    type Item = (usize, Option<T::Item>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_children(cx, |index, stream, cx| {
+            stream.poll_next(cx).map(|item| match item {
+                Some(item) => Ready::Item((index, Some(item))),
+                None => Ready::Done(Some((index, None))),
+            })
+        })
+    }
+}
+
+#[cfg(feature = "futures-rs")]
+impl<T> iter::FromIterator<T> for LocalStreamsUnordered<T>
+where
+    T: Stream,
+{
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut streams = LocalStreamsUnordered::new();
+        streams.extend(iter);
+        streams
+    }
+}
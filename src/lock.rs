@@ -1,144 +1,255 @@
-#[cfg(not(feature = "parking-lot"))]
-mod internals {
-    //! Manual implementation using atomics.
-    use std::sync::atomic::{AtomicIsize, Ordering};
-
-    /// A simplified RwLock implementation which only supports voluntary locking.
-    #[repr(C)]
-    pub struct RwLock {
-        state: AtomicIsize,
-    }
-
-    impl RwLock {
-        /// Construct a new lock that's in an unlocked state.
-        pub const fn new() -> Self {
-            Self {
-                state: AtomicIsize::new(0),
-            }
-        }
+//! Lock backends used to coordinate wakeups with an [Unordered] set.
+//!
+//! Which backend is used is picked through a type parameter of [Unordered],
+//! which defaults to [DefaultLock]. This means that a single
+//! binary can use different backends for different sets, regardless of which
+//! features happen to be enabled by other dependencies.
+//!
+//! The following backends are provided:
+//! * [AtomicLock] - a lock implemented using atomics, which only supports
+//!   voluntary locking.
+//! * Any raw reader-writer lock implementing [lock_api::RawRwLock], like
+//!   [parking_lot::RawRwLock]. This requires the `lock-api` feature.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use unicycle::FuturesUnordered;
+//! use unicycle::lock::AtomicLock;
+//!
+//! # #[tokio::main] async fn main() {
+//! let mut futures = FuturesUnordered::<_, AtomicLock>::with_lock();
+//!
+//! futures.push(async { 42 });
+//! assert_eq!(Some(42), futures.next().await);
+//! # }
+//! ```
+//!
+//! [Unordered]: crate::Unordered
+//! [lock_api::RawRwLock]: https://docs.rs/lock_api/latest/lock_api/trait.RawRwLock.html
+//! [parking_lot::RawRwLock]: https://docs.rs/parking_lot/latest/parking_lot/struct.RawRwLock.html
 
-        /// Construct a new lock that is already locked.
-        pub fn locked() -> Self {
-            Self {
-                state: AtomicIsize::new(-std::isize::MAX),
-            }
-        }
+use crate::sync::{AtomicIsize, Ordering};
 
-        /// Try to lock exclusively.
-        pub fn try_lock_exclusive_immediate(&self) -> bool {
-            let last = self.state.fetch_sub(std::isize::MAX, Ordering::AcqRel);
+/// The lock backend used by default.
+///
+/// This is `parking_lot::RawRwLock` if the `parking-lot` feature is enabled,
+/// and [AtomicLock] otherwise or when building under loom.
+#[cfg(all(feature = "parking-lot", not(loom)))]
+pub type DefaultLock = parking_lot::RawRwLock;
 
-            if last != 0 {
-                // try again later
-                self.state.fetch_add(std::isize::MAX, Ordering::AcqRel);
-                return false;
-            }
+/// The lock backend used by default.
+///
+/// This is `parking_lot::RawRwLock` if the `parking-lot` feature is enabled,
+/// and [AtomicLock] otherwise or when building under loom.
+#[cfg(any(not(feature = "parking-lot"), loom))]
+pub type DefaultLock = AtomicLock;
 
-            if last == std::isize::MIN {
-                // Sentinel value in case we observe a value that has wrapped
-                // around. This is such a abnormal state that there's not much
-                // we _can_ do. Abort the process.
-                std::process::abort();
-            }
+/// A raw reader-writer lock which can be used by an [Unordered] set.
+///
+/// Locks are only ever acquired through the non-blocking `try_*` methods, any
+/// waiting is done by the caller.
+///
+/// # Safety
+///
+/// Implementors must guarantee that while an exclusive lock is held, no other
+/// exclusive or shared lock can be acquired, and that any number of shared
+/// locks can be held at the same time otherwise.
+///
+/// [Unordered]: crate::Unordered
+pub unsafe trait RawLock: 'static + Send + Sync {
+    /// Construct a new lock that's in an unlocked state.
+    fn new() -> Self;
 
-            true
-        }
+    /// Construct a new lock that is already locked exclusively.
+    fn locked() -> Self;
+
+    /// Try to lock exclusively.
+    fn try_lock_exclusive(&self) -> bool;
+
+    /// Unlock exclusive access.
+    ///
+    /// # Safety
+    ///
+    /// This method may only be called if an exclusive lock is held in the
+    /// current context.
+    unsafe fn unlock_exclusive(&self);
+
+    /// Try to lock shared.
+    fn try_lock_shared(&self) -> bool;
 
-        /// Unlock shared access.
-        pub fn unlock_exclusive_immediate(&self) {
-            let old = self.state.fetch_add(std::isize::MAX, Ordering::AcqRel);
-            debug_assert!(old >= -std::isize::MAX && old < 0);
+    /// Unlock shared access.
+    ///
+    /// # Safety
+    ///
+    /// This method may only be called if a shared lock is held in the
+    /// current context.
+    unsafe fn unlock_shared(&self);
+}
+
+/// A simplified RwLock implementation using atomics which only supports
+/// voluntary locking.
+#[repr(C)]
+pub struct AtomicLock {
+    state: AtomicIsize,
+}
+
+unsafe impl RawLock for AtomicLock {
+    fn new() -> Self {
+        Self {
+            state: AtomicIsize::new(0),
         }
+    }
 
-        /// Try to lock shared.
-        pub fn try_lock_shared_immediate(&self) -> bool {
-            let existing = self.state.fetch_add(1, Ordering::AcqRel);
+    fn locked() -> Self {
+        Self {
+            state: AtomicIsize::new(-isize::MAX),
+        }
+    }
 
-            if existing < 0 {
-                self.state.fetch_sub(1, Ordering::AcqRel);
-                return false;
-            }
+    fn try_lock_exclusive(&self) -> bool {
+        let last = self.state.fetch_sub(isize::MAX, Ordering::AcqRel);
 
-            if existing == std::isize::MAX {
-                // Sentinel value in case we observe a value that has wrapped
-                // around. This is such a abnormal state that there's not much
-                // we _can_ do. Abort the process.
-                std::process::abort();
-            }
+        if last != 0 {
+            // try again later
+            self.state.fetch_add(isize::MAX, Ordering::AcqRel);
+            return false;
+        }
 
-            true
+        if last == isize::MIN {
+            // Sentinel value in case we observe a value that has wrapped
+            // around. This is such a abnormal state that there's not much
+            // we _can_ do. Abort the process.
+            abort();
         }
 
-        /// Unlock shared access.
-        pub fn unlock_shared_immediate(&self) {
+        true
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        let old = self.state.fetch_add(isize::MAX, Ordering::AcqRel);
+        debug_assert!((-isize::MAX..0).contains(&old));
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        let existing = self.state.fetch_add(1, Ordering::AcqRel);
+
+        if existing < 0 {
             self.state.fetch_sub(1, Ordering::AcqRel);
+            return false;
+        }
+
+        if existing == isize::MAX {
+            // Sentinel value in case we observe a value that has wrapped
+            // around. This is such a abnormal state that there's not much
+            // we _can_ do. Abort the process.
+            abort();
         }
+
+        true
+    }
+
+    unsafe fn unlock_shared(&self) {
+        self.state.fetch_sub(1, Ordering::AcqRel);
     }
 }
 
-#[cfg(feature = "parking-lot")]
-mod internals {
-    //! Implementation using raw locks from parking_lot.
-    use lock_api::RawRwLock as _;
+/// Implementation for raw locks from [lock_api], such as the ones provided by
+/// parking_lot.
+///
+/// [lock_api]: https://docs.rs/lock_api
+#[cfg(feature = "lock-api")]
+#[cfg_attr(docsrs, doc(cfg(feature = "lock-api")))]
+unsafe impl<L> RawLock for L
+where
+    L: 'static + lock_api::RawRwLock + Send + Sync,
+{
+    fn new() -> Self {
+        L::INIT
+    }
 
-    /// A simplified RwLock implementation which only supports voluntary locking.
-    pub struct RwLock {
-        state: parking_lot::RawRwLock,
+    fn locked() -> Self {
+        let lock = L::INIT;
+        lock.lock_exclusive();
+        lock
     }
 
-    impl RwLock {
-        /// Construct a new lock that's in an unlocked state.
-        pub(crate) const fn new() -> Self {
-            Self {
-                state: parking_lot::RawRwLock::INIT,
-            }
-        }
+    fn try_lock_exclusive(&self) -> bool {
+        lock_api::RawRwLock::try_lock_exclusive(self)
+    }
 
-        /// Construct a new lock that is already locked.
-        pub(crate) fn locked() -> Self {
-            let state = parking_lot::RawRwLock::INIT;
-            state.lock_exclusive();
+    unsafe fn unlock_exclusive(&self) {
+        lock_api::RawRwLock::unlock_exclusive(self)
+    }
 
-            Self { state }
-        }
+    fn try_lock_shared(&self) -> bool {
+        lock_api::RawRwLock::try_lock_shared(self)
+    }
 
-        /// Try to lock exclusively.
-        pub(crate) fn try_lock_exclusive_immediate(&self) -> bool {
-            self.state.try_lock_exclusive()
-        }
+    unsafe fn unlock_shared(&self) {
+        lock_api::RawRwLock::unlock_shared(self)
+    }
+}
 
-        /// Unlock shared access.
-        ///
-        /// # Safety
-        ///
-        /// This method may only be called if an exclusive lock is held in the
-        /// current context.
-        pub(crate) unsafe fn unlock_exclusive_immediate(&self) {
-            self.state.unlock_exclusive()
-        }
+/// Abort the process.
+#[cfg(feature = "std")]
+fn abort() -> ! {
+    std::process::abort()
+}
 
-        /// Try to lock shared.
-        pub(crate) fn try_lock_shared_immediate(&self) -> bool {
-            self.state.try_lock_shared()
-        }
+/// Abort without access to `std::process::abort` by panicking while already
+/// panicking.
+#[cfg(not(feature = "std"))]
+fn abort() -> ! {
+    struct Abort;
 
-        /// Unlock shared access.
-        ///
-        /// # Safety
-        ///
-        /// This method may only be called if a shared lock is held in the
-        /// current context.
-        pub(crate) unsafe fn unlock_shared_immediate(&self) {
-            self.state.unlock_shared()
+    impl Drop for Abort {
+        fn drop(&mut self) {
+            panic!("aborting");
         }
     }
+
+    let _abort = Abort;
+    panic!("aborting");
+}
+
+/// A reader-writer lock wrapping a [RawLock] backend.
+pub(crate) struct RwLock<L> {
+    raw: L,
 }
 
-pub use self::internals::RwLock;
+impl<L> RwLock<L>
+where
+    L: RawLock,
+{
+    /// Construct a new lock that's in an unlocked state.
+    pub(crate) fn new() -> Self {
+        Self { raw: L::new() }
+    }
+
+    /// Construct a new lock that is already locked.
+    pub(crate) fn locked() -> Self {
+        Self { raw: L::locked() }
+    }
+
+    /// Try to lock exclusively.
+    pub(crate) fn try_lock_exclusive_immediate(&self) -> bool {
+        self.raw.try_lock_exclusive()
+    }
+
+    /// Unlock exclusive access.
+    ///
+    /// # Safety
+    ///
+    /// This method may only be called if an exclusive lock is held in the
+    /// current context.
+    pub(crate) unsafe fn unlock_exclusive_immediate(&self) {
+        self.raw.unlock_exclusive()
+    }
 
-impl RwLock {
-    pub fn try_lock_exclusive_guard(&self) -> Option<LockExclusiveGuard<'_>> {
-        if self.try_lock_exclusive_immediate() {
+    /// Try to acquire an exclusive lock with a guard.
+    pub(crate) fn try_lock_exclusive_guard(&self) -> Option<LockExclusiveGuard<'_, L>> {
+        if self.raw.try_lock_exclusive() {
             Some(LockExclusiveGuard { lock: self })
         } else {
             None
@@ -146,8 +257,8 @@ impl RwLock {
     }
 
     /// Try to acquire a shared lock with a guard.
-    pub fn try_lock_shared(&self) -> Option<LockSharedGuard<'_>> {
-        if self.try_lock_shared_immediate() {
+    pub(crate) fn try_lock_shared(&self) -> Option<LockSharedGuard<'_, L>> {
+        if self.raw.try_lock_shared() {
             Some(LockSharedGuard { lock: self })
         } else {
             None
@@ -156,25 +267,37 @@ impl RwLock {
 }
 
 /// A lock guard for an exclusive lock.
-pub struct LockExclusiveGuard<'a> {
-    lock: &'a RwLock,
+pub(crate) struct LockExclusiveGuard<'a, L>
+where
+    L: RawLock,
+{
+    lock: &'a RwLock<L>,
 }
 
-impl Drop for LockExclusiveGuard<'_> {
+impl<L> Drop for LockExclusiveGuard<'_, L>
+where
+    L: RawLock,
+{
     fn drop(&mut self) {
         // SAFETY: lock is held by the guard correctly.
-        unsafe { self.lock.unlock_exclusive_immediate() }
+        unsafe { self.lock.raw.unlock_exclusive() }
     }
 }
 
 /// A lock guard for a shared lock.
-pub struct LockSharedGuard<'a> {
-    lock: &'a RwLock,
+pub(crate) struct LockSharedGuard<'a, L>
+where
+    L: RawLock,
+{
+    lock: &'a RwLock<L>,
 }
 
-impl Drop for LockSharedGuard<'_> {
+impl<L> Drop for LockSharedGuard<'_, L>
+where
+    L: RawLock,
+{
     fn drop(&mut self) {
         // SAFETY: lock is held by the guard correctly.
-        unsafe { self.lock.unlock_shared_immediate() }
+        unsafe { self.lock.raw.unlock_shared() }
     }
 }
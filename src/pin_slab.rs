@@ -1,5 +1,7 @@
 //! A slab-like, pre-allocated storage where the slab is divided into immovable
-//! slots. Each allocated slot doubles the capacity of the slab.
+//! slots. By default each allocated slot doubles the capacity of the slab,
+//! which can be configured through [Growth]. The order in which the keys of
+//! removed values are reused can be configured through [Reuse].
 //!
 //! Converted from <https://github.com/carllerche/slab>, this slab however
 //! contains a growable collection of fixed-size regions called slots.
@@ -19,30 +21,262 @@
 //! assert!(!slab.remove(index));
 //! ```
 
-use std::{mem, pin::Pin, ptr};
+use crate::allocator::{self, Allocator, Global, RawVec};
+use core::fmt;
+use core::iter::FusedIterator;
+use core::ops::{Index, IndexMut};
+use core::{mem, pin::Pin, ptr, slice};
 
-// Size of the first slot.
+// Size of the first slot in the default growth policy.
 const FIRST_SLOT_SIZE: usize = 16;
-// The initial number of bits to ignore for the first slot.
-const FIRST_SLOT_MASK: usize =
-    std::mem::size_of::<usize>() * 8 - FIRST_SLOT_SIZE.leading_zeros() as usize - 1;
+
+/// The policy used to size the slots of a [PinSlab].
+///
+/// Sizes are restricted to powers of two, which keeps finding the slot of a
+/// key a constant time operation regardless of policy.
+///
+/// # Examples
+///
+/// ```rust
+/// use unicycle::pin_slab::{Growth, PinSlab};
+///
+/// // Pages of 1024 entries each.
+/// let mut slab = PinSlab::with_growth(Growth::fixed(1024));
+/// assert_eq!(Growth::fixed(1024), slab.growth());
+///
+/// for n in 0..2048 {
+///     slab.insert(n);
+/// }
+///
+/// assert_eq!(2048, slab.capacity());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Growth {
+    // Base two logarithm of the size of the first slot.
+    first: u32,
+    // Base two logarithm of the growth factor, or zero if every slot has the
+    // same size.
+    factor: u32,
+}
+
+impl Growth {
+    /// Slots which start out with `first` entries, where each new slot doubles
+    /// the capacity of the slab.
+    ///
+    /// The default policy is `Growth::doubling(16)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `first` is not a power of two.
+    pub const fn doubling(first: usize) -> Self {
+        Self::geometric(first, 2)
+    }
+
+    /// Slots which start out with `first` entries, where each new slot
+    /// multiplies the capacity of the slab by `factor`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `first` is not a power of two, or if `factor` is not a power
+    /// of two greater than one.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::{Growth, PinSlab};
+    ///
+    /// let mut slab = PinSlab::with_growth(Growth::geometric(4, 4));
+    /// slab.insert(0);
+    /// assert_eq!(4, slab.capacity());
+    ///
+    /// // The second slot brings the capacity up to 16.
+    /// for n in 1..5 {
+    ///     slab.insert(n);
+    /// }
+    ///
+    /// assert_eq!(16, slab.capacity());
+    /// ```
+    pub const fn geometric(first: usize, factor: usize) -> Self {
+        assert!(
+            first.is_power_of_two(),
+            "first slot size must be a power of two"
+        );
+        assert!(
+            factor > 1 && factor.is_power_of_two(),
+            "growth factor must be a power of two greater than one"
+        );
+
+        Self {
+            first: first.trailing_zeros(),
+            factor: factor.trailing_zeros(),
+        }
+    }
+
+    /// Slots which all have `size` entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is not a power of two.
+    pub const fn fixed(size: usize) -> Self {
+        assert!(size.is_power_of_two(), "slot size must be a power of two");
+
+        Self {
+            first: size.trailing_zeros(),
+            factor: 0,
+        }
+    }
+
+    /// The number of entries in the first slot.
+    pub const fn first_slot_size(&self) -> usize {
+        1 << self.first
+    }
+
+    /// The number of entries in the given slot.
+    fn slot_size(&self, slot: usize) -> usize {
+        if self.factor == 0 || slot == 0 {
+            return self.first_slot_size();
+        }
+
+        self.slot_start(slot).saturating_mul((1 << self.factor) - 1)
+    }
+
+    /// The key of the first entry in the given slot.
+    fn slot_start(&self, slot: usize) -> usize {
+        if self.factor == 0 {
+            return slot << self.first;
+        }
+
+        match slot {
+            0 => 0,
+            // NB: saturates for slots which could never be allocated.
+            n => 1usize
+                .checked_shl(self.first + (n - 1) as u32 * self.factor)
+                .unwrap_or(usize::MAX),
+        }
+    }
+
+    /// Sizes of all slots, in order.
+    fn slot_sizes(self) -> impl Iterator<Item = usize> {
+        (0usize..).map(move |slot| self.slot_size(slot))
+    }
+
+    /// Calculate the key as a (slot, offset, len) tuple.
+    fn calculate_key(&self, key: usize) -> (usize, usize, usize) {
+        assert!(key < (1usize << (mem::size_of::<usize>() * 8 - 1)));
+
+        if self.factor == 0 {
+            let len = self.first_slot_size();
+            return (key >> self.first, key & (len - 1), len);
+        }
+
+        let slot = match key >> self.first {
+            0 => 0,
+            // The base two logarithm of the number of first slots which fit
+            // below the key, divided by the logarithm of the growth factor.
+            n => ((usize::BITS - 1 - n.leading_zeros()) / self.factor) as usize + 1,
+        };
+
+        let start = self.slot_start(slot);
+        (slot, key - start, self.slot_size(slot))
+    }
+}
+
+impl Default for Growth {
+    fn default() -> Self {
+        Self::doubling(FIRST_SLOT_SIZE)
+    }
+}
+
+/// The order in which the keys of removed values are reused by a [PinSlab].
+///
+/// # Examples
+///
+/// ```rust
+/// use unicycle::pin_slab::{PinSlab, Reuse};
+///
+/// let mut slab = PinSlab::new();
+///
+/// for n in 0..4 {
+///     slab.insert(n);
+/// }
+///
+/// assert!(slab.remove(2));
+/// assert!(slab.remove(1));
+///
+/// // The most recently freed key is reused first by default.
+/// assert_eq!(Reuse::Lifo, slab.reuse());
+/// assert_eq!(1, slab.insert(10));
+/// assert!(slab.remove(1));
+///
+/// slab.set_reuse(Reuse::Lowest);
+/// assert_eq!(1, slab.insert(10));
+/// assert_eq!(2, slab.insert(20));
+/// assert_eq!(4, slab.insert(40));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Reuse {
+    /// Reuse the most recently freed key first. This is cheap and
+    /// cache-friendly, since the entry of a freed key was recently accessed.
+    /// This is the default.
+    #[default]
+    Lifo,
+    /// Reuse the least recently freed key first. This maximizes the time before
+    /// a key is reused, which makes it less likely that a stale key is confused
+    /// with a new one.
+    Fifo,
+    /// Reuse the lowest free key first. This keeps values dense at the start
+    /// of the slab, at the cost of a logarithmic number of operations to
+    /// remove and insert values.
+    Lowest,
+}
 
 /// Pre-allocated storage for a uniform data type, with slots of immovable
 /// memory regions.
-#[derive(Clone)]
-pub struct PinSlab<T> {
+///
+/// With the `allocator-api2` feature, the slots and the table which keeps track
+/// of them are allocated through a custom allocator `A`, see
+/// [PinSlab::new_in].
+pub struct PinSlab<T, A = Global>
+where
+    A: Allocator,
+{
     // Slots of memory. Once one has been allocated it is never moved.
     // This allows us to store entries in there and fetch them as `Pin<&mut T>`.
-    slots: Vec<ptr::NonNull<Entry<T>>>,
+    slots: RawVec<ptr::NonNull<Entry<T>>>,
     // Number of Filled elements currently in the slab
     len: usize,
     // Offset of the next available slot in the slab.
     next: usize,
+    // The last key in the list of vacant entries, used to append to it when
+    // keys are reused in FIFO order.
+    tail: Option<usize>,
+    // Vacant keys when they're reused lowest first, in which case the list of
+    // vacant entries isn't used.
+    vacant: VacantHeap,
+    // The order in which keys are reused.
+    reuse: Reuse,
+    // How slots are sized.
+    growth: Growth,
+    // The allocator used for slots.
+    alloc: A,
+}
+
+unsafe impl<T, A> Send for PinSlab<T, A>
+where
+    T: Send,
+    A: Allocator + Send,
+{
 }
 
-unsafe impl<T> Send for PinSlab<T> where T: Send {}
-unsafe impl<T> Sync for PinSlab<T> where T: Sync {}
+unsafe impl<T, A> Sync for PinSlab<T, A>
+where
+    T: Sync,
+    A: Allocator + Sync,
+{
+}
 
+#[derive(Clone)]
 enum Entry<T> {
     // Each slot is pre-allocated with entries of `None`.
     None,
@@ -69,11 +303,172 @@ impl<T> PinSlab<T> {
     /// assert!(!slab.remove(index));
     /// ```
     pub fn new() -> Self {
+        Self::with_growth(Growth::default())
+    }
+
+    /// Construct a new, empty [PinSlab] which sizes its slots according to the
+    /// given [Growth] policy.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::{Growth, PinSlab};
+    ///
+    /// let mut slab = PinSlab::with_growth(Growth::doubling(4));
+    /// slab.insert(42);
+    /// assert_eq!(4, slab.capacity());
+    /// ```
+    pub fn with_growth(growth: Growth) -> Self {
+        Self::with_growth_and_alloc(growth, Global)
+    }
+}
+
+impl<T, A> PinSlab<T, A>
+where
+    A: Allocator,
+{
+    /// Construct a new, empty [PinSlab] which allocates its slots through the
+    /// given allocator.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use allocator_api2::alloc::Global;
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new_in(Global);
+    /// let key = slab.insert(42);
+    /// assert_eq!(Some(&42), slab.get(key));
+    /// ```
+    #[cfg(feature = "allocator-api2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "allocator-api2")))]
+    pub fn new_in(alloc: A) -> Self {
+        Self::with_growth_and_alloc(Growth::default(), alloc)
+    }
+
+    /// Construct a new, empty [PinSlab] which sizes its slots according to the
+    /// given [Growth] policy, and allocates them through the given allocator.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use allocator_api2::alloc::Global;
+    /// use unicycle::pin_slab::{Growth, PinSlab};
+    ///
+    /// let mut slab = PinSlab::with_growth_in(Growth::fixed(4), Global);
+    /// slab.insert(42);
+    /// assert_eq!(4, slab.capacity());
+    /// ```
+    #[cfg(feature = "allocator-api2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "allocator-api2")))]
+    pub fn with_growth_in(growth: Growth, alloc: A) -> Self {
+        Self::with_growth_and_alloc(growth, alloc)
+    }
+
+    pub(crate) fn with_growth_and_alloc(growth: Growth, alloc: A) -> Self {
         Self {
-            slots: Vec::new(),
+            slots: RawVec::new(),
             next: 0,
+            tail: None,
+            vacant: VacantHeap::new(),
+            reuse: Reuse::Lifo,
             len: 0,
+            growth,
+            alloc,
+        }
+    }
+
+    /// Get a reference to the allocator of the slab.
+    #[cfg(feature = "allocator-api2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "allocator-api2")))]
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Get the [Growth] policy of the slab.
+    pub fn growth(&self) -> Growth {
+        self.growth
+    }
+
+    /// Change the [Growth] policy of the slab.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the slab has allocated any slots, which happens the first
+    /// time a value is inserted and lasts until it's [cleared][PinSlab::clear].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::{Growth, PinSlab};
+    ///
+    /// let mut slab = PinSlab::new();
+    /// slab.set_growth(Growth::fixed(4));
+    /// slab.insert(42);
+    /// assert_eq!(4, slab.capacity());
+    /// ```
+    pub fn set_growth(&mut self, growth: Growth) {
+        assert!(
+            self.slots.is_empty(),
+            "growth policy can't be changed once slots have been allocated"
+        );
+
+        self.growth = growth;
+    }
+
+    /// Get the number of entries the slab can hold without allocating another
+    /// slot.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    /// assert_eq!(0, slab.capacity());
+    /// slab.insert(42);
+    /// assert_eq!(16, slab.capacity());
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.growth.slot_start(self.slots.len())
+    }
+
+    /// Get the order in which the keys of removed values are reused.
+    pub fn reuse(&self) -> Reuse {
+        self.reuse
+    }
+
+    /// Change the order in which the keys of removed values are reused, see
+    /// [Reuse].
+    ///
+    /// If the policy changes, keys which are already free are reused in
+    /// ascending order under the new policy, which requires visiting every
+    /// entry in the slab.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::{PinSlab, Reuse};
+    ///
+    /// let mut slab = PinSlab::new();
+    /// slab.set_reuse(Reuse::Fifo);
+    ///
+    /// for n in 0..4 {
+    ///     slab.insert(n);
+    /// }
+    ///
+    /// assert!(slab.remove(2));
+    /// assert!(slab.remove(1));
+    /// assert_eq!(2, slab.insert(20));
+    /// assert_eq!(1, slab.insert(10));
+    /// ```
+    pub fn set_reuse(&mut self, reuse: Reuse) {
+        if self.reuse == reuse {
+            return;
         }
+
+        self.reuse = reuse;
+        self.rebuild_vacant();
     }
 
     /// Get the length of the slab.
@@ -137,7 +532,7 @@ impl<T> PinSlab<T> {
     /// let key = slab.insert(42);
     /// assert_eq!(Some(&42), slab.get(key));
     /// ```
-    pub fn get(&mut self, key: usize) -> Option<&T> {
+    pub fn get(&self, key: usize) -> Option<&T> {
         // Safety: We only use this to acquire an immutable reference.
         // The internal calculation guarantees that the key is in bounds.
         unsafe { self.internal_get(key) }
@@ -164,10 +559,28 @@ impl<T> PinSlab<T> {
         unsafe { self.internal_get_mut(key) }
     }
 
+    /// Test if the slab contains a value at the given key.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    /// let key = slab.insert(42);
+    /// assert!(slab.contains(key));
+    /// assert!(slab.remove(key));
+    /// assert!(!slab.contains(key));
+    /// ```
+    pub fn contains(&self, key: usize) -> bool {
+        // Safety: We only use this to test the entry.
+        unsafe { self.internal_get(key).is_some() }
+    }
+
     /// Get a mutable reference to the value at the given slot.
     #[inline(always)]
     unsafe fn internal_get_mut(&mut self, key: usize) -> Option<&mut T> {
-        let (slot, offset, len) = calculate_key(key);
+        let (slot, offset, len) = self.growth.calculate_key(key);
         let slot = *self.slots.get_mut(slot)?;
 
         // Safety: all slots are fully allocated and initialized in `new_slot`.
@@ -185,8 +598,8 @@ impl<T> PinSlab<T> {
 
     /// Get a reference to the value at the given slot.
     #[inline(always)]
-    unsafe fn internal_get(&mut self, key: usize) -> Option<&T> {
-        let (slot, offset, len) = calculate_key(key);
+    unsafe fn internal_get(&self, key: usize) -> Option<&T> {
+        let (slot, offset, len) = self.growth.calculate_key(key);
         let slot = *self.slots.get(slot)?;
 
         // Safety: all slots are fully allocated and initialized in `new_slot`.
@@ -224,32 +637,91 @@ impl<T> PinSlab<T> {
     /// assert!(!slab.remove(index));
     /// ```
     pub fn remove(&mut self, key: usize) -> bool {
-        let (slot, offset, len) = calculate_key(key);
+        self.remove_with(key, |_| ()).is_some()
+    }
 
-        let slot = match self.slots.get_mut(slot) {
-            Some(slot) => *slot,
-            None => return false,
-        };
+    /// Remove the key from the slab, returning its value.
+    ///
+    /// Returns `None` if there is no value associated with the key. Since this
+    /// moves the value out of the slab, it requires that it is [Unpin].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    ///
+    /// assert_eq!(None, slab.try_remove(0));
+    /// let index = slab.insert(String::from("hello"));
+    /// assert_eq!(Some(String::from("hello")), slab.try_remove(index));
+    /// assert_eq!(None, slab.try_remove(index));
+    /// ```
+    pub fn try_remove(&mut self, key: usize) -> Option<T>
+    where
+        T: Unpin,
+    {
+        let entry = self.occupied(key)?;
 
-        // Safety: all slots are fully allocated and initialized in `new_slot`.
-        // As long as we have access to it, we know that we will only find
-        // initialized entries assuming offset < len.
-        debug_assert!(offset < len);
-        unsafe {
-            let entry = slot.as_ptr().add(offset);
+        let vacant = self.release(key);
 
-            match &*entry {
-                Entry::Occupied(..) => (),
-                _ => return false,
-            }
+        // Safety: the entry is occupied, and since the value is `Unpin` it's
+        // fine to move it out.
+        let entry = unsafe { ptr::replace(entry, vacant) };
+        self.len -= 1;
+
+        match entry {
+            Entry::Occupied(value) => Some(value),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Remove the key from the slab, calling the given closure with the pinned
+    /// value before it's dropped in place.
+    ///
+    /// Returns the output of the closure, or `None` if there is no value
+    /// associated with the key. If the closure panics, the value is left in the
+    /// slab.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use tokio::time::{sleep_until, Instant};
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut slab = PinSlab::new();
+    ///
+    ///     let deadline = Instant::now() + Duration::from_secs(1);
+    ///     let index = slab.insert(sleep_until(deadline));
+    ///
+    ///     assert_eq!(Some(deadline), slab.remove_with(index, |sleep| sleep.deadline()));
+    ///     assert_eq!(None, slab.remove_with(index, |sleep| sleep.deadline()));
+    /// }
+    /// ```
+    pub fn remove_with<F, R>(&mut self, key: usize, f: F) -> Option<R>
+    where
+        F: FnOnce(Pin<&mut T>) -> R,
+    {
+        let entry = self.occupied(key)?;
+
+        // Safety: the entry is occupied, and the value is dropped in place
+        // once the closure has been called so it's never moved.
+        unsafe {
+            let output = match &mut *entry {
+                Entry::Occupied(value) => f(Pin::new_unchecked(value)),
+                _ => unreachable!(),
+            };
 
             ptr::drop_in_place(entry);
-            ptr::write(entry, Entry::Vacant(self.next));
+            let vacant = self.release(key);
+            ptr::write(entry, vacant);
             self.len -= 1;
-            self.next = key;
+            Some(output)
         }
-
-        true
     }
 
     /// Clear all available data in the PinSlot.
@@ -265,106 +737,1064 @@ impl<T> PinSlab<T> {
     /// assert!(slab.get(0).is_none());
     /// ```
     pub fn clear(&mut self) {
-        for (len, entry) in slot_sizes().zip(self.slots.iter_mut()) {
-            // reconstruct the vector for the slot.
-            drop(unsafe { Vec::from_raw_parts(entry.as_ptr(), len, len) });
-        }
-
-        unsafe {
-            self.slots.set_len(0);
+        for (len, entry) in self.growth.slot_sizes().zip(self.slots.iter_mut()) {
+            // Safety: the slot was allocated in `new_slot` with this length.
+            unsafe { allocator::drop_slice(*entry, len, &self.alloc) };
         }
-    }
 
-    /// Construct a new slot.
-    fn new_slot(&self, len: usize) -> ptr::NonNull<Entry<T>> {
-        let mut d = Vec::with_capacity(len);
+        self.slots.truncate(0);
 
-        for _ in 0..len {
-            d.push(Entry::None);
-        }
+        self.len = 0;
+        self.next = 0;
+        self.tail = None;
+        self.vacant.clear();
+    }
 
-        let ptr = d.as_mut_ptr();
-        mem::forget(d);
+    /// Move values into the lowest free keys, so that they occupy the keys
+    /// `0..len`, and free the slots which are no longer needed.
+    ///
+    /// Once the slab has been compacted, `f` is called with the old and the
+    /// new key of every value which was moved. Since this moves values around
+    /// it requires that they are [Unpin].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::{Growth, PinSlab};
+    ///
+    /// let mut slab = PinSlab::with_growth(Growth::fixed(4));
+    ///
+    /// for n in 0..12 {
+    ///     slab.insert(n);
+    /// }
+    ///
+    /// for key in (0..10).filter(|key| *key != 3) {
+    ///     assert!(slab.remove(key));
+    /// }
+    ///
+    /// assert_eq!(12, slab.capacity());
+    ///
+    /// let mut moved = Vec::new();
+    /// slab.compact(|old, new| moved.push((old, new)));
+    ///
+    /// assert_eq!(vec![(11, 0), (10, 1), (3, 2)], moved);
+    /// assert_eq!(4, slab.capacity());
+    /// assert_eq!(vec![(0, &11), (1, &10), (2, &3)], slab.iter().collect::<Vec<_>>());
+    /// assert_eq!(3, slab.insert(12));
+    /// ```
+    pub fn compact<F>(&mut self, mut f: F)
+    where
+        T: Unpin,
+        F: FnMut(usize, usize),
+    {
+        let mut moved = RawVec::new();
+        let mut low = 0;
+        let mut high = self.capacity();
 
-        // Safety: We just initialized the pointer to be non-null above.
-        unsafe { ptr::NonNull::new_unchecked(ptr) }
-    }
+        loop {
+            while low < high && self.occupied(low).is_some() {
+                low += 1;
+            }
 
-    /// Insert a value at the given slot.
-    fn insert_at(&mut self, key: usize, val: T) {
-        let (slot, offset, len) = calculate_key(key);
+            while high > low && self.occupied(high - 1).is_none() {
+                high -= 1;
+            }
 
-        if let Some(slot) = self.slots.get_mut(slot) {
-            // Safety: all slots are fully allocated and initialized in
-            // `new_slot`. As long as we have access to it, we know that we will
-            // only find initialized entries assuming offset < slot_size.
-            // We also know it's safe to have unique access to _any_ slots,
-            // since we have unique access to the slab in this function.
-            debug_assert!(offset < len);
-            let entry = unsafe { &mut *slot.as_ptr().add(offset) };
+            if low == high {
+                break;
+            }
 
-            self.next = match *entry {
-                Entry::None => key + 1,
-                Entry::Vacant(next) => next,
-                // NB: unreachable because insert_at is an internal function,
-                // which can only be appropriately called on non-occupied
-                // entries. This is however, not a safety concern.
-                _ => unreachable!(),
-            };
+            high -= 1;
 
-            *entry = Entry::Occupied(val);
-        } else {
+            // Safety: `high` is occupied and `low` is a distinct key which is
+            // not, and since values are `Unpin` they can be moved.
             unsafe {
-                let slot = self.new_slot(len);
-                *slot.as_ptr() = Entry::Occupied(val);
-                self.slots.push(slot);
-                self.next = key + 1;
+                let from = self.entry(high).expect("high key is allocated");
+                let to = self.entry(low).expect("low key is allocated");
+                ptr::swap(from, to);
             }
+
+            moved.push((high, low), &self.alloc);
+            low += 1;
         }
 
-        self.len += 1;
-    }
-}
+        let mut keep = 0;
 
-impl<T> Default for PinSlab<T> {
-    fn default() -> Self {
-        Self::new()
-    }
-}
+        while keep < self.slots.len() && self.growth.slot_start(keep) < self.len {
+            keep += 1;
+        }
 
-impl<T> Drop for PinSlab<T> {
-    fn drop(&mut self) {
-        self.clear();
-    }
-}
+        for (n, slot) in self.slots[keep..].iter().enumerate() {
+            // Safety: the slot was allocated in `new_slot` with this length,
+            // and only contains vacant entries.
+            unsafe { allocator::drop_slice(*slot, self.growth.slot_size(keep + n), &self.alloc) };
+        }
 
-/// Calculate the key as a (slot, offset, len) tuple.
-fn calculate_key(key: usize) -> (usize, usize, usize) {
-    assert!(key < (1usize << (mem::size_of::<usize>() * 8 - 1)));
+        self.slots.truncate(keep);
 
-    let slot = ((mem::size_of::<usize>() * 8) as usize - key.leading_zeros() as usize)
-        .saturating_sub(FIRST_SLOT_MASK);
+        // NB: every remaining key past the last value is marked as unused, so
+        // there are no free keys left to keep track of.
+        for key in self.len..self.capacity() {
+            // Safety: the key is within an allocated slot.
+            unsafe {
+                *self.entry(key).expect("key is allocated") = Entry::None;
+            }
+        }
 
-    let (start, end) = if key < FIRST_SLOT_SIZE {
-        (0, FIRST_SLOT_SIZE)
-    } else {
-        (FIRST_SLOT_SIZE << (slot - 1), FIRST_SLOT_SIZE << slot)
-    };
+        self.next = self.len;
+        self.tail = None;
+        self.vacant.clear();
 
-    (slot, key - start, end - start)
-}
+        for &(old, new) in moved.iter() {
+            f(old, new);
+        }
 
-fn slot_sizes() -> impl Iterator<Item = usize> {
-    (0usize..).map(|n| match n {
-        0 | 1 => FIRST_SLOT_SIZE,
-        n => FIRST_SLOT_SIZE << (n - 1),
-    })
-}
+        // Safety: the moves were only ever pushed with our allocator.
+        unsafe { moved.free(&self.alloc) };
+    }
+
+    /// Get the entry for the key which the next value will be inserted at.
+    ///
+    /// This allows the key to be known before the value is constructed, for
+    /// values which need to know their own key.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    /// slab.insert((0, "first"));
+    ///
+    /// let entry = slab.vacant_entry();
+    /// let key = entry.key();
+    /// entry.insert((key, "second"));
+    ///
+    /// assert_eq!(Some(&(1, "second")), slab.get(key));
+    /// ```
+    pub fn vacant_entry(&mut self) -> VacantEntry<'_, T, A> {
+        VacantEntry {
+            key: self.next,
+            slab: self,
+        }
+    }
+
+    /// Iterate over the keys of all occupied entries in the slab, in
+    /// ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    /// let a = slab.insert("a");
+    /// let b = slab.insert("b");
+    /// let c = slab.insert("c");
+    /// assert!(slab.remove(b));
+    ///
+    /// assert_eq!(vec![a, c], slab.keys().collect::<Vec<_>>());
+    /// ```
+    pub fn keys(&self) -> Keys<'_, T> {
+        Keys {
+            raw: RawIter::new(self),
+        }
+    }
+
+    /// Iterate over all occupied entries in the slab as `(key, value)` pairs,
+    /// in ascending order of keys.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    /// let a = slab.insert("a");
+    /// let b = slab.insert("b");
+    ///
+    /// assert_eq!(vec![(a, &"a"), (b, &"b")], slab.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            raw: RawIter::new(self),
+        }
+    }
+
+    /// Iterate mutably over all occupied entries in the slab as `(key, value)`
+    /// pairs, in ascending order of keys.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    /// let a = slab.insert(1);
+    /// let b = slab.insert(2);
+    ///
+    /// for (_, value) in slab.iter_mut() {
+    ///     *value *= 10;
+    /// }
+    ///
+    /// assert_eq!(vec![(a, &10), (b, &20)], slab.iter().collect::<Vec<_>>());
+    /// ```
+    pub fn iter_mut(&mut self) -> IterMut<'_, T>
+    where
+        T: Unpin,
+    {
+        IterMut {
+            raw: RawIter::new(self),
+        }
+    }
+
+    /// Iterate over all occupied entries in the slab as `(key, value)` pairs,
+    /// where each value is pinned, in ascending order of keys.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::future::Future;
+    /// use std::task::{Context, Poll};
+    ///
+    /// use futures::task::noop_waker;
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// async fn value(n: u32) -> u32 {
+    ///     n
+    /// }
+    ///
+    /// let mut slab = PinSlab::new();
+    /// slab.insert(value(1));
+    /// slab.insert(value(2));
+    ///
+    /// let waker = noop_waker();
+    /// let mut cx = Context::from_waker(&waker);
+    ///
+    /// let mut results = Vec::new();
+    ///
+    /// for (key, future) in slab.iter_pin_mut() {
+    ///     if let Poll::Ready(value) = future.poll(&mut cx) {
+    ///         results.push((key, value));
+    ///     }
+    /// }
+    ///
+    /// assert_eq!(vec![(0, 1), (1, 2)], results);
+    /// ```
+    pub fn iter_pin_mut(&mut self) -> IterPinMut<'_, T> {
+        IterPinMut {
+            raw: RawIter::new(self),
+        }
+    }
+
+    /// Get a pointer to the entry at the given key, if its slot has been
+    /// allocated.
+    fn entry(&self, key: usize) -> Option<*mut Entry<T>> {
+        let (slot, offset, len) = self.growth.calculate_key(key);
+        let slot = *self.slots.get(slot)?;
+
+        // Safety: all slots are fully allocated and initialized in `new_slot`.
+        // As long as we have access to it, we know that we will only find
+        // initialized entries assuming offset < len.
+        debug_assert!(offset < len);
+        Some(unsafe { slot.as_ptr().add(offset) })
+    }
+
+    /// Get a pointer to the entry at the given key, if it's occupied.
+    fn occupied(&mut self, key: usize) -> Option<*mut Entry<T>> {
+        let entry = self.entry(key)?;
+
+        // Safety: we have exclusive access to the slab.
+        match unsafe { &*entry } {
+            Entry::Occupied(..) => Some(entry),
+            _ => None,
+        }
+    }
+
+    /// Release a key whose value is being removed, returning the vacant entry
+    /// which should replace it.
+    fn release(&mut self, key: usize) -> Entry<T> {
+        match self.reuse {
+            Reuse::Lifo => {
+                let vacant = Entry::Vacant(self.next);
+                self.next = key;
+                vacant
+            }
+            Reuse::Fifo => {
+                let vacant = match self.tail {
+                    Some(tail) => {
+                        // Safety: the tail is always a vacant entry, and we
+                        // have exclusive access to the slab.
+                        let tail = unsafe { &mut *self.entry(tail).expect("tail is allocated") };
+                        mem::replace(tail, Entry::Vacant(key))
+                    }
+                    None => {
+                        let vacant = Entry::Vacant(self.next);
+                        self.next = key;
+                        vacant
+                    }
+                };
+
+                self.tail = Some(key);
+                vacant
+            }
+            Reuse::Lowest => {
+                self.vacant.push(key, &self.alloc);
+                self.next = self.next.min(key);
+                // NB: the list of vacant entries isn't used.
+                Entry::Vacant(key)
+            }
+        }
+    }
+
+    /// Rebuild the bookkeeping of vacant entries for the current reuse policy,
+    /// with free keys ordered in ascending order.
+    fn rebuild_vacant(&mut self) {
+        let mut keys = RawVec::new();
+        let mut end = self.capacity();
+
+        'outer: for (n, slot) in self.slots.iter().enumerate() {
+            let start = self.growth.slot_start(n);
+
+            for offset in 0..self.growth.slot_size(n) {
+                // Safety: all slots are fully allocated and initialized in
+                // `new_slot`, and we only read entries within its length.
+                match unsafe { &*slot.as_ptr().add(offset) } {
+                    Entry::None => {
+                        end = start + offset;
+                        break 'outer;
+                    }
+                    Entry::Vacant(..) => keys.push(start + offset, &self.alloc),
+                    Entry::Occupied(..) => {}
+                }
+            }
+        }
+
+        self.next = keys.first().copied().unwrap_or(end);
+        self.tail = None;
+        self.vacant.clear();
+
+        match self.reuse {
+            Reuse::Lifo | Reuse::Fifo => {
+                let links = keys.iter().skip(1).copied().chain([end]);
+
+                for (key, link) in keys.iter().zip(links) {
+                    // Safety: the key was found among the allocated entries.
+                    unsafe {
+                        *self.entry(*key).expect("key is allocated") = Entry::Vacant(link);
+                    }
+                }
+
+                if self.reuse == Reuse::Fifo {
+                    self.tail = keys.last().copied();
+                }
+            }
+            Reuse::Lowest => {
+                // NB: keys in ascending order already form a min-heap.
+                mem::swap(&mut self.vacant.keys, &mut keys);
+            }
+        }
+
+        // Safety: the keys were only ever pushed with our allocator.
+        unsafe { keys.free(&self.alloc) };
+    }
+
+    /// Construct a new slot.
+    fn new_slot(&self, len: usize) -> ptr::NonNull<Entry<T>> {
+        allocator::new_slice(len, |_| Entry::None, &self.alloc)
+    }
+
+    /// Insert a value at the given slot.
+    fn insert_at(&mut self, key: usize, val: T) {
+        let (slot, offset, len) = self.growth.calculate_key(key);
+
+        if let Some(slot) = self.slots.get_mut(slot) {
+            // Safety: all slots are fully allocated and initialized in
+            // `new_slot`. As long as we have access to it, we know that we will
+            // only find initialized entries assuming offset < slot_size.
+            // We also know it's safe to have unique access to _any_ slots,
+            // since we have unique access to the slab in this function.
+            debug_assert!(offset < len);
+            let entry = unsafe { &mut *slot.as_ptr().add(offset) };
+
+            let link = match *entry {
+                Entry::None => None,
+                Entry::Vacant(next) => Some(next),
+                // NB: unreachable because insert_at is an internal function,
+                // which can only be appropriately called on non-occupied
+                // entries. This is however, not a safety concern.
+                _ => unreachable!(),
+            };
+
+            *entry = Entry::Occupied(val);
+            self.len += 1;
+            self.advance(key, link);
+        } else {
+            unsafe {
+                let slot = self.new_slot(len);
+                *slot.as_ptr() = Entry::Occupied(val);
+                self.slots.push(slot, &self.alloc);
+            }
+
+            self.len += 1;
+            self.advance(key, None);
+        }
+    }
+
+    /// Advance to the next key to insert at, after inserting at `key` which
+    /// was either vacant with the given link or had never been used.
+    fn advance(&mut self, key: usize, link: Option<usize>) {
+        self.next = match self.reuse {
+            Reuse::Lifo => link.unwrap_or(key + 1),
+            Reuse::Fifo => {
+                if self.tail == Some(key) {
+                    self.tail = None;
+                }
+
+                link.unwrap_or(key + 1)
+            }
+            Reuse::Lowest => {
+                if link.is_some() {
+                    self.vacant.pop();
+                }
+
+                match self.vacant.peek() {
+                    Some(key) => key,
+                    // NB: every key before the first unused one is either
+                    // occupied or vacant.
+                    None => self.len + self.vacant.len(),
+                }
+            }
+        };
+    }
+}
+
+impl<T, A> Clone for PinSlab<T, A>
+where
+    T: Clone,
+    A: Allocator + Clone,
+{
+    /// Clone the slab and all of its values.
+    ///
+    /// The values of the clone are stored in newly allocated slots, so every
+    /// key refers to the same value in both slabs.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    /// let key = slab.insert(String::from("hello"));
+    ///
+    /// let mut copy = slab.clone();
+    /// assert!(copy.remove(key));
+    /// assert_eq!(Some(&String::from("hello")), slab.get(key));
+    /// ```
+    fn clone(&self) -> Self {
+        let mut slab = Self {
+            slots: RawVec::new(),
+            len: self.len,
+            next: self.next,
+            tail: self.tail,
+            vacant: self.vacant.clone_in(&self.alloc),
+            reuse: self.reuse,
+            growth: self.growth,
+            alloc: self.alloc.clone(),
+        };
+
+        for (slot, len) in self.slots.iter().zip(self.growth.slot_sizes()) {
+            // Safety: all slots are fully allocated and initialized in
+            // `new_slot`, and we only hold a shared reference to the slab.
+            let entries = unsafe { slice::from_raw_parts(slot.as_ptr(), len) };
+            // NB: if cloning a value panics, the slots which have already been
+            // pushed are freed when `slab` is dropped.
+            let slot = allocator::new_slice(len, |n| entries[n].clone(), &slab.alloc);
+            slab.slots.push(slot, &slab.alloc);
+        }
+
+        slab
+    }
+}
+
+impl<T> Default for PinSlab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, A> fmt::Debug for PinSlab<T, A>
+where
+    T: fmt::Debug,
+    A: Allocator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<T, A> Index<usize> for PinSlab<T, A>
+where
+    A: Allocator,
+{
+    type Output = T;
+
+    /// Get a reference to the value at the given key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not occupied.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    /// let key = slab.insert(42);
+    /// assert_eq!(42, slab[key]);
+    /// ```
+    fn index(&self, key: usize) -> &Self::Output {
+        match self.get(key) {
+            Some(value) => value,
+            None => panic!("invalid key {key}"),
+        }
+    }
+}
+
+impl<T, A> IndexMut<usize> for PinSlab<T, A>
+where
+    T: Unpin,
+    A: Allocator,
+{
+    /// Get a mutable reference to the value at the given key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not occupied.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    /// let key = slab.insert(42);
+    /// slab[key] = 43;
+    /// assert_eq!(43, slab[key]);
+    /// ```
+    fn index_mut(&mut self, key: usize) -> &mut Self::Output {
+        match self.get_mut(key) {
+            Some(value) => value,
+            None => panic!("invalid key {key}"),
+        }
+    }
+}
+
+impl<T> FromIterator<T> for PinSlab<T> {
+    /// Collect values into a slab, where they are assigned keys in ascending
+    /// order starting at zero.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let slab = ["a", "b"].into_iter().collect::<PinSlab<_>>();
+    /// assert_eq!(Some(&"a"), slab.get(0));
+    /// assert_eq!(Some(&"b"), slab.get(1));
+    /// ```
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut slab = Self::new();
+
+        for value in iter {
+            slab.insert(value);
+        }
+
+        slab
+    }
+}
+
+impl<T, A> IntoIterator for PinSlab<T, A>
+where
+    T: Unpin,
+    A: Allocator,
+{
+    type Item = (usize, T);
+    type IntoIter = IntoIter<T, A>;
+
+    /// Consume the slab and iterate over all of its values as `(key, value)`
+    /// pairs, in ascending order of keys.
+    ///
+    /// This requires `T: Unpin`, since values stored in the slab might have
+    /// been pinned.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    /// let a = slab.insert("a");
+    /// let b = slab.insert("b");
+    /// let c = slab.insert("c");
+    /// assert!(slab.remove(b));
+    ///
+    /// assert_eq!(vec![(a, "a"), (c, "c")], slab.into_iter().collect::<Vec<_>>());
+    /// ```
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { slab: self, key: 0 }
+    }
+}
+
+impl<'a, T, A> IntoIterator for &'a PinSlab<T, A>
+where
+    A: Allocator,
+{
+    type Item = (usize, &'a T);
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T, A> IntoIterator for &'a mut PinSlab<T, A>
+where
+    T: Unpin,
+    A: Allocator,
+{
+    type Item = (usize, &'a mut T);
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T, A> Drop for PinSlab<T, A>
+where
+    A: Allocator,
+{
+    fn drop(&mut self) {
+        self.clear();
+
+        // Safety: the table of slots and the vacant keys are only ever grown
+        // with our allocator.
+        unsafe {
+            self.slots.free(&self.alloc);
+            self.vacant.free(&self.alloc);
+        }
+    }
+}
+
+/// A min-heap of vacant keys, used when keys are reused lowest first.
+///
+/// Like [RawVec] it doesn't know its allocator, so the owner is responsible for
+/// calling [VacantHeap::free].
+struct VacantHeap {
+    keys: RawVec<usize>,
+}
+
+impl VacantHeap {
+    const fn new() -> Self {
+        Self {
+            keys: RawVec::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Get the lowest vacant key.
+    fn peek(&self) -> Option<usize> {
+        self.keys.first().copied()
+    }
+
+    fn push<A>(&mut self, key: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        self.keys.push(key, alloc);
+
+        let mut n = self.keys.len() - 1;
+
+        while n > 0 {
+            let parent = (n - 1) / 2;
+
+            if self.keys[parent] <= self.keys[n] {
+                break;
+            }
+
+            self.keys.swap(parent, n);
+            n = parent;
+        }
+    }
+
+    /// Remove the lowest vacant key.
+    fn pop(&mut self) -> Option<usize> {
+        let last = self.keys.len().checked_sub(1)?;
+        self.keys.swap(0, last);
+        let key = self.keys[last];
+        self.keys.truncate(last);
+
+        let mut n = 0;
+
+        loop {
+            let left = 2 * n + 1;
+            let right = left + 1;
+            let mut lowest = n;
+
+            if left < last && self.keys[left] < self.keys[lowest] {
+                lowest = left;
+            }
+
+            if right < last && self.keys[right] < self.keys[lowest] {
+                lowest = right;
+            }
+
+            if lowest == n {
+                break;
+            }
+
+            self.keys.swap(n, lowest);
+            n = lowest;
+        }
+
+        Some(key)
+    }
+
+    fn clear(&mut self) {
+        self.keys.truncate(0);
+    }
+
+    fn clone_in<A>(&self, alloc: &A) -> Self
+    where
+        A: Allocator,
+    {
+        let mut keys = self.keys.iter().copied();
+        let mut clone = Self::new();
+        clone
+            .keys
+            .extend_to(self.len(), || keys.next().unwrap_or_default(), alloc);
+        clone
+    }
+
+    /// Free the heap, leaving it empty.
+    ///
+    /// # Safety
+    ///
+    /// `alloc` must be the allocator every key has been pushed with.
+    unsafe fn free<A>(&mut self, alloc: &A)
+    where
+        A: Allocator,
+    {
+        self.keys.free(alloc);
+    }
+}
+
+/// A vacant entry in a [PinSlab], constructed through
+/// [PinSlab::vacant_entry].
+pub struct VacantEntry<'a, T, A = Global>
+where
+    A: Allocator,
+{
+    slab: &'a mut PinSlab<T, A>,
+    key: usize,
+}
+
+impl<'a, T, A> VacantEntry<'a, T, A>
+where
+    A: Allocator,
+{
+    /// Get the key which the value will be inserted at.
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    /// Insert a value into the entry, returning a pinned reference to it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::pin_slab::PinSlab;
+    ///
+    /// let mut slab = PinSlab::new();
+    /// let entry = slab.vacant_entry();
+    /// let key = entry.key();
+    /// assert_eq!(42, *entry.insert(42));
+    /// assert_eq!(Some(&42), slab.get(key));
+    /// ```
+    pub fn insert(self, val: T) -> Pin<&'a mut T> {
+        self.slab.insert_at(self.key, val);
+
+        // Safety: the value was just inserted, and is never moved as
+        // described in [PinSlab::get_pin_mut].
+        unsafe {
+            let value = self
+                .slab
+                .internal_get_mut(self.key)
+                .expect("value was just inserted");
+            Pin::new_unchecked(value)
+        }
+    }
+}
+
+impl<T, A> fmt::Debug for VacantEntry<'_, T, A>
+where
+    A: Allocator,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VacantEntry")
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+/// Cursor over the occupied entries of a slab, shared by its iterators.
+struct RawIter<'a, T> {
+    slots: &'a [ptr::NonNull<Entry<T>>],
+    // Index of the current slot.
+    slot: usize,
+    // Offset of the next entry to inspect in the current slot.
+    offset: usize,
+    // Number of occupied entries which have not been visited yet.
+    remaining: usize,
+    growth: Growth,
+}
+
+impl<'a, T> RawIter<'a, T> {
+    fn new<A>(slab: &'a PinSlab<T, A>) -> Self
+    where
+        A: Allocator,
+    {
+        Self {
+            slots: &slab.slots,
+            slot: 0,
+            offset: 0,
+            remaining: slab.len,
+            growth: slab.growth,
+        }
+    }
+
+    /// Get the key and a pointer to the next occupied entry.
+    ///
+    /// The pointer is only valid for as long as the slab is borrowed, and is
+    /// guaranteed to point to an [Entry::Occupied].
+    fn next(&mut self) -> Option<(usize, *mut Entry<T>)> {
+        while self.remaining > 0 {
+            let slot = *self.slots.get(self.slot)?;
+            let len = self.growth.slot_size(self.slot);
+
+            if self.offset == len {
+                self.slot += 1;
+                self.offset = 0;
+                continue;
+            }
+
+            let offset = self.offset;
+            self.offset += 1;
+
+            // Safety: all slots are fully allocated and initialized in
+            // `new_slot`, and we only read entries within its length.
+            let entry = unsafe { slot.as_ptr().add(offset) };
+
+            if let Entry::Occupied(..) = unsafe { &*entry } {
+                self.remaining -= 1;
+                return Some((self.growth.slot_start(self.slot) + offset, entry));
+            }
+        }
+
+        None
+    }
+}
+
+/// An iterator over the keys of a [PinSlab], constructed through
+/// [PinSlab::keys].
+pub struct Keys<'a, T> {
+    raw: RawIter<'a, T>,
+}
+
+impl<T> Iterator for Keys<'_, T> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, _) = self.raw.next()?;
+        Some(key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.raw.remaining, Some(self.raw.remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for Keys<'_, T> {}
+impl<T> FusedIterator for Keys<'_, T> {}
+
+/// An iterator over the entries of a [PinSlab], constructed through
+/// [PinSlab::iter].
+pub struct Iter<'a, T> {
+    raw: RawIter<'a, T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, entry) = self.raw.next()?;
+
+        // Safety: we hold a shared borrow of the slab.
+        match unsafe { &*entry } {
+            Entry::Occupied(value) => Some((key, value)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.raw.remaining, Some(self.raw.remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+impl<T> FusedIterator for Iter<'_, T> {}
+
+/// A mutable iterator over the entries of a [PinSlab], constructed through
+/// [PinSlab::iter_mut].
+pub struct IterMut<'a, T> {
+    raw: RawIter<'a, T>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T>
+where
+    T: Unpin,
+{
+    type Item = (usize, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, entry) = self.raw.next()?;
+
+        // Safety: we hold an exclusive borrow of the slab, and each entry is
+        // only visited once.
+        match unsafe { &mut *entry } {
+            Entry::Occupied(value) => Some((key, value)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.raw.remaining, Some(self.raw.remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for IterMut<'_, T> where T: Unpin {}
+impl<T> FusedIterator for IterMut<'_, T> where T: Unpin {}
+
+/// A pinned mutable iterator over the entries of a [PinSlab], constructed
+/// through [PinSlab::iter_pin_mut].
+pub struct IterPinMut<'a, T> {
+    raw: RawIter<'a, T>,
+}
+
+impl<'a, T> Iterator for IterPinMut<'a, T> {
+    type Item = (usize, Pin<&'a mut T>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, entry) = self.raw.next()?;
+
+        // Safety: we hold an exclusive borrow of the slab, and each entry is
+        // only visited once. Values are never moved as described in
+        // [PinSlab::get_pin_mut].
+        match unsafe { &mut *entry } {
+            Entry::Occupied(value) => Some((key, unsafe { Pin::new_unchecked(value) })),
+            _ => unreachable!(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.raw.remaining, Some(self.raw.remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for IterPinMut<'_, T> {}
+impl<T> FusedIterator for IterPinMut<'_, T> {}
+
+/// An owning iterator over the entries of a [PinSlab], constructed through
+/// its [IntoIterator] implementation.
+pub struct IntoIter<T, A = Global>
+where
+    A: Allocator,
+{
+    slab: PinSlab<T, A>,
+    // The next key to inspect.
+    key: usize,
+}
+
+impl<T, A> Iterator for IntoIter<T, A>
+where
+    T: Unpin,
+    A: Allocator,
+{
+    type Item = (usize, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.slab.len > 0 {
+            let key = self.key;
+            self.key += 1;
+
+            let (slot, offset, len) = self.slab.growth.calculate_key(key);
+            // NB: there are occupied entries left, so the slot must exist.
+            let slot = self.slab.slots[slot];
+            debug_assert!(offset < len);
+
+            // Safety: all slots are fully allocated and initialized in
+            // `new_slot`. Taken entries are replaced with `Entry::None`, which
+            // is fine since the slab is never inserted into again.
+            unsafe {
+                let entry = &mut *slot.as_ptr().add(offset);
+
+                if let Entry::Occupied(..) = entry {
+                    let Entry::Occupied(value) = mem::replace(entry, Entry::None) else {
+                        unreachable!();
+                    };
+
+                    self.slab.len -= 1;
+                    return Some((key, value));
+                }
+            }
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.slab.len, Some(self.slab.len))
+    }
+}
+
+impl<T, A> ExactSizeIterator for IntoIter<T, A>
+where
+    T: Unpin,
+    A: Allocator,
+{
+}
+
+impl<T, A> FusedIterator for IntoIter<T, A>
+where
+    T: Unpin,
+    A: Allocator,
+{
+}
 
 #[cfg(test)]
 mod tests {
-    use super::{calculate_key, slot_sizes, PinSlab, FIRST_SLOT_SIZE};
+    use super::{Growth, PinSlab, Reuse, FIRST_SLOT_SIZE};
 
+    // Miri does its own allocation checking.
+    #[cfg(not(miri))]
     #[global_allocator]
     static ALLOCATOR: checkers::Allocator = checkers::Allocator::system();
 
@@ -378,33 +1808,78 @@ mod tests {
                 FIRST_SLOT_SIZE << 2,
                 FIRST_SLOT_SIZE << 3
             ],
-            slot_sizes().take(5).collect::<Vec<_>>()
+            Growth::default().slot_sizes().take(5).collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            vec![4, 12, 48, 192],
+            Growth::geometric(4, 4)
+                .slot_sizes()
+                .take(4)
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            vec![8, 8, 8],
+            Growth::fixed(8).slot_sizes().take(3).collect::<Vec<_>>()
         );
     }
 
     #[test]
     fn key_test() {
+        let growth = Growth::default();
+
         // NB: range of the first slot.
-        assert_eq!((0, 0, 16), calculate_key(0));
-        assert_eq!((0, 15, 16), calculate_key(15));
+        assert_eq!((0, 0, 16), growth.calculate_key(0));
+        assert_eq!((0, 15, 16), growth.calculate_key(15));
 
         for i in 4..=62 {
             let end_range = 1usize << i;
-            assert_eq!((i - 3, 0, end_range), calculate_key(end_range));
+            assert_eq!((i - 3, 0, end_range), growth.calculate_key(end_range));
             assert_eq!(
                 (i - 3, end_range - 1, end_range),
-                calculate_key((1usize << (i + 1)) - 1)
+                growth.calculate_key((1usize << (i + 1)) - 1)
             );
         }
     }
 
-    #[checkers::test]
+    #[test]
+    fn keys_are_contiguous() {
+        let policies = [
+            Growth::default(),
+            Growth::doubling(1),
+            Growth::geometric(2, 8),
+            Growth::geometric(64, 16),
+            Growth::fixed(1),
+            Growth::fixed(32),
+        ];
+
+        for growth in policies {
+            let mut expected = (0, 0);
+
+            for key in 0..if cfg!(miri) { 256 } else { 4096 } {
+                let (slot, offset, len) = growth.calculate_key(key);
+                assert_eq!(expected, (slot, offset), "{growth:?} {key}");
+                assert_eq!(len, growth.slot_size(slot));
+                assert_eq!(key, growth.slot_start(slot) + offset);
+
+                expected = if offset + 1 == len {
+                    (slot + 1, 0)
+                } else {
+                    (slot, offset + 1)
+                };
+            }
+        }
+    }
+
+    #[cfg_attr(not(miri), checkers::test)]
+    #[cfg_attr(miri, test)]
     fn insert_get_remove_many() {
         let mut slab = PinSlab::new();
 
         let mut keys = Vec::new();
 
-        for i in 0..1024 {
+        for i in 0..if cfg!(miri) { 64 } else { 1024 } {
             keys.push((i as u128, slab.insert(Box::new(i as u128))));
         }
 
@@ -418,4 +1893,168 @@ mod tests {
             assert!(slab.get_pin_mut(key).is_none());
         }
     }
+
+    #[cfg_attr(not(miri), checkers::test)]
+    #[cfg_attr(miri, test)]
+    fn clone_is_deep() {
+        let mut slab = PinSlab::new();
+
+        for i in 0..40u32 {
+            assert_eq!(i as usize, slab.insert(Box::new(i)));
+        }
+
+        assert!(slab.remove(3));
+        assert!(slab.remove(20));
+
+        let mut copy = slab.clone();
+        assert_eq!(slab.len(), copy.len());
+
+        for key in 0..40 {
+            assert_eq!(slab.get(key), copy.get(key));
+        }
+
+        // Both slabs reuse removed keys in the same order.
+        assert_eq!(20, copy.insert(Box::new(100)));
+        assert_eq!(3, copy.insert(Box::new(101)));
+        assert!(slab.get(20).is_none());
+
+        **copy.get_mut(0).unwrap() = 200;
+        assert_eq!(Some(&Box::new(0)), slab.get(0));
+        assert_eq!(Some(&Box::new(200)), copy.get(0));
+    }
+
+    #[cfg_attr(not(miri), checkers::test)]
+    #[cfg_attr(miri, test)]
+    fn iterators_span_slots() {
+        let mut slab = (0..100u32).map(Box::new).collect::<PinSlab<_>>();
+
+        for key in (0..100).step_by(3) {
+            assert!(slab.remove(key));
+        }
+
+        let expected = (0..100).filter(|n| n % 3 != 0).collect::<Vec<_>>();
+
+        assert_eq!(expected.len(), slab.keys().len());
+        assert_eq!(expected, slab.keys().collect::<Vec<_>>());
+
+        for (key, value) in slab.iter_mut() {
+            assert_eq!(key as u32, **value);
+            **value += 1000;
+        }
+
+        for (key, value) in slab.iter_pin_mut() {
+            assert_eq!(key as u32 + 1000, **value);
+        }
+
+        let mut iter = slab.into_iter();
+        assert_eq!(Some((1, Box::new(1001))), iter.next());
+        assert_eq!(expected.len() - 1, iter.len());
+        // NB: the remaining values are dropped with the iterator.
+        drop(iter);
+    }
+
+    #[cfg_attr(not(miri), checkers::test)]
+    #[cfg_attr(miri, test)]
+    fn vacant_entry_reuses_keys() {
+        let mut slab = PinSlab::new();
+        slab.insert(Box::new(0));
+        slab.insert(Box::new(1));
+        assert!(slab.remove(0));
+
+        let entry = slab.vacant_entry();
+        assert_eq!(0, entry.key());
+        assert_eq!(2, **entry.insert(Box::new(2)));
+        assert_eq!(2, slab.insert(Box::new(3)));
+        assert_eq!(3, slab.len());
+    }
+
+    #[cfg_attr(not(miri), checkers::test)]
+    #[cfg_attr(miri, test)]
+    fn reuse_policies() {
+        let cases = [
+            (Reuse::Lifo, [7, 2, 5, 20]),
+            (Reuse::Fifo, [5, 2, 7, 20]),
+            (Reuse::Lowest, [2, 5, 7, 20]),
+        ];
+
+        for (reuse, expected) in cases {
+            let mut slab = PinSlab::with_growth(Growth::fixed(4));
+            slab.set_reuse(reuse);
+
+            for n in 0..20 {
+                slab.insert(Box::new(n));
+            }
+
+            for key in [5, 2, 7] {
+                assert!(slab.remove(key));
+            }
+
+            let keys = expected.map(|_| slab.insert(Box::new(0)));
+            assert_eq!(expected, keys, "{reuse:?}");
+        }
+    }
+
+    #[cfg_attr(not(miri), checkers::test)]
+    #[cfg_attr(miri, test)]
+    fn set_reuse_orders_free_keys() {
+        for reuse in [Reuse::Lifo, Reuse::Fifo, Reuse::Lowest] {
+            let mut slab = PinSlab::with_growth(Growth::fixed(4));
+
+            for n in 0..10 {
+                slab.insert(Box::new(n));
+            }
+
+            for key in [9, 1, 6] {
+                assert!(slab.remove(key));
+            }
+
+            slab.set_reuse(reuse);
+            assert!(slab.remove(3));
+
+            let keys = [0; 5].map(|_| slab.insert(Box::new(0)));
+
+            let expected = match reuse {
+                Reuse::Lifo => [3, 6, 1, 9, 10],
+                Reuse::Fifo => [1, 6, 9, 3, 10],
+                Reuse::Lowest => [1, 3, 6, 9, 10],
+            };
+
+            assert_eq!(expected, keys, "{reuse:?}");
+        }
+    }
+
+    #[cfg_attr(not(miri), checkers::test)]
+    #[cfg_attr(miri, test)]
+    fn compact_frees_trailing_slots() {
+        for reuse in [Reuse::Lifo, Reuse::Fifo, Reuse::Lowest] {
+            let mut slab = PinSlab::with_growth(Growth::doubling(2));
+            slab.set_reuse(reuse);
+
+            for n in 0..40 {
+                slab.insert(Box::new(n));
+            }
+
+            for key in (0..40).filter(|key| key % 8 != 1) {
+                assert!(slab.remove(key));
+            }
+
+            assert_eq!(64, slab.capacity());
+
+            let mut moved = Vec::new();
+            slab.compact(|old, new| moved.push((old, new)));
+
+            assert_eq!(vec![(33, 0), (25, 2), (17, 3), (9, 4)], moved, "{reuse:?}");
+            assert_eq!(8, slab.capacity());
+
+            let values = slab
+                .iter()
+                .map(|(key, value)| (key, **value))
+                .collect::<Vec<_>>();
+            assert_eq!(vec![(0, 33), (1, 1), (2, 25), (3, 17), (4, 9)], values);
+
+            let keys = [0; 5].map(|_| slab.insert(Box::new(0)));
+            assert_eq!([5, 6, 7, 8, 9], keys, "{reuse:?}");
+            assert_eq!(10, slab.len());
+        }
+    }
 }
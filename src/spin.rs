@@ -0,0 +1,170 @@
+//! Detection of children which continuously wake themselves while being
+//! polled, also known as _spinners_.
+//!
+//! Unicycle already limits spinners to being polled once per cycle, but a
+//! child which wakes itself every time it is polled still forces every single
+//! cycle to be run. We detect this by noting which index is currently being
+//! polled, and checking if a wakeup for that index arrives before the poll
+//! completes.
+
+use crate::allocator::{Allocator, RawVec};
+use crate::sync::{AtomicBool, AtomicUsize, Ordering};
+use core::mem;
+
+/// Shared state used by wakers to detect if a child woke itself while it was
+/// being polled.
+pub(crate) struct SelfWake {
+    /// The index currently being polled plus one, or zero if nothing is being
+    /// polled.
+    polling: AtomicUsize,
+    /// Set if the child currently being polled was woken.
+    woken: AtomicBool,
+}
+
+impl SelfWake {
+    pub(crate) fn new() -> Self {
+        Self {
+            polling: AtomicUsize::new(0),
+            woken: AtomicBool::new(false),
+        }
+    }
+
+    /// Mark that we are about to poll the given index.
+    pub(crate) fn enter(&self, index: usize) {
+        self.woken.store(false, Ordering::Relaxed);
+        self.polling.store(index.wrapping_add(1), Ordering::Relaxed);
+    }
+
+    /// Mark that we are done polling.
+    pub(crate) fn leave(&self) {
+        self.polling.store(0, Ordering::Relaxed);
+    }
+
+    /// Test if the most recently polled child woke itself while it was being
+    /// polled.
+    pub(crate) fn woken(&self) -> bool {
+        self.woken.load(Ordering::Relaxed)
+    }
+
+    /// Register a wakeup for the given index.
+    ///
+    /// Note that a wakeup for the polled index from another thread which
+    /// happens to coincide with the poll is indistinguishable from a self
+    /// wake, but it will also cause the child to be polled in the next cycle.
+    pub(crate) fn wake(&self, index: usize) {
+        if self.polling.load(Ordering::Relaxed) == index.wrapping_add(1) {
+            self.woken.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Per-child tracking of consecutive self-wakes.
+pub(crate) struct Spin {
+    /// Number of consecutive cycles after which a child is considered a
+    /// spinner. If `None`, tracking is disabled.
+    threshold: Option<usize>,
+    /// Number of consecutive cycles in which the child at the corresponding
+    /// index woke itself.
+    counts: RawVec<usize>,
+}
+
+impl Spin {
+    pub(crate) fn new() -> Self {
+        Self {
+            threshold: None,
+            counts: RawVec::new(),
+        }
+    }
+
+    pub(crate) fn threshold(&self) -> Option<usize> {
+        self.threshold
+    }
+
+    pub(crate) fn set_threshold<A>(&mut self, threshold: Option<usize>, alloc: &A)
+    where
+        A: Allocator,
+    {
+        self.threshold = threshold;
+
+        if threshold.is_none() {
+            // Safety: The counts are only ever grown with the allocator of the
+            // set, which is what we're called with.
+            unsafe { self.counts.free(alloc) };
+        }
+    }
+
+    /// Reset tracking for a newly inserted child.
+    pub(crate) fn insert(&mut self, index: usize) {
+        if let Some(count) = self.counts.get_mut(index) {
+            *count = 0;
+        }
+    }
+
+    /// Reset tracking for all children.
+    pub(crate) fn clear(&mut self) {
+        self.counts.truncate(0);
+    }
+
+    /// Move the tracking of a child which has been moved to a new index.
+    pub(crate) fn relocate(&mut self, old: usize, new: usize) {
+        let count = self.counts.get_mut(old).map(mem::take).unwrap_or_default();
+
+        if let Some(slot) = self.counts.get_mut(new) {
+            *slot = count;
+        }
+    }
+
+    /// Record the outcome of polling the child at the given index.
+    pub(crate) fn record<A>(&mut self, index: usize, self_woken: bool, alloc: &A)
+    where
+        A: Allocator,
+    {
+        if self.threshold.is_none() {
+            return;
+        }
+
+        if self.counts.len() <= index {
+            if !self_woken {
+                return;
+            }
+
+            self.counts.extend_to(index + 1, || 0, alloc);
+        }
+
+        let count = &mut self.counts[index];
+
+        if self_woken {
+            *count = count.saturating_add(1);
+        } else {
+            *count = 0;
+        }
+    }
+
+    /// Get the number of consecutive self-wakes for the given index.
+    pub(crate) fn count(&self, index: usize) -> usize {
+        self.counts.get(index).copied().unwrap_or_default()
+    }
+
+    /// Iterate over all indexes whose count exceeds the threshold.
+    pub(crate) fn spinners(&self) -> impl Iterator<Item = usize> + '_ {
+        let threshold = self.threshold.unwrap_or(usize::MAX);
+
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(move |(_, count)| **count > threshold)
+            .map(|(index, _)| index)
+    }
+
+    /// Free the tracked counts.
+    ///
+    /// # Safety
+    ///
+    /// `alloc` must be the allocator the counts were recorded with.
+    pub(crate) unsafe fn free<A>(&mut self, alloc: &A)
+    where
+        A: Allocator,
+    {
+        self.counts.free(alloc);
+    }
+}
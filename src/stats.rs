@@ -0,0 +1,332 @@
+//! Scheduling statistics, collected when the `stats` feature is enabled.
+//!
+//! When the feature is disabled the collectors in here are zero-sized and all
+//! of their methods compile down to nothing, so they can be unconditionally
+//! threaded through the polling loop.
+
+#[cfg(feature = "stats")]
+pub use self::enabled::{ChildStats, SetStats};
+#[cfg(feature = "stats")]
+pub(crate) use self::enabled::{Stats, WakeEntry, WakeStats};
+
+#[cfg(not(feature = "stats"))]
+pub(crate) use self::disabled::{Stats, WakeEntry, WakeStats};
+
+#[cfg(feature = "stats")]
+mod enabled {
+    use crate::allocator::{Allocator, RawVec};
+    use crate::sync::{AtomicU64, Ordering};
+    use std::mem;
+    use std::time::{Duration, Instant};
+
+    /// Aggregate statistics for an [Unordered][crate::Unordered] set.
+    ///
+    /// See [Unordered::stats][crate::Unordered::stats].
+    #[derive(Debug, Default, Clone, Copy, PartialEq)]
+    #[non_exhaustive]
+    pub struct SetStats {
+        /// The number of polling cycles which have been completed, that is the
+        /// number of times a wake set has been fully drained.
+        pub cycles: u64,
+        /// The total number of times a child has been polled.
+        pub polled: u64,
+        /// The number of children polled during the last completed cycle.
+        pub last_cycle_polled: u64,
+        /// The number of wakeups for indexes which no longer had a child
+        /// associated with them by the time they were processed.
+        pub spurious_wakes: u64,
+        /// The number of times the active and alternate wake sets have been
+        /// swapped. This includes swaps caused by growing the sets when
+        /// pushing.
+        pub swaps: u64,
+    }
+
+    impl SetStats {
+        /// The average number of children polled per cycle.
+        pub fn polled_per_cycle(&self) -> f64 {
+            if self.cycles == 0 {
+                return 0.0;
+            }
+
+            self.polled as f64 / self.cycles as f64
+        }
+    }
+
+    /// Statistics for a single child in an [Unordered][crate::Unordered] set.
+    ///
+    /// See [Unordered::child_stats][crate::Unordered::child_stats].
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    #[non_exhaustive]
+    pub struct ChildStats {
+        /// The number of times the child has been polled.
+        pub polls: u64,
+        /// The total amount of time spent polling the child.
+        pub poll_time: Duration,
+        /// The number of wakeups the child has received.
+        pub wakes: u64,
+        /// The time elapsed since the child was last woken, if ever.
+        pub since_last_wake: Option<Duration>,
+    }
+
+    #[derive(Default, Clone, Copy)]
+    struct PollEntry {
+        polls: u64,
+        poll_time: Duration,
+    }
+
+    /// Statistics owned by the unordered set.
+    #[derive(Default)]
+    pub(crate) struct Stats {
+        set: SetStats,
+        /// Children polled in the cycle which is currently in progress.
+        current_cycle: u64,
+        children: RawVec<PollEntry>,
+    }
+
+    impl Stats {
+        pub(crate) fn new() -> Self {
+            Self::default()
+        }
+
+        /// Reset statistics for a newly inserted child.
+        pub(crate) fn insert<A>(&mut self, index: usize, alloc: &A)
+        where
+            A: Allocator,
+        {
+            if self.children.len() <= index {
+                self.children
+                    .extend_to(index + 1, PollEntry::default, alloc);
+            }
+
+            self.children[index] = PollEntry::default();
+        }
+
+        /// Reset statistics for all children.
+        pub(crate) fn clear(&mut self) {
+            self.children.truncate(0);
+        }
+
+        /// Move the statistics of a child which has been moved to a new index.
+        pub(crate) fn relocate<A>(&mut self, old: usize, new: usize, alloc: &A)
+        where
+            A: Allocator,
+        {
+            let entry = self
+                .children
+                .get_mut(old)
+                .map(mem::take)
+                .unwrap_or_default();
+            self.insert(new, alloc);
+            self.children[new] = entry;
+        }
+
+        /// Free the per-child statistics.
+        ///
+        /// # Safety
+        ///
+        /// `alloc` must be the allocator the statistics were grown with.
+        pub(crate) unsafe fn free<A>(&mut self, alloc: &A)
+        where
+            A: Allocator,
+        {
+            self.children.free(alloc);
+        }
+
+        /// Mark the end of a polling cycle, once a wake set has been fully
+        /// drained.
+        pub(crate) fn end_cycle(&mut self) {
+            self.set.cycles += 1;
+            self.set.last_cycle_polled = self.current_cycle;
+            self.current_cycle = 0;
+        }
+
+        /// Record a swap of the active and alternate wake sets.
+        pub(crate) fn swap(&mut self) {
+            self.set.swaps += 1;
+        }
+
+        /// Record a spurious wakeup.
+        pub(crate) fn spurious(&mut self) {
+            self.set.spurious_wakes += 1;
+        }
+
+        /// Time the poll of the child at the given index.
+        pub(crate) fn poll<F, R>(&mut self, index: usize, f: F) -> R
+        where
+            F: FnOnce() -> R,
+        {
+            let start = Instant::now();
+            let result = f();
+            let elapsed = start.elapsed();
+
+            self.set.polled += 1;
+            self.current_cycle += 1;
+
+            if let Some(entry) = self.children.get_mut(index) {
+                entry.polls += 1;
+                entry.poll_time += elapsed;
+            }
+
+            result
+        }
+
+        pub(crate) fn set_stats(&self) -> SetStats {
+            self.set
+        }
+
+        pub(crate) fn child_stats(
+            &self,
+            index: usize,
+            wake_stats: &WakeStats,
+            wake_entry: &WakeEntry,
+        ) -> ChildStats {
+            let entry = self.children.get(index).copied().unwrap_or_default();
+            let (wakes, last_wake) = wake_stats.get(wake_entry);
+
+            ChildStats {
+                polls: entry.polls,
+                poll_time: entry.poll_time,
+                wakes,
+                since_last_wake: last_wake.map(|last| last.elapsed()),
+            }
+        }
+    }
+
+    /// Wake statistics for a single child.
+    ///
+    /// These are stored in the waker cell of each index, which wakers on any
+    /// thread can reach without locking.
+    pub(crate) struct WakeEntry {
+        wakes: AtomicU64,
+        /// The time of the last wakeup in nanoseconds since the base of the
+        /// wake statistics plus one, or zero if the child was never woken.
+        last_wake: AtomicU64,
+    }
+
+    impl WakeEntry {
+        pub(crate) fn new() -> Self {
+            Self {
+                wakes: AtomicU64::new(0),
+                last_wake: AtomicU64::new(0),
+            }
+        }
+    }
+
+    /// Wake statistics, which are updated from wakers on any thread.
+    pub(crate) struct WakeStats {
+        /// The instant which wakeup times are measured from.
+        base: Instant,
+    }
+
+    impl WakeStats {
+        pub(crate) fn new() -> Self {
+            Self {
+                base: Instant::now(),
+            }
+        }
+
+        /// Reset the statistics of a child, either because it was newly
+        /// inserted or because the collection is being cleared.
+        pub(crate) fn reset(&self, entry: &WakeEntry) {
+            entry.wakes.store(0, Ordering::Relaxed);
+            entry.last_wake.store(0, Ordering::Relaxed);
+        }
+
+        /// Move the statistics of a child which has been moved to a new index.
+        pub(crate) fn relocate(&self, old: &WakeEntry, new: &WakeEntry) {
+            let wakes = old.wakes.swap(0, Ordering::Relaxed);
+            let last_wake = old.last_wake.swap(0, Ordering::Relaxed);
+            new.wakes.store(wakes, Ordering::Relaxed);
+            new.last_wake.store(last_wake, Ordering::Relaxed);
+        }
+
+        /// Record a wakeup of the child the entry belongs to.
+        pub(crate) fn wake(&self, entry: &WakeEntry) {
+            let now = u64::try_from(self.base.elapsed().as_nanos()).unwrap_or(u64::MAX);
+
+            // Ordering: The statistics are only ever read as a snapshot, and
+            // concurrent wakeups keep the latest time.
+            entry.wakes.fetch_add(1, Ordering::Relaxed);
+            entry
+                .last_wake
+                .fetch_max(now.saturating_add(1), Ordering::Relaxed);
+        }
+
+        fn get(&self, entry: &WakeEntry) -> (u64, Option<Instant>) {
+            let wakes = entry.wakes.load(Ordering::Relaxed);
+            let last_wake = entry.last_wake.load(Ordering::Relaxed);
+            let last_wake = last_wake
+                .checked_sub(1)
+                .map(|nanos| self.base + Duration::from_nanos(nanos));
+            (wakes, last_wake)
+        }
+    }
+}
+
+#[cfg(not(feature = "stats"))]
+mod disabled {
+    pub(crate) struct Stats;
+
+    impl Stats {
+        #[inline(always)]
+        pub(crate) fn new() -> Self {
+            Self
+        }
+
+        #[inline(always)]
+        pub(crate) fn insert<A>(&mut self, _: usize, _: &A) {}
+
+        #[inline(always)]
+        pub(crate) fn clear(&mut self) {}
+
+        #[inline(always)]
+        pub(crate) fn relocate<A>(&mut self, _: usize, _: usize, _: &A) {}
+
+        #[inline(always)]
+        pub(crate) unsafe fn free<A>(&mut self, _: &A) {}
+
+        #[inline(always)]
+        pub(crate) fn end_cycle(&mut self) {}
+
+        #[inline(always)]
+        pub(crate) fn swap(&mut self) {}
+
+        #[inline(always)]
+        pub(crate) fn spurious(&mut self) {}
+
+        #[inline(always)]
+        pub(crate) fn poll<F, R>(&mut self, _: usize, f: F) -> R
+        where
+            F: FnOnce() -> R,
+        {
+            f()
+        }
+    }
+
+    pub(crate) struct WakeEntry;
+
+    impl WakeEntry {
+        #[inline(always)]
+        pub(crate) fn new() -> Self {
+            Self
+        }
+    }
+
+    pub(crate) struct WakeStats;
+
+    impl WakeStats {
+        #[inline(always)]
+        pub(crate) fn new() -> Self {
+            Self
+        }
+
+        #[inline(always)]
+        pub(crate) fn reset(&self, _: &WakeEntry) {}
+
+        #[inline(always)]
+        pub(crate) fn relocate(&self, _: &WakeEntry, _: &WakeEntry) {}
+
+        #[inline(always)]
+        pub(crate) fn wake(&self, _: &WakeEntry) {}
+    }
+}
@@ -0,0 +1,242 @@
+//! Atomics and reference counting used by unicycle.
+//!
+//! By default these come from `core` and `alloc`. With the `portable-atomic`
+//! feature they are instead provided by [portable-atomic], which makes it
+//! possible to target platforms without native atomic compare-and-swap such as
+//! `thumbv6m-none-eabi`. On such platforms portable-atomic needs to be told how
+//! to implement them, either through the `critical-section` feature or one of
+//! its `unsafe-assume-*` configuration flags.
+//!
+//! Since hosts always have native atomics, portable-atomic only falls back to
+//! critical sections when building for such a target:
+//!
+//! ```text
+//! cargo build --target thumbv6m-none-eabi --no-default-features --features critical-section
+//! ```
+//!
+//! To exercise the fallback on a host, building with `--cfg
+//! unicycle_critical_section` replaces the atomics with ones which perform
+//! every operation inside of a critical section, the same way portable-atomic
+//! does on targets without compare-and-swap. This requires the
+//! `critical-section` feature and an implementation of critical-section, which
+//! the tests get from its `std` feature:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg unicycle_critical_section" cargo test --no-default-features --features critical-section,futures-rs
+//! ```
+//!
+//! When building with `--cfg loom` everything is instead provided by [loom],
+//! which is used by the model tests in `tests/loom_test.rs`:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --test loom_test --release
+//! ```
+//!
+//! [loom]: https://docs.rs/loom
+//! [portable-atomic]: https://docs.rs/portable-atomic
+
+// Not every atomic is used in every configuration, e.g. `AtomicIsize` is only
+// used by the atomic lock backend.
+#![allow(unused_imports)]
+
+#[cfg(loom)]
+pub(crate) use loom::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicIsize, AtomicPtr, AtomicU64, AtomicUsize},
+    sync::Arc,
+};
+
+#[cfg(not(loom))]
+pub(crate) use core::hint::spin_loop;
+
+#[cfg(loom)]
+pub(crate) use loom::thread::yield_now;
+
+/// Give another thread the chance to make progress before retrying through
+/// the executor. This is only needed to tell loom that we're not livelocked,
+/// so it does nothing otherwise.
+#[cfg(not(loom))]
+#[inline(always)]
+pub(crate) fn yield_now() {}
+
+#[cfg(all(not(loom), not(feature = "portable-atomic")))]
+pub(crate) use alloc::sync::Arc;
+#[cfg(all(not(loom), not(feature = "portable-atomic")))]
+pub(crate) use core::sync::atomic::{AtomicBool, AtomicIsize, AtomicPtr, AtomicUsize};
+
+#[cfg(all(not(loom), feature = "portable-atomic"))]
+pub(crate) use portable_atomic_util::Arc;
+
+#[cfg(all(not(loom), feature = "portable-atomic", not(unicycle_critical_section)))]
+pub(crate) use portable_atomic::{AtomicBool, AtomicIsize, AtomicPtr, AtomicUsize};
+
+#[cfg(all(not(loom), unicycle_critical_section))]
+pub(crate) use self::critical::{AtomicBool, AtomicIsize, AtomicPtr, AtomicUsize};
+
+// 64-bit atomics are only needed for statistics, so targets which don't have
+// them only need them if that feature is enabled.
+#[cfg(all(not(loom), unicycle_critical_section, feature = "stats"))]
+pub(crate) use self::critical::AtomicU64;
+#[cfg(all(not(loom), not(feature = "portable-atomic"), feature = "stats"))]
+pub(crate) use core::sync::atomic::AtomicU64;
+#[cfg(all(
+    not(loom),
+    feature = "portable-atomic",
+    not(unicycle_critical_section),
+    feature = "stats"
+))]
+pub(crate) use portable_atomic::AtomicU64;
+
+#[cfg(all(unicycle_critical_section, not(feature = "critical-section")))]
+compile_error!("`--cfg unicycle_critical_section` requires the `critical-section` feature");
+
+/// Atomics which perform every operation inside of a critical section.
+#[cfg(all(not(loom), unicycle_critical_section, feature = "critical-section"))]
+mod critical {
+    use core::cell::UnsafeCell;
+    use core::sync::atomic::Ordering;
+
+    pub(crate) type AtomicBool = Atomic<bool>;
+    pub(crate) type AtomicIsize = Atomic<isize>;
+    pub(crate) type AtomicPtr<T> = Atomic<*mut T>;
+    pub(crate) type AtomicUsize = Atomic<usize>;
+    #[cfg(feature = "stats")]
+    pub(crate) type AtomicU64 = Atomic<u64>;
+
+    #[repr(transparent)]
+    pub(crate) struct Atomic<T> {
+        value: UnsafeCell<T>,
+    }
+
+    // Safety: The value is only ever accessed inside of a critical section or
+    // through exclusive access, and is only ever a primitive which can be
+    // freely copied between threads.
+    unsafe impl<T> Send for Atomic<T> {}
+    unsafe impl<T> Sync for Atomic<T> {}
+
+    impl<T> Atomic<T>
+    where
+        T: Copy,
+    {
+        pub(crate) const fn new(value: T) -> Self {
+            Self {
+                value: UnsafeCell::new(value),
+            }
+        }
+
+        #[inline]
+        fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+            // Safety: The critical section excludes every other access.
+            critical_section::with(|_| f(unsafe { &mut *self.value.get() }))
+        }
+
+        pub(crate) fn get_mut(&mut self) -> &mut T {
+            self.value.get_mut()
+        }
+
+        pub(crate) fn load(&self, _: Ordering) -> T {
+            self.with(|value| *value)
+        }
+
+        pub(crate) fn store(&self, new: T, _: Ordering) {
+            self.with(|value| *value = new);
+        }
+
+        pub(crate) fn swap(&self, new: T, _: Ordering) -> T {
+            self.with(|value| core::mem::replace(value, new))
+        }
+    }
+
+    impl<T> Atomic<T>
+    where
+        T: Copy + PartialEq,
+    {
+        pub(crate) fn compare_exchange(
+            &self,
+            current: T,
+            new: T,
+            _: Ordering,
+            _: Ordering,
+        ) -> Result<T, T> {
+            self.with(|value| {
+                if *value == current {
+                    *value = new;
+                    Ok(current)
+                } else {
+                    Err(*value)
+                }
+            })
+        }
+
+        pub(crate) fn compare_exchange_weak(
+            &self,
+            current: T,
+            new: T,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<T, T> {
+            self.compare_exchange(current, new, success, failure)
+        }
+    }
+
+    macro_rules! integer {
+        ($ty:ty) => {
+            // Not every operation is used for every width.
+            #[allow(dead_code)]
+            impl Atomic<$ty> {
+                pub(crate) fn fetch_add(&self, n: $ty, _: Ordering) -> $ty {
+                    self.with(|value| core::mem::replace(value, value.wrapping_add(n)))
+                }
+
+                pub(crate) fn fetch_sub(&self, n: $ty, _: Ordering) -> $ty {
+                    self.with(|value| core::mem::replace(value, value.wrapping_sub(n)))
+                }
+
+                pub(crate) fn fetch_or(&self, n: $ty, _: Ordering) -> $ty {
+                    self.with(|value| core::mem::replace(value, *value | n))
+                }
+
+                pub(crate) fn fetch_max(&self, n: $ty, _: Ordering) -> $ty {
+                    self.with(|value| core::mem::replace(value, (*value).max(n)))
+                }
+            }
+        };
+    }
+
+    integer!(isize);
+    integer!(usize);
+    #[cfg(feature = "stats")]
+    integer!(u64);
+}
+
+pub(crate) use core::sync::atomic::Ordering;
+
+/// An `UnsafeCell` with the closure-based API of `loom::cell::UnsafeCell`, so
+/// that loom can track accesses to it.
+#[cfg(not(loom))]
+#[repr(transparent)]
+pub(crate) struct UnsafeCell<T> {
+    inner: core::cell::UnsafeCell<T>,
+}
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self {
+            inner: core::cell::UnsafeCell::new(value),
+        }
+    }
+
+    /// Access the value immutably through a raw pointer.
+    #[inline(always)]
+    pub(crate) fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.inner.get())
+    }
+
+    /// Access the value mutably through a raw pointer.
+    #[inline(always)]
+    pub(crate) fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.inner.get())
+    }
+}
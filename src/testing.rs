@@ -0,0 +1,534 @@
+//! Utilities for deterministically testing code built on top of unicycle.
+//!
+//! This provides child futures and streams which are completed by hand
+//! through a handle ([ManualFuture] and [ManualStream]), a parent waker which
+//! records how often it has been woken ([RecordingWaker]), and a [Harness]
+//! which drives an [Unordered] set one `poll_next` at a time while recording
+//! which children were polled in which order.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use std::task::Poll;
+//! use unicycle::FuturesUnordered;
+//! use unicycle::testing::{Harness, ManualFuture};
+//!
+//! let mut harness = Harness::new(FuturesUnordered::new());
+//!
+//! let (future, handle) = ManualFuture::new();
+//! let index = harness.push(future);
+//!
+//! // Newly added children are polled once.
+//! let step = harness.step();
+//! assert_eq!(vec![index], step.polled);
+//! assert!(step.output.is_pending());
+//!
+//! // Children are not polled again until they are woken.
+//! assert!(harness.step().polled.is_empty());
+//! harness.waker().take_wakes();
+//!
+//! handle.complete(42);
+//! assert_eq!(1, harness.waker().wakes());
+//!
+//! let step = harness.step();
+//! assert_eq!(vec![index], step.polled);
+//! assert_eq!(Poll::Ready(Some(42)), step.output);
+//! assert_eq!(Poll::Ready(None), harness.step().output);
+//! ```
+//!
+//! [Unordered]: crate::Unordered
+
+use self::private::{PollLog, Sealed};
+use crate::allocator::{Allocator, Global};
+use crate::bitset::{DefaultBitSet, RawBitSet};
+use crate::lock::{DefaultLock, RawLock};
+use crate::{PollNext, Sentinel, Unordered};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Wake, Waker};
+
+#[cfg(feature = "futures-rs")]
+use futures_core::Stream;
+#[cfg(feature = "futures-rs")]
+use std::collections::VecDeque;
+
+/// State shared between a manual child and its handle.
+struct State<T> {
+    /// The value or values which are ready to be produced.
+    ready: T,
+    /// The waker registered by the last poll.
+    waker: Option<Waker>,
+    /// The number of times the child has been polled.
+    polls: usize,
+    /// The log to record polls in and the index of the child, if it has been
+    /// pushed into a [Harness].
+    attached: Option<(PollLog, usize)>,
+}
+
+impl<T> State<T> {
+    fn new(ready: T) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            ready,
+            waker: None,
+            polls: 0,
+            attached: None,
+        }))
+    }
+
+    /// Record a poll with the given context.
+    fn poll(&mut self, cx: &Context<'_>) {
+        self.polls += 1;
+
+        if let Some((log, index)) = &self.attached {
+            log.record(*index);
+        }
+
+        match &self.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => self.waker = Some(cx.waker().clone()),
+        }
+    }
+
+    /// Release the lock and wake the registered waker, if any.
+    ///
+    /// The waker is called without holding the lock, since waking might poll
+    /// the child again on the current thread.
+    fn wake(mut this: MutexGuard<'_, Self>) {
+        let waker = this.waker.take();
+        drop(this);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // NB: a poisoned lock means a test has already failed, so there's no need
+    // to make things worse.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+mod private {
+    use super::lock;
+    use std::mem;
+    use std::sync::{Arc, Mutex};
+
+    /// Log of the indexes of children as they are polled.
+    #[derive(Clone, Default)]
+    pub struct PollLog {
+        polled: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl PollLog {
+        pub(super) fn record(&self, index: usize) {
+            lock(&self.polled).push(index);
+        }
+
+        pub(super) fn take(&self) -> Vec<usize> {
+            mem::take(&mut *lock(&self.polled))
+        }
+    }
+
+    pub trait Sealed {
+        /// Record polls of this child in the given log under `index`.
+        fn attach(&self, log: PollLog, index: usize);
+    }
+}
+
+/// Trait implemented by children whose polls can be recorded by a [Harness].
+pub trait Manual: self::private::Sealed {}
+
+/// A future which is completed by hand through its [FutureHandle].
+///
+/// See the [module level documentation][self] for more.
+pub struct ManualFuture<T> {
+    state: Arc<Mutex<State<Option<T>>>>,
+}
+
+impl<T> ManualFuture<T> {
+    /// Construct a new pending future and the handle used to complete it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::FuturesUnordered;
+    /// use unicycle::testing::ManualFuture;
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let mut futures = FuturesUnordered::new();
+    /// let (future, handle) = ManualFuture::new();
+    /// futures.push(future);
+    ///
+    /// handle.complete(42);
+    /// assert_eq!(Some(42), futures.next().await);
+    /// # }
+    /// ```
+    pub fn new() -> (Self, FutureHandle<T>) {
+        let state = State::new(None);
+        let handle = FutureHandle {
+            state: state.clone(),
+        };
+        (Self { state }, handle)
+    }
+}
+
+impl<T> Manual for ManualFuture<T> {}
+
+impl<T> Sealed for ManualFuture<T> {
+    fn attach(&self, log: PollLog, index: usize) {
+        lock(&self.state).attached = Some((log, index));
+    }
+}
+
+impl<T> Future for ManualFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.state);
+        state.poll(cx);
+
+        match state.ready.take() {
+            Some(value) => Poll::Ready(value),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T> fmt::Debug for ManualFuture<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManualFuture")
+            .field("polls", &lock(&self.state).polls)
+            .finish()
+    }
+}
+
+/// Handle used to control a [ManualFuture].
+pub struct FutureHandle<T> {
+    state: Arc<Mutex<State<Option<T>>>>,
+}
+
+impl<T> FutureHandle<T> {
+    /// Complete the future with the given value and wake it.
+    pub fn complete(&self, value: T) {
+        let mut state = lock(&self.state);
+        state.ready = Some(value);
+        State::wake(state);
+    }
+
+    /// Wake the future without completing it, causing it to be polled again.
+    pub fn wake(&self) {
+        State::wake(lock(&self.state));
+    }
+
+    /// The number of times the future has been polled.
+    pub fn polls(&self) -> usize {
+        lock(&self.state).polls
+    }
+
+    /// Test if the future has registered a waker which has not yet been
+    /// woken.
+    pub fn is_registered(&self) -> bool {
+        lock(&self.state).waker.is_some()
+    }
+}
+
+/// A stream whose items are sent by hand through its [StreamHandle].
+///
+/// See the [module level documentation][self] for more.
+#[cfg(feature = "futures-rs")]
+#[cfg_attr(docsrs, doc(cfg(feature = "futures-rs")))]
+pub struct ManualStream<T> {
+    state: Arc<Mutex<State<Queue<T>>>>,
+}
+
+#[cfg(feature = "futures-rs")]
+impl<T> ManualStream<T> {
+    /// Construct a new empty stream and the handle used to feed it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use unicycle::StreamsUnordered;
+    /// use unicycle::testing::ManualStream;
+    ///
+    /// # #[tokio::main] async fn main() {
+    /// let mut streams = StreamsUnordered::new();
+    /// let (stream, handle) = ManualStream::new();
+    /// streams.push(stream);
+    ///
+    /// handle.send(1);
+    /// handle.send(2);
+    /// handle.close();
+    ///
+    /// assert_eq!(Some(1), streams.next().await);
+    /// assert_eq!(Some(2), streams.next().await);
+    /// assert_eq!(None, streams.next().await);
+    /// # }
+    /// ```
+    pub fn new() -> (Self, StreamHandle<T>) {
+        let state = State::new(Queue {
+            items: VecDeque::new(),
+            closed: false,
+        });
+        let handle = StreamHandle {
+            state: state.clone(),
+        };
+        (Self { state }, handle)
+    }
+}
+
+#[cfg(feature = "futures-rs")]
+impl<T> Manual for ManualStream<T> {}
+
+#[cfg(feature = "futures-rs")]
+impl<T> Sealed for ManualStream<T> {
+    fn attach(&self, log: PollLog, index: usize) {
+        lock(&self.state).attached = Some((log, index));
+    }
+}
+
+#[cfg(feature = "futures-rs")]
+impl<T> Stream for ManualStream<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = lock(&self.state);
+        state.poll(cx);
+
+        match state.ready.items.pop_front() {
+            Some(item) => Poll::Ready(Some(item)),
+            None if state.ready.closed => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(feature = "futures-rs")]
+impl<T> fmt::Debug for ManualStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManualStream")
+            .field("polls", &lock(&self.state).polls)
+            .finish()
+    }
+}
+
+/// Items queued up for a [ManualStream].
+#[cfg(feature = "futures-rs")]
+struct Queue<T> {
+    items: VecDeque<T>,
+    /// Whether the stream ends once `items` is empty.
+    closed: bool,
+}
+
+/// Handle used to control a [ManualStream].
+#[cfg(feature = "futures-rs")]
+#[cfg_attr(docsrs, doc(cfg(feature = "futures-rs")))]
+pub struct StreamHandle<T> {
+    state: Arc<Mutex<State<Queue<T>>>>,
+}
+
+#[cfg(feature = "futures-rs")]
+impl<T> StreamHandle<T> {
+    /// Queue up an item to be produced by the stream and wake it.
+    pub fn send(&self, item: T) {
+        let mut state = lock(&self.state);
+        state.ready.items.push_back(item);
+        State::wake(state);
+    }
+
+    /// Close the stream once all queued items have been produced and wake
+    /// it.
+    pub fn close(&self) {
+        let mut state = lock(&self.state);
+        state.ready.closed = true;
+        State::wake(state);
+    }
+
+    /// Wake the stream without producing anything, causing it to be polled
+    /// again.
+    pub fn wake(&self) {
+        State::wake(lock(&self.state));
+    }
+
+    /// The number of times the stream has been polled.
+    pub fn polls(&self) -> usize {
+        lock(&self.state).polls
+    }
+
+    /// Test if the stream has registered a waker which has not yet been
+    /// woken.
+    pub fn is_registered(&self) -> bool {
+        lock(&self.state).waker.is_some()
+    }
+}
+
+/// A parent waker which records how many times it has been woken.
+///
+/// # Examples
+///
+/// ```rust
+/// use unicycle::testing::RecordingWaker;
+///
+/// let recorder = RecordingWaker::new();
+/// let waker = recorder.waker();
+///
+/// waker.wake_by_ref();
+/// waker.wake();
+/// assert_eq!(2, recorder.wakes());
+/// assert_eq!(2, recorder.take_wakes());
+/// assert_eq!(0, recorder.wakes());
+/// ```
+#[derive(Clone, Default)]
+pub struct RecordingWaker {
+    inner: Arc<WakeCounter>,
+}
+
+#[derive(Default)]
+struct WakeCounter {
+    wakes: AtomicUsize,
+}
+
+impl Wake for WakeCounter {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wakes.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl RecordingWaker {
+    /// Construct a new recording waker.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a [Waker] which records its wakeups in this recorder.
+    pub fn waker(&self) -> Waker {
+        Waker::from(self.inner.clone())
+    }
+
+    /// The number of times the waker has been woken.
+    pub fn wakes(&self) -> usize {
+        self.inner.wakes.load(Ordering::SeqCst)
+    }
+
+    /// Take the number of times the waker has been woken, resetting it to
+    /// zero.
+    pub fn take_wakes(&self) -> usize {
+        self.inner.wakes.swap(0, Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for RecordingWaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecordingWaker")
+            .field("wakes", &self.wakes())
+            .finish()
+    }
+}
+
+/// The outcome of a single [Harness::step].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Step<T> {
+    /// The indexes of the children which were polled, in the order they were
+    /// polled.
+    pub polled: Vec<usize>,
+    /// The result of the call to `poll_next`.
+    pub output: Poll<Option<T>>,
+}
+
+/// The item produced by polling an [Unordered] set.
+type Item<T, S, L, B, A> = <Unordered<T, S, L, B, A> as PollNext>::Item;
+
+/// Drives an [Unordered] set one `poll_next` at a time with a
+/// [RecordingWaker], recording which children are polled.
+///
+/// Only polls of children which have been added through [Harness::push] are
+/// recorded.
+///
+/// See the [module level documentation][self] for more.
+///
+/// [Unordered]: crate::Unordered
+pub struct Harness<T, S, L = DefaultLock, B = DefaultBitSet, A = Global>
+where
+    S: Sentinel,
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
+{
+    set: Unordered<T, S, L, B, A>,
+    log: PollLog,
+    waker: RecordingWaker,
+}
+
+impl<T, S, L, B, A> Harness<T, S, L, B, A>
+where
+    S: Sentinel,
+    L: RawLock,
+    B: RawBitSet,
+    A: Allocator,
+{
+    /// Construct a new harness driving the given set.
+    pub fn new(set: Unordered<T, S, L, B, A>) -> Self {
+        Self {
+            set,
+            log: PollLog::default(),
+            waker: RecordingWaker::new(),
+        }
+    }
+
+    /// Push a manual child into the set, returning its index.
+    pub fn push(&mut self, child: T) -> usize
+    where
+        T: Manual,
+    {
+        // NB: we attach after pushing, since nothing is polled while pushing.
+        let index = self.set.push(child);
+
+        if let Some(child) = self.set.slab.get(index) {
+            child.attach(self.log.clone(), index);
+        }
+
+        index
+    }
+
+    /// Perform exactly one `poll_next` of the set.
+    pub fn step(&mut self) -> Step<Item<T, S, L, B, A>>
+    where
+        Unordered<T, S, L, B, A>: PollNext,
+    {
+        self.log.take();
+        let waker = self.waker.waker();
+        let mut cx = Context::from_waker(&waker);
+        let output = PollNext::poll_next(Pin::new(&mut self.set), &mut cx);
+        let polled = self.log.take();
+        Step { polled, output }
+    }
+
+    /// The waker which is passed to the set when stepping.
+    pub fn waker(&self) -> &RecordingWaker {
+        &self.waker
+    }
+
+    /// Access the underlying set.
+    pub fn get_ref(&self) -> &Unordered<T, S, L, B, A> {
+        &self.set
+    }
+
+    /// Access the underlying set mutably.
+    pub fn get_mut(&mut self) -> &mut Unordered<T, S, L, B, A> {
+        &mut self.set
+    }
+
+    /// Consume the harness, returning the underlying set.
+    pub fn into_inner(self) -> Unordered<T, S, L, B, A> {
+        self.set
+    }
+}
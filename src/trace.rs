@@ -0,0 +1,166 @@
+//! Integration with [tracing], enabled through the `tracing` feature.
+//!
+//! When the feature is disabled the types in here are zero-sized and all of
+//! their methods compile down to nothing, so they can be unconditionally
+//! threaded through the polling loop.
+//!
+//! [tracing]: https://docs.rs/tracing
+
+#[cfg(feature = "tracing")]
+pub(crate) use self::enabled::{current, Span, Trace};
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use self::disabled::{current, Span, Trace};
+
+#[cfg(feature = "tracing")]
+mod enabled {
+    use crate::allocator::{Allocator, RawVec};
+    pub(crate) use tracing::Span;
+
+    /// Capture the current span.
+    pub(crate) fn current() -> Span {
+        Span::current()
+    }
+
+    /// Per-child spans and per-cycle bookkeeping.
+    #[derive(Default)]
+    pub(crate) struct Trace {
+        /// The span captured when the child at the corresponding index was
+        /// pushed.
+        spans: RawVec<Option<Span>>,
+        /// Number of indexes drained in the current cycle.
+        drained: usize,
+    }
+
+    impl Trace {
+        pub(crate) fn new() -> Self {
+            Self::default()
+        }
+
+        /// Associate a span with a newly inserted child.
+        pub(crate) fn insert<A>(&mut self, index: usize, span: Span, alloc: &A)
+        where
+            A: Allocator,
+        {
+            if self.spans.len() <= index {
+                self.spans.extend_to(index + 1, || None, alloc);
+            }
+
+            self.spans[index] = Some(span);
+        }
+
+        /// Drop the span associated with a removed child, so that it can be
+        /// closed.
+        pub(crate) fn remove(&mut self, index: usize) {
+            if let Some(span) = self.spans.get_mut(index) {
+                *span = None;
+            }
+        }
+
+        /// Move the span of a child which has been moved to a new index.
+        pub(crate) fn relocate<A>(&mut self, old: usize, new: usize, alloc: &A)
+        where
+            A: Allocator,
+        {
+            let span = self.spans.get_mut(old).and_then(Option::take);
+
+            if let Some(span) = span {
+                self.insert(new, span, alloc);
+            } else {
+                self.remove(new);
+            }
+        }
+
+        /// Drop all spans.
+        pub(crate) fn clear(&mut self) {
+            self.spans.truncate(0);
+        }
+
+        /// Drop all spans and free their storage.
+        ///
+        /// # Safety
+        ///
+        /// `alloc` must be the allocator the spans were inserted with.
+        pub(crate) unsafe fn free<A>(&mut self, alloc: &A)
+        where
+            A: Allocator,
+        {
+            self.spans.free(alloc);
+        }
+
+        /// Record that an index has been drained from the wake set.
+        pub(crate) fn drain(&mut self) {
+            self.drained += 1;
+        }
+
+        /// Emit an event at the end of a polling cycle.
+        pub(crate) fn end_cycle(&mut self) {
+            let drained = core::mem::take(&mut self.drained);
+            tracing::trace!(drained, "polling cycle completed");
+        }
+
+        /// Poll the child at the given index inside of a span carrying its
+        /// index, which is parented to the span captured when it was pushed.
+        pub(crate) fn poll<F, R>(&self, index: usize, f: F) -> R
+        where
+            F: FnOnce() -> R,
+        {
+            let parent = self.spans.get(index).and_then(Option::as_ref);
+
+            let span = match parent {
+                Some(parent) => tracing::trace_span!(parent: parent, "poll", index),
+                None => tracing::trace_span!("poll", index),
+            };
+
+            span.in_scope(f)
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod disabled {
+    pub(crate) struct Span;
+
+    #[inline(always)]
+    pub(crate) fn current() -> Span {
+        Span
+    }
+
+    pub(crate) struct Trace;
+
+    impl Trace {
+        #[inline(always)]
+        pub(crate) fn new() -> Self {
+            Self
+        }
+
+        #[inline(always)]
+        pub(crate) fn insert<A>(&mut self, _: usize, _: Span, _: &A) {}
+
+        #[inline(always)]
+        pub(crate) fn remove(&mut self, _: usize) {}
+
+        #[inline(always)]
+        pub(crate) fn relocate<A>(&mut self, _: usize, _: usize, _: &A) {}
+
+        #[inline(always)]
+        pub(crate) fn clear(&mut self) {}
+
+        #[inline(always)]
+        pub(crate) unsafe fn free<A>(&mut self, _: &A) {}
+
+        #[inline(always)]
+        pub(crate) fn drain(&mut self) {}
+
+        #[inline(always)]
+        pub(crate) fn end_cycle(&mut self) {}
+
+        #[inline(always)]
+        pub(crate) fn poll<F, R>(&self, _: usize, f: F) -> R
+        where
+            F: FnOnce() -> R,
+        {
+            f()
+        }
+    }
+}
@@ -1,11 +1,16 @@
-use crate::lock::{LockExclusiveGuard, LockSharedGuard, RwLock};
-use std::hint;
-use std::sync::atomic::{AtomicPtr, Ordering};
-use uniset::{AtomicBitSet, BitSet};
+use crate::allocator::{self, Allocator, RawVec};
+use crate::bitset::RawBitSet;
+use crate::lock::{LockExclusiveGuard, LockSharedGuard, RawLock, RwLock};
+use crate::sync::{self, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use core::ptr::{self, NonNull};
 
 /// A wake set which allows us to immutably set an index.
-pub(crate) struct WakeSet {
-    set: AtomicBitSet,
+pub(crate) struct WakeSet<L, B>
+where
+    L: RawLock,
+    B: RawBitSet,
+{
+    set: Woken<B>,
     /// Read locks are held every time someone manipulates the underlying set,
     /// we then (briefly) acquire a write lock to get unique access, after we
     /// have swapped out the wake set pointer.
@@ -15,26 +20,50 @@ pub(crate) struct WakeSet {
     /// appropriate. I.e. we don't want to keep track of a lock guard, but we
     /// still have a region of operation where we want to consider the wake set
     /// as exclusively owned.
-    lock: RwLock,
+    lock: RwLock<L>,
 }
 
-impl WakeSet {
+impl<L, B> WakeSet<L, B>
+where
+    L: RawLock,
+    B: RawBitSet,
+{
     pub(crate) fn new() -> Self {
         Self {
-            set: AtomicBitSet::new(),
+            set: Woken::new(),
             lock: RwLock::new(),
         }
     }
 
     pub(crate) fn locked() -> Self {
         Self {
-            set: AtomicBitSet::new(),
+            set: Woken::new(),
             lock: RwLock::locked(),
         }
     }
 
-    pub(crate) fn new_raw() -> *mut Self {
-        Box::into_raw(Box::new(Self::new()))
+    /// Move the wake set into memory allocated by the given allocator.
+    pub(crate) fn into_raw<A>(self, alloc: &A) -> *mut Self
+    where
+        A: Allocator,
+    {
+        allocator::new_box(self, alloc).as_ptr()
+    }
+
+    /// Drop a wake set constructed through [WakeSet::into_raw], including the
+    /// storage of its set.
+    ///
+    /// # Safety
+    ///
+    /// `this` must have been constructed through [WakeSet::into_raw] with an
+    /// equivalent allocator, the set must only have been grown through it,
+    /// and nothing else may be accessing it.
+    pub(crate) unsafe fn drop_raw<A>(this: *mut Self, alloc: &A)
+    where
+        A: Allocator,
+    {
+        Self::as_mut_set(this).free(alloc);
+        allocator::drop_box(NonNull::new_unchecked(this), alloc);
     }
 
     /// Try to lock the current thread until we have unique access.
@@ -44,7 +73,7 @@ impl WakeSet {
 
     pub(crate) fn lock_exclusive(&self) {
         while !self.try_lock_exclusive() {
-            hint::spin_loop();
+            sync::spin_loop();
         }
     }
 
@@ -52,66 +81,526 @@ impl WakeSet {
         self.lock.unlock_exclusive_immediate();
     }
 
-    /// Lock interest in reading.
-    pub(crate) fn try_lock_shared(&self) -> Option<LockSharedGuard<'_>> {
-        self.lock.try_lock_shared()
+    /// Try to set the given index in the wake set behind `this`.
+    ///
+    /// Returns `false` if the wake set is locked exclusively.
+    ///
+    /// # Safety
+    ///
+    /// `this` must point to a live wake set. We only access the lock until a
+    /// shared lock is held, since the owner might be modifying the set
+    /// concurrently.
+    unsafe fn try_set(this: *const Self, index: usize) -> bool {
+        let lock = &*ptr::addr_of!((*this).lock);
+
+        if let Some(_guard) = lock.try_lock_shared() {
+            let set = &*ptr::addr_of!((*this).set);
+
+            // An index past the capacity can only come from a waker which
+            // raced with the set being truncated by compaction, so it no
+            // longer belongs to any child.
+            if index < set.capacity() {
+                set.set_shared(index);
+            }
+
+            return true;
+        }
+
+        false
     }
 
-    /// Set the given index in the referenced bitset.
-    pub(crate) fn set(&self, index: usize) {
-        self.set.set(index);
+    /// Access the set of woken indexes immutably.
+    ///
+    /// # Safety
+    ///
+    /// `this` must point to a live wake set, and the caller must ensure that
+    /// nothing modifies the set other than through
+    /// [set_shared][RawBitSet::set_shared], which is the case while a shared
+    /// lock is held or while the owner holds on to it as its alternate set.
+    pub(crate) unsafe fn as_set<'a>(this: *const Self) -> &'a Woken<B> {
+        &*ptr::addr_of!((*this).set)
     }
 
-    /// Treat the bitset as a local, mutable BitSet.
+    /// Access the set of woken indexes mutably.
+    ///
+    /// This only borrows the set and not the lock, since other threads might
+    /// concurrently try to lock it.
+    ///
+    /// # Safety
     ///
     /// Caller must ensure that they have unique access to the atomic bit set by
     /// only using this while an exclusive lock is held through
     /// `lock_exclusive`.
-    pub(crate) fn as_mut_set(&mut self) -> &mut BitSet {
-        self.set.as_local_mut()
+    pub(crate) unsafe fn as_mut_set<'a>(this: *mut Self) -> &'a mut Woken<B> {
+        &mut *ptr::addr_of_mut!((*this).set)
     }
 }
 
-pub(crate) struct SharedWakeSet {
-    wake_set: AtomicPtr<WakeSet>,
-    prevent_drop_lock: RwLock,
+/// A bit set of woken indexes, optionally paired with a bounded record of the
+/// order in which they were woken.
+///
+/// An index is only recorded when its bit goes from unset to set, so it appears
+/// at most once in the record until it has been drained.
+pub(crate) struct Woken<B> {
+    set: B,
+    order: WakeOrderQueue,
 }
 
-impl SharedWakeSet {
-    /// Construct a new shared wake set.
+/// Move the wakeups of every child which was moved by compaction from its
+/// old index to its new one in either set, returning `true` if any were moved.
+///
+/// Any wakeups for the new index were left behind by a child that has been
+/// removed, so they are discarded.
+pub(crate) fn compact<B, A>(
+    active: &mut Woken<B>,
+    alternate: &mut Woken<B>,
+    moved: &[(usize, usize)],
+    alloc: &A,
+) -> bool
+where
+    B: RawBitSet,
+    A: Allocator,
+{
+    let mut woken = false;
+
+    for &(old, new) in moved {
+        active.take(new);
+        alternate.take(new);
+
+        // NB: Both sets need to be cleared, so this must not short-circuit.
+        if active.take(old) | alternate.take(old) {
+            alternate.set(new, alloc);
+            woken = true;
+        }
+    }
+
+    woken
+}
+
+impl<B> Woken<B>
+where
+    B: RawBitSet,
+{
     pub(crate) fn new() -> Self {
         Self {
-            wake_set: AtomicPtr::new(WakeSet::new_raw()),
+            set: B::new(),
+            order: WakeOrderQueue::new(),
+        }
+    }
+
+    /// Get the number of indexes the set can hold without growing.
+    pub(crate) fn capacity(&self) -> usize {
+        self.set.capacity()
+    }
+
+    /// Make sure that the set can hold at least `cap` indexes.
+    pub(crate) fn reserve<A>(&mut self, cap: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        self.set.reserve(cap, alloc);
+    }
+
+    /// Shrink the set to hold `cap` indexes, clearing every index past it.
+    pub(crate) fn truncate<A>(&mut self, cap: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        self.set.truncate(cap, alloc);
+    }
+
+    /// Set the given index, growing the set if needed.
+    pub(crate) fn set<A>(&mut self, index: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        if self.set.set(index, alloc) {
+            self.order.push(index);
+        }
+    }
+
+    /// Set the given index through a shared reference.
+    fn set_shared(&self, index: usize) {
+        // Only the wakeup which sets the bit is recorded, so an index which is
+        // woken repeatedly only takes up one slot and keeps its place.
+        if self.set.set_shared(index) {
+            self.order.push(index);
+        }
+    }
+
+    /// Test if the given index is set.
+    pub(crate) fn test(&self, index: usize) -> bool {
+        self.set.test(index)
+    }
+
+    /// Clear the given index, returning `true` if it was set.
+    ///
+    /// If the index has been recorded in wake order, the record is skipped
+    /// once it's drained.
+    pub(crate) fn take(&mut self, index: usize) -> bool {
+        self.set.take(index)
+    }
+
+    /// Iterate over every index which is set in ascending order, without
+    /// clearing them.
+    pub(crate) fn iter(&self) -> B::Iter<'_> {
+        self.set.iter()
+    }
+
+    /// Test if no indexes are set.
+    ///
+    /// If so, every recorded index has already been drained, so the record of
+    /// wake order is reset as well.
+    pub(crate) fn is_empty(&mut self) -> bool {
+        if !self.set.is_empty() {
+            return false;
+        }
+
+        self.order.clear();
+        true
+    }
+
+    /// Clear every index and the record of wake order.
+    pub(crate) fn clear(&mut self) {
+        for _ in self.set.drain() {}
+        self.order.clear();
+    }
+
+    /// Set the number of wakeups which are recorded in order, discarding the
+    /// current record.
+    ///
+    /// Indexes which are set remain set, so they will be drained in index
+    /// order instead.
+    pub(crate) fn set_order_capacity<A>(&mut self, capacity: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        self.order.resize(capacity, alloc);
+    }
+
+    /// Release the storage of the set and the record of wake order.
+    ///
+    /// # Safety
+    ///
+    /// `alloc` must be equivalent to the allocator every other operation on
+    /// the set has been performed with.
+    pub(crate) unsafe fn free<A>(&mut self, alloc: &A)
+    where
+        A: Allocator,
+    {
+        self.set.free(alloc);
+        self.order.slots.free(alloc);
+    }
+
+    /// Drain the set, first yielding recorded indexes in the order in which
+    /// they were woken, followed by any remaining indexes in ascending order.
+    ///
+    /// Each index is yielded at most once. If the iterator is dropped early the
+    /// remaining indexes are left in the set.
+    pub(crate) fn drain(&mut self) -> Drain<'_, B> {
+        Drain {
+            order: &mut self.order,
+            set: Some(&mut self.set),
+            rest: None,
+        }
+    }
+}
+
+/// A draining iterator over [Woken] indexes.
+pub(crate) struct Drain<'a, B>
+where
+    B: RawBitSet,
+{
+    order: &'a mut WakeOrderQueue,
+    set: Option<&'a mut B>,
+    rest: Option<B::Drain<'a>>,
+}
+
+impl<B> Iterator for Drain<'_, B>
+where
+    B: RawBitSet,
+{
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(set) = self.set.take() {
+            while let Some(index) = self.order.pop() {
+                // Only yield indexes which are still set, in case the bit has
+                // been cleared without going through the record.
+                if set.take(index) {
+                    self.set = Some(set);
+                    return Some(index);
+                }
+            }
+
+            // Wakeups which didn't fit in the record are still in the set.
+            self.order.clear();
+            self.rest = Some(set.drain());
+        }
+
+        self.rest.as_mut()?.next()
+    }
+}
+
+/// A bounded record of the order in which indexes were woken.
+///
+/// Wakers push to it while holding a shared lock on the wake set it belongs to.
+/// Once it's full further wakeups are only recorded in the bit set. Everything
+/// else is done by the unordered set while it has exclusive access.
+struct WakeOrderQueue {
+    slots: RawVec<AtomicUsize>,
+    /// The number of pushed indexes, which might exceed the number of slots.
+    tail: AtomicUsize,
+    /// The next slot to pop.
+    head: usize,
+}
+
+impl WakeOrderQueue {
+    fn new() -> Self {
+        Self {
+            slots: RawVec::new(),
+            tail: AtomicUsize::new(0),
+            head: 0,
+        }
+    }
+
+    /// Change the number of slots, discarding all recorded indexes if it
+    /// changed.
+    fn resize<A>(&mut self, capacity: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        if self.slots.len() != capacity {
+            self.slots.truncate(capacity);
+            self.slots.shrink_to_fit(alloc);
+            self.slots
+                .extend_to(capacity, || AtomicUsize::new(0), alloc);
+            self.clear();
+        }
+    }
+
+    /// Push an index, unless the queue is full.
+    fn push(&self, index: usize) {
+        // Ordering: Like the bit set, we rely on external synchronization when
+        // reading the queue.
+        //
+        // Checking before incrementing keeps `tail` from growing without bound
+        // once the queue is full.
+        if self.tail.load(Ordering::Relaxed) >= self.slots.len() {
+            return;
+        }
+
+        let n = self.tail.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(slot) = self.slots.get(n) {
+            slot.store(index, Ordering::Relaxed);
+        }
+    }
+
+    /// Pop the next index in the order it was pushed.
+    fn pop(&mut self) -> Option<usize> {
+        let len = self.tail.load(Ordering::Relaxed).min(self.slots.len());
+
+        if self.head >= len {
+            return None;
+        }
+
+        let index = self.slots[self.head].load(Ordering::Relaxed);
+        self.head += 1;
+        Some(index)
+    }
+
+    /// Discard all recorded indexes.
+    fn clear(&mut self) {
+        self.head = 0;
+        self.tail.store(0, Ordering::Relaxed);
+    }
+}
+
+/// The active wake set, shared with all wakers.
+///
+/// Its allocations are released through [SharedWakeSet::free], since they need
+/// the allocator they were made with.
+pub(crate) struct SharedWakeSet<L, B>
+where
+    L: RawLock,
+    B: RawBitSet,
+{
+    wake_set: AtomicPtr<WakeSet<L, B>>,
+    prevent_drop_lock: RwLock<L>,
+    /// Set if a wakeup could not be registered in the active wake set, in
+    /// which case it's recorded in the pending flag of its waker cell instead.
+    pending: AtomicBool,
+}
+
+impl<L, B> SharedWakeSet<L, B>
+where
+    L: RawLock,
+    B: RawBitSet,
+{
+    /// Construct a new shared wake set, where the active wake set is
+    /// allocated using the given allocator.
+    pub(crate) fn new<A>(alloc: &A) -> Self
+    where
+        A: Allocator,
+    {
+        Self {
+            wake_set: AtomicPtr::new(WakeSet::new().into_raw(alloc)),
             prevent_drop_lock: RwLock::new(),
+            pending: AtomicBool::new(false),
         }
     }
 
     /// Swap the current pointer with another.
-    pub(crate) fn swap(&self, other: *mut WakeSet) -> *mut WakeSet {
+    pub(crate) fn swap(&self, other: *mut WakeSet<L, B>) -> *mut WakeSet<L, B> {
         self.wake_set.swap(other, Ordering::AcqRel)
     }
 
-    /// Register wakeup for the specified index.
-    pub(crate) fn wake(&self, index: usize) {
-        // We need to spin here, since the wake set might be swapped out while we
-        // are trying to update it.
-        while !self.try_wake(index) {}
+    /// Register wakeup for the specified index, given the pending flag of its
+    /// waker cell.
+    ///
+    /// This never spins. If the index can't be set in the active wake set
+    /// because we're racing with the unordered set swapping or dropping its
+    /// wake sets, the wakeup is recorded in the pending flag instead, which is
+    /// taken by the unordered set the next time it swaps its wake sets.
+    pub(crate) fn wake(&self, index: usize, pending: &AtomicBool) {
+        if !self.try_wake(index) {
+            // Ordering: Pairs with the acquire in `take_pending`, so that the
+            // flag of the cell is observed by whoever observes this one.
+            pending.store(true, Ordering::Relaxed);
+            self.pending.store(true, Ordering::Release);
+        }
+    }
+
+    /// Test if any wakeups have been recorded as pending since the last time
+    /// this was called, in which case they need to be taken from the waker
+    /// cells.
+    pub(crate) fn take_pending(&self) -> bool {
+        // Fast path: Avoid the read-modify-write if nothing is pending.
+        self.pending.load(Ordering::Relaxed) && self.pending.swap(false, Ordering::Acquire)
+    }
+
+    /// Move the wakeups of every child which was moved by compaction from its
+    /// old index to its new one, and truncate both wake sets to `cap`
+    /// indexes.
+    ///
+    /// Returns `true` if any wakeups were moved.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the unordered set which owns this wake set, with
+    /// exclusive access to its alternate set and after taking pending wakeups,
+    /// and `alloc` must be the allocator this wake set was constructed with.
+    pub(crate) unsafe fn compact<A>(
+        &self,
+        alternate: &mut Woken<B>,
+        moved: &[(usize, usize)],
+        cap: usize,
+        alloc: &A,
+    ) -> bool
+    where
+        A: Allocator,
+    {
+        self.with_active(|active| {
+            let woken = compact(active, alternate, moved, alloc);
+            active.truncate(cap, alloc);
+            alternate.truncate(cap, alloc);
+            woken
+        })
+    }
+
+    /// Clear every index in the active wake set.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the unordered set which owns this wake set.
+    pub(crate) unsafe fn clear(&self) {
+        self.with_active(|set| set.clear());
+    }
+
+    /// Test if the given index is set in the active wake set.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the unordered set which owns this wake set, and
+    /// not while it's swapping or dropping its wake sets.
+    pub(crate) unsafe fn is_woken(&self, index: usize) -> bool {
+        self.with_active_shared(|set| set.test(index))
+    }
+
+    /// Call `f` with every index which is set in the active wake set, in no
+    /// particular order.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the unordered set which owns this wake set, and
+    /// not while it's swapping or dropping its wake sets.
+    pub(crate) unsafe fn for_each_woken(&self, f: impl FnMut(usize)) {
+        self.with_active_shared(|set| set.iter().for_each(f));
+    }
+
+    /// Access the active wake set while holding an exclusive lock on it.
+    ///
+    /// Wakers which race with us fail to lock it, so they record their
+    /// wakeups as pending instead.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the unordered set which owns this wake set, and
+    /// not while it's swapping or dropping its wake sets.
+    unsafe fn with_active<R>(&self, f: impl FnOnce(&mut Woken<B>) -> R) -> R {
+        let wake_set = self.wake_set.load(Ordering::Acquire);
+        debug_assert!(!wake_set.is_null());
+
+        (*wake_set).lock_exclusive();
+        let output = f(WakeSet::as_mut_set(wake_set));
+        (*wake_set).unlock_exclusive();
+        output
+    }
+
+    /// Read the active wake set while holding a shared lock on it.
+    ///
+    /// Wakers hold the same kind of lock while setting indexes, so neither
+    /// side blocks the other. Indexes which are set concurrently might or
+    /// might not be observed.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the unordered set which owns this wake set, and
+    /// not while it's swapping or dropping its wake sets.
+    unsafe fn with_active_shared<R>(&self, f: impl FnOnce(&Woken<B>) -> R) -> R {
+        let wake_set = self.wake_set.load(Ordering::Acquire);
+        debug_assert!(!wake_set.is_null());
+
+        let lock = &*ptr::addr_of!((*wake_set).lock);
+
+        // Only the owner locks the wake set exclusively, and it isn't doing so
+        // while we're reading it. So this only fails spuriously, if at all.
+        let _guard = loop {
+            if let Some(guard) = lock.try_lock_shared() {
+                break guard;
+            }
+
+            sync::spin_loop();
+        };
+
+        f(WakeSet::as_set(wake_set))
     }
 
     /// Prevent that the pointer is being written to while this guard is being
     /// held. This makes sure there are no readers in the critical section that
     /// might read an invalid wake set while it's being deallocated.
-    pub(crate) fn prevent_drop_write(&self) -> LockExclusiveGuard<'_> {
+    pub(crate) fn prevent_drop_write(&self) -> LockExclusiveGuard<'_, L> {
         loop {
             if let Some(guard) = self.prevent_drop_lock.try_lock_exclusive_guard() {
                 return guard;
             }
 
-            hint::spin_loop();
+            sync::spin_loop();
         }
     }
 
-    fn try_prevent_drop_read(&self) -> Option<LockSharedGuard<'_>> {
+    fn try_prevent_drop_read(&self) -> Option<LockSharedGuard<'_, L>> {
         self.prevent_drop_lock.try_lock_shared()
     }
 
@@ -130,40 +619,55 @@ impl SharedWakeSet {
             None => return false,
         };
 
-        let wake_set = self.wake_set.load(Ordering::Acquire);
-        debug_assert!(!wake_set.is_null());
+        let mut wake_set = self.wake_set.load(Ordering::Acquire);
 
-        // Safety: We know wake_set references valid memory, because in order to
-        // have access to `SharedWakeSet`, we must also hold an `Arc` to it - either
-        // through a reference or by it being stored in `Internals`.
-        //
-        // There is however a short window in which the wake set has been swapped in
-        // `Unordered`, but at this point it is not possible for it to be
-        // invalidated. This can only happen if `Unordered` is dropped, and this
-        // does not happen while it's being polled.
-        let wake_set = unsafe { &*wake_set };
+        // If we fail to lock the wake set we've loaded, it's because it has
+        // just been swapped out and is being locked by the unordered set. In
+        // that case the freshly swapped in set is worth one more attempt.
+        for _ in 0..2 {
+            debug_assert!(!wake_set.is_null());
 
-        let _guard2 = match wake_set.try_lock_shared() {
-            Some(guard) => guard,
-            None => return false,
-        };
+            // Safety: We know wake_set references valid memory, because in
+            // order to have access to `SharedWakeSet`, we must also hold an
+            // `Arc` to it - either through a reference or by it being stored in
+            // `Internals`.
+            //
+            // There is however a short window in which the wake set has been
+            // swapped in `Unordered`, but at this point it is not possible for
+            // it to be invalidated. This can only happen if `Unordered` is
+            // dropped, which is prevented by the guard above.
+            if unsafe { WakeSet::try_set(wake_set, index) } {
+                return true;
+            }
 
-        wake_set.set(index);
-        true
+            let current = self.wake_set.load(Ordering::Acquire);
+
+            if current == wake_set {
+                break;
+            }
+
+            wake_set = current;
+        }
+
+        false
     }
-}
 
-impl Drop for SharedWakeSet {
-    fn drop(&mut self) {
+    /// Free the active wake set.
+    ///
+    /// # Safety
+    ///
+    /// At this point there must be no other ways to access the shared wake
+    /// set, so we're not racing against someone trying to call wake. Nor are
+    /// we racing against `Unordered` dropping the wake set since this is the
+    /// active set which has been swapped in exclusively. `alloc` must be the
+    /// allocator this wake set was constructed with, and the wake set must not
+    /// be used again.
+    pub(crate) unsafe fn free<A>(&mut self, alloc: &A)
+    where
+        A: Allocator,
+    {
         let wake_set = self.wake_set.load(Ordering::Acquire);
         debug_assert!(!wake_set.is_null());
-
-        // Safety: At this point, there are no other ways to access the
-        // `SharedWakeSet`, so we are not racing against someone trying to call
-        // wake. Nor are we racing against `Unordered` dropping the wake set
-        // since this is the active set which has been swapped in exclusively.
-        unsafe {
-            drop(Box::from_raw(wake_set));
-        }
+        WakeSet::drop_raw(wake_set, alloc);
     }
 }
@@ -1,104 +1,520 @@
 //! Wake plumbing for unicycle.
 //!
-//! We provide two different forms of wakers:
+//! Every index has a `WakerCell` with a stable address, and all wakers handed
+//! to a child point to the cell for its index. This gives the wakers of a
+//! child a stable identity across polls, so that [Waker::will_wake] works as
+//! intended, and it means that cloning a waker only needs to bump the
+//! reference count of the shared data. Cells also hold the wake statistics of
+//! their index, so that wakers can update them without locking, and a flag
+//! which records a wakeup that couldn't be registered in the active wake set
+//! without allocating.
 //!
-//! * `Internals` - which takes full ownership of the plumbing necessary to
-//!   wake the task from another thread.
+//! When the collection is compacted the cells of the indexes which were
+//! vacated are retired. Wakers which still point to a retired cell are
+//! forwarded to the cell of the child which used to own it, or do nothing if
+//! that child is gone, and the cell is freed once the last such waker has
+//! been dropped.
+//!
+//! The cells are generic over the shared data they point to, which decides
+//! what waking an index means through [WakeIndex]. This way they are used both
+//! by the thread-safe and the local collections.
 
-use crate::{lock::RwLock, Shared};
-use std::{
+use crate::{
+    allocator::{self, Allocator, RawVec},
+    lock::{RawLock, RwLock},
+    stats::WakeEntry,
+    sync::{self, Arc, AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+use core::{
     cell::UnsafeCell,
-    mem, ptr,
-    sync::Arc,
+    mem, ptr, slice,
     task::{Context, RawWaker, RawWakerVTable, Waker},
 };
 
-/// Wrap the current context in one that updates the local WakeSet.
-/// This takes the shared data by reference and reuses the `INTERNALS_VTABLE`.
-///
-/// It works because we don't drop the waker inside of this function.
-pub(crate) fn poll_with_ref<F, R>(shared: &Arc<Shared>, index: usize, f: F) -> R
-where
-    F: FnOnce(&mut Context<'_>) -> R,
-{
-    // Need to assigned owned a fixed location, so do not move it from here for the duration of the poll.
-    let internals = Internals::new(&**shared as *const Shared, index);
+/// Number of waker cells allocated at a time.
+const CHUNK_SIZE: usize = 64;
 
-    let waker = RawWaker::new(&internals as *const _ as *const (), INTERNALS_VTABLE);
-    let waker = mem::ManuallyDrop::new(unsafe { Waker::from_raw(waker) });
-    let mut cx = Context::from_waker(&*waker);
-    f(&mut cx)
+/// Shared data of a collection, which the waker cells of its children point
+/// to.
+///
+/// Every owned waker holds a strong reference to the shared data through an
+/// [Arc], so wakers can be cloned and dropped on any thread.
+pub(crate) trait WakeIndex: Sized {
+    /// Wake the child at the given index, given the wake statistics and the
+    /// pending flag of its cell.
+    ///
+    /// This might be called from any thread.
+    fn wake_index(&self, index: usize, stats: &WakeEntry, pending: &AtomicBool);
 }
 
-static INTERNALS_VTABLE: &RawWakerVTable = &RawWakerVTable::new(
-    Internals::clone,
-    Internals::wake,
-    Internals::wake_by_ref,
-    Internals::drop,
-);
-
-struct Internals {
-    shared: *const Shared,
+/// A waker for a single index.
+///
+/// Every owned waker which points to a cell holds a strong reference to the
+/// shared data, which in turn owns the cell, and is counted in `wakers`. So
+/// the cell is guaranteed to be alive as long as there are wakers referencing
+/// it, even after it has been retired. The waker used while polling borrows
+/// the shared data instead.
+struct WakerCell<H> {
+    shared: *const H,
     index: usize,
+    stats: WakeEntry,
+    /// The number of owned wakers referencing this cell, including retired
+    /// cells which forward to it.
+    wakers: AtomicUsize,
+    /// Set if a wakeup of the index couldn't be registered in the active wake
+    /// set, until the owner takes it.
+    pending: AtomicBool,
+    /// Set once the cell has been retired by compaction.
+    retired: AtomicBool,
+    /// The cell that wakeups are forwarded to once retired, or null if they
+    /// are discarded.
+    forward: AtomicPtr<WakerCell<H>>,
 }
 
-impl Internals {
-    /// Construct a new waker.
-    fn new(shared: *const Shared, index: usize) -> Self {
-        Self { shared, index }
+impl<H> WakerCell<H>
+where
+    H: WakeIndex,
+{
+    const VTABLE: &'static RawWakerVTable =
+        &RawWakerVTable::new(Self::clone, Self::wake, Self::wake_by_ref, Self::drop);
+
+    fn new(shared: *const H, index: usize) -> Self {
+        Self {
+            shared,
+            index,
+            stats: WakeEntry::new(),
+            wakers: AtomicUsize::new(0),
+            pending: AtomicBool::new(false),
+            retired: AtomicBool::new(false),
+            forward: AtomicPtr::new(ptr::null_mut()),
+        }
     }
 
     unsafe fn clone(this: *const ()) -> RawWaker {
         let this = &(*(this as *const Self));
-        let s1 = mem::ManuallyDrop::new(Arc::from_raw(this.shared));
-        #[allow(clippy::redundant_clone)]
-        let s2 = s1.clone();
-        let index = this.index;
-        let waker = Box::into_raw(Box::new(Internals::new(&**s2 as *const Shared, index)));
-        RawWaker::new(waker as *const (), INTERNALS_VTABLE)
+        // Ordering: A new waker can only be cloned from one which is already
+        // counted, or from the waker used while polling, so like with `Arc`
+        // this doesn't need to synchronize with anything.
+        this.wakers.fetch_add(1, Ordering::Relaxed);
+        Arc::increment_strong_count(this.shared);
+        RawWaker::new(this as *const Self as *const (), Self::VTABLE)
     }
 
     unsafe fn wake(this: *const ()) {
-        // Note: this will never be called when it's passed by ref.
-        let this = Box::from_raw(this as *mut Self);
-        let shared = &(*this.shared);
-        shared.wake_set.wake(this.index);
-        shared.waker.wake_by_ref();
-        drop(Arc::from_raw(this.shared));
+        Self::wake_by_ref(this);
+        Self::drop(this);
     }
 
     unsafe fn wake_by_ref(this: *const ()) {
-        let this = &(*(this as *const Self));
+        let mut this = &(*(this as *const Self));
+
+        // Ordering: Pairs with the release when the cell is retired, so that
+        // we observe where it forwards to.
+        while this.retired.load(Ordering::Acquire) {
+            let forward = this.forward.load(Ordering::Relaxed);
+
+            if forward.is_null() {
+                return;
+            }
+
+            // Safety: A retired cell counts as a waker of the cell it forwards
+            // to, so it's kept alive for as long as this one is.
+            this = &*forward;
+        }
+
         let shared = &(*this.shared);
-        shared.wake_set.wake(this.index);
-        shared.waker.wake_by_ref();
+        shared.wake_index(this.index, &this.stats, &this.pending);
     }
 
     unsafe fn drop(this: *const ()) {
-        let this = Box::from_raw(this as *mut Self);
-        drop(Arc::from_raw(this.shared));
+        let this = &(*(this as *const Self));
+        // NB: A retired cell might be freed as soon as the count is released,
+        // so the shared data has to be read before that.
+        let shared = this.shared;
+        // Ordering: Pairs with the acquire in `WakerCells::sweep`, so that all
+        // uses of the cell happen before it's freed.
+        this.wakers.fetch_sub(1, Ordering::Release);
+        Arc::decrement_strong_count(shared);
     }
 }
 
-pub(crate) struct SharedWaker {
-    lock: RwLock,
-    waker: UnsafeCell<Waker>,
+/// Storage for waker cells, one for each index.
+///
+/// Cells are allocated in fixed-size chunks which are never moved, so wakers
+/// can reference them directly. Chunks are only removed when the collection
+/// is compacted, at which point they are retired and kept around until no
+/// wakers reference them any longer.
+pub(crate) struct WakerCells<H> {
+    chunks: UnsafeCell<RawVec<ptr::NonNull<WakerCell<H>>>>,
+    /// Chunks which have been retired, but are still referenced by wakers.
+    retired: UnsafeCell<RawVec<ptr::NonNull<WakerCell<H>>>>,
 }
 
-impl SharedWaker {
+// Safety: The collections of chunks are only accessed by the owning unordered
+// set, and the cells themselves are immutable once they have been allocated
+// except for their atomic fields.
+unsafe impl<H> Send for WakerCells<H> {}
+unsafe impl<H> Sync for WakerCells<H> {}
+
+impl<H> WakerCells<H>
+where
+    H: WakeIndex,
+{
+    /// Construct new empty storage.
+    pub(crate) fn new() -> Self {
+        Self {
+            chunks: UnsafeCell::new(RawVec::new()),
+            retired: UnsafeCell::new(RawVec::new()),
+        }
+    }
+
+    /// Get the number of indexes which have a cell allocated.
+    ///
+    /// # Safety
+    ///
+    /// Caller must make sure that this is not called concurrently with
+    /// [WakerCells::reserve] or [WakerCells::compact].
+    pub(crate) unsafe fn capacity(&self) -> usize {
+        let chunks = &*self.chunks.get();
+        chunks.len() * CHUNK_SIZE
+    }
+
+    /// Make sure that there is a cell allocated for the given index.
+    ///
+    /// # Safety
+    ///
+    /// Caller must have exclusive access to the unordered set which owns this
+    /// storage, `shared` must point to the shared data which contains it and
+    /// be kept alive through an [Arc], and `alloc` must be the allocator this
+    /// storage is always used with.
+    pub(crate) unsafe fn reserve<A>(&self, shared: *const H, index: usize, alloc: &A)
+    where
+        A: Allocator,
+    {
+        let chunks = &mut *self.chunks.get();
+
+        while chunks.len() <= index / CHUNK_SIZE {
+            let base = chunks.len() * CHUNK_SIZE;
+            let chunk =
+                allocator::new_slice(CHUNK_SIZE, |n| WakerCell::new(shared, base + n), alloc);
+            chunks.push(chunk, alloc);
+        }
+    }
+
+    /// Get the cell for the given index.
+    ///
+    /// # Safety
+    ///
+    /// The cell must have been reserved through [WakerCells::reserve], and the
+    /// caller must make sure that this is not called concurrently with it.
+    unsafe fn get(&self, index: usize) -> *const WakerCell<H> {
+        let chunks = &*self.chunks.get();
+        debug_assert!(index / CHUNK_SIZE < chunks.len());
+        chunks[index / CHUNK_SIZE].as_ptr().add(index % CHUNK_SIZE)
+    }
+
+    /// Get the wake statistics for the given index.
+    ///
+    /// # Safety
+    ///
+    /// Same as [WakerCells::get].
+    pub(crate) unsafe fn stats(&self, index: usize) -> &WakeEntry {
+        &(*self.get(index)).stats
+    }
+
+    /// Get the pending flag for the given index.
+    ///
+    /// # Safety
+    ///
+    /// Same as [WakerCells::get].
+    pub(crate) unsafe fn pending(&self, index: usize) -> &AtomicBool {
+        &(*self.get(index)).pending
+    }
+
+    /// Test if a wakeup is pending for the given index, which might not have a
+    /// cell.
+    ///
+    /// # Safety
+    ///
+    /// Caller must make sure that this is not called concurrently with
+    /// [WakerCells::reserve] or [WakerCells::compact].
+    pub(crate) unsafe fn is_pending(&self, index: usize) -> bool {
+        index < self.capacity() && self.pending(index).load(Ordering::Relaxed)
+    }
+
+    /// Take every pending wakeup, calling `f` with its index.
+    ///
+    /// This visits every cell, so it should only be done once wakeups are
+    /// known to be pending.
+    ///
+    /// # Safety
+    ///
+    /// Caller must make sure that this is not called concurrently with
+    /// [WakerCells::reserve] or [WakerCells::compact].
+    pub(crate) unsafe fn take_pending(&self, mut f: impl FnMut(usize)) {
+        self.for_each_cell(|cell| {
+            if cell.pending.load(Ordering::Relaxed) && cell.pending.swap(false, Ordering::Relaxed) {
+                f(cell.index);
+            }
+        });
+    }
+
+    /// Call `f` with the index of every pending wakeup, without taking them.
+    ///
+    /// # Safety
+    ///
+    /// Same as [WakerCells::take_pending].
+    pub(crate) unsafe fn for_each_pending(&self, mut f: impl FnMut(usize)) {
+        self.for_each_cell(|cell| {
+            if cell.pending.load(Ordering::Relaxed) {
+                f(cell.index);
+            }
+        });
+    }
+
+    /// Call `f` with every cell which hasn't been retired.
+    unsafe fn for_each_cell(&self, mut f: impl FnMut(&WakerCell<H>)) {
+        let chunks = &*self.chunks.get();
+
+        for chunk in chunks.iter() {
+            slice::from_raw_parts(chunk.as_ptr(), CHUNK_SIZE)
+                .iter()
+                .for_each(&mut f);
+        }
+    }
+
+    /// Poll with a waker that references the cell for the given index, without
+    /// taking ownership of the shared data.
+    ///
+    /// It works because we don't drop the waker inside of this function.
+    ///
+    /// # Safety
+    ///
+    /// Same as [WakerCells::get].
+    pub(crate) unsafe fn poll_with_ref<F, R>(&self, index: usize, f: F) -> R
+    where
+        F: FnOnce(&mut Context<'_>) -> R,
+    {
+        let cell = self.get(index);
+        let waker = RawWaker::new(cell as *const (), WakerCell::<H>::VTABLE);
+        let waker = mem::ManuallyDrop::new(Waker::from_raw(waker));
+        let mut cx = Context::from_waker(&waker);
+        f(&mut cx)
+    }
+
+    /// Retire the cells of indexes which were vacated by compacting the
+    /// collection down to `len` children, given the old and the new index of
+    /// every child which was moved.
+    ///
+    /// Chunks past the compacted length are released, and chunks which are
+    /// kept are replaced if wakers still reference one of their vacated
+    /// indexes. Wakers referencing a retired cell are forwarded to the current
+    /// cell of the child which owned it, if any. `relocate` is called with the
+    /// wake statistics of every cell whose child changed cells, so that they
+    /// can be moved along.
+    ///
+    /// # Safety
+    ///
+    /// Same as [WakerCells::reserve], and cells must have been reserved for
+    /// every child.
+    pub(crate) unsafe fn compact<A>(
+        &self,
+        shared: *const H,
+        len: usize,
+        moved: &[(usize, usize)],
+        mut relocate: impl FnMut(&WakeEntry, &WakeEntry),
+        alloc: &A,
+    ) where
+        A: Allocator,
+    {
+        let retired = &mut *self.retired.get();
+        let keep = len.div_ceil(CHUNK_SIZE);
+
+        let has_wakers = |index: usize| (*self.get(index)).wakers.load(Ordering::Acquire) != 0;
+        let mut replaced = RawVec::new();
+
+        let mut replace = |index: usize| {
+            let chunk = index / CHUNK_SIZE;
+
+            if chunk < keep && !replaced.contains(&chunk) {
+                replaced.push(chunk, alloc);
+            }
+        };
+
+        for &(old, new) in moved {
+            relocate(self.stats(old), self.stats(new));
+
+            // The new index was vacant, so its wakers belong to a removed
+            // child.
+            if has_wakers(old) {
+                replace(old);
+            }
+
+            if has_wakers(new) {
+                replace(new);
+            }
+        }
+
+        for index in len..keep * CHUNK_SIZE {
+            if has_wakers(index) {
+                replace(index);
+            }
+        }
+
+        let start = retired.len();
+
+        // NB: The table of chunks is only borrowed mutably while it's being
+        // modified, since looking up cells borrows it as well.
+        {
+            let chunks = &mut *self.chunks.get();
+
+            for chunk in chunks[keep..].iter() {
+                retired.push(*chunk, alloc);
+            }
+
+            chunks.truncate(keep);
+            chunks.shrink_to_fit(alloc);
+        }
+
+        for &chunk in replaced.iter() {
+            let base = chunk * CHUNK_SIZE;
+            let new = allocator::new_slice(CHUNK_SIZE, |n| WakerCell::new(shared, base + n), alloc);
+            let old = mem::replace(&mut (&mut *self.chunks.get())[chunk], new);
+            retired.push(old, alloc);
+
+            for n in 0..CHUNK_SIZE.min(len - base) {
+                relocate(&(*old.as_ptr().add(n)).stats, self.stats(base + n));
+            }
+        }
+
+        replaced.free(alloc);
+
+        for chunk in retired[start..].iter() {
+            for cell in slice::from_raw_parts(chunk.as_ptr(), CHUNK_SIZE) {
+                // A cell without wakers can never gain one once retired, so it
+                // doesn't need to forward anything.
+                if cell.wakers.load(Ordering::Acquire) != 0 {
+                    let target = match moved.iter().find(|&&(old, _)| old == cell.index) {
+                        Some(&(_, new)) => Some(new),
+                        None if cell.index < len
+                            && !moved.iter().any(|&(_, new)| new == cell.index) =>
+                        {
+                            Some(cell.index)
+                        }
+                        None => None,
+                    };
+
+                    if let Some(target) = target {
+                        let target = self.get(target);
+                        (*target).wakers.fetch_add(1, Ordering::Relaxed);
+                        cell.forward.store(target as *mut _, Ordering::Relaxed);
+                    }
+                }
+
+                // Ordering: Pairs with the acquire in `wake_by_ref`.
+                cell.retired.store(true, Ordering::Release);
+            }
+        }
+
+        self.sweep(alloc);
+    }
+
+    /// Free retired chunks which are no longer referenced by any wakers.
+    ///
+    /// Retired chunks only forward to cells which were current when they were
+    /// retired, so freeing them in the order they were retired also releases
+    /// chunks which are only kept alive by older ones.
+    unsafe fn sweep<A>(&self, alloc: &A)
+    where
+        A: Allocator,
+    {
+        let retired = &mut *self.retired.get();
+        let mut kept = 0;
+
+        for n in 0..retired.len() {
+            let chunk = retired[n];
+            let cells = slice::from_raw_parts(chunk.as_ptr(), CHUNK_SIZE);
+
+            // Ordering: Pairs with the release when wakers are dropped.
+            if cells
+                .iter()
+                .any(|cell| cell.wakers.load(Ordering::Acquire) != 0)
+            {
+                retired[kept] = chunk;
+                kept += 1;
+                continue;
+            }
+
+            for cell in cells {
+                let forward = cell.forward.load(Ordering::Relaxed);
+
+                if !forward.is_null() {
+                    (*forward).wakers.fetch_sub(1, Ordering::Release);
+                }
+            }
+
+            // Safety: Chunks are allocated as slices of `CHUNK_SIZE` cells in
+            // `reserve`, and nothing references this one any longer.
+            allocator::drop_slice(chunk, CHUNK_SIZE, alloc);
+        }
+
+        retired.truncate(kept);
+    }
+
+    /// Free all cells.
+    ///
+    /// # Safety
+    ///
+    /// The shared data which contains the storage must be being dropped, so
+    /// there are no wakers left referencing the cells. `alloc` must be the
+    /// allocator the cells were reserved with.
+    pub(crate) unsafe fn free<A>(&mut self, alloc: &A)
+    where
+        A: Allocator,
+    {
+        for chunks in [self.chunks.get_mut(), self.retired.get_mut()] {
+            for chunk in chunks.iter() {
+                // Safety: Chunks are allocated as slices of `CHUNK_SIZE` cells
+                // in `reserve`.
+                allocator::drop_slice(*chunk, CHUNK_SIZE, alloc);
+            }
+
+            chunks.free(alloc);
+        }
+    }
+}
+
+/// The parent waker, shared between all child wakers and swapped in by the
+/// owner of the collection when it is polled.
+pub(crate) struct SharedWaker<L>
+where
+    L: RawLock,
+{
+    lock: RwLock<L>,
+    waker: sync::UnsafeCell<Waker>,
+}
+
+// Safety: All access to the inner waker is guarded by `lock`, and `Waker` is
+// itself `Send` and `Sync`.
+unsafe impl<L> Send for SharedWaker<L> where L: RawLock {}
+unsafe impl<L> Sync for SharedWaker<L> where L: RawLock {}
+
+impl<L> SharedWaker<L>
+where
+    L: RawLock,
+{
     /// Construct a new shared waker.
     pub(crate) fn new() -> Self {
         Self {
             lock: RwLock::new(),
-            waker: UnsafeCell::new(noop_waker()),
+            waker: sync::UnsafeCell::new(noop_waker()),
         }
     }
 
     /// Wake the shared waker by ref.
     pub(crate) fn wake_by_ref(&self) {
         if let Some(_guard) = self.lock.try_lock_shared() {
-            let waker = unsafe { &*self.waker.get() };
-            waker.wake_by_ref();
+            self.waker.with(|waker| unsafe { (*waker).wake_by_ref() });
         }
     }
 
@@ -116,20 +532,23 @@ impl SharedWaker {
     /// Caller must ensure that they are the only one who will attempt to lock
     /// the waker exclusively.
     pub(crate) unsafe fn swap(&self, waker: &Waker) -> bool {
-        let shared_waker = self.waker.get();
-
         // Safety: No need to lock the shared waker exclusively to access an
         // immutable reference since the caller is assured to be the only one
         // trying to swap.
-        if (*shared_waker).will_wake(waker) {
+        if self
+            .waker
+            .with(|shared_waker| (*shared_waker).will_wake(waker))
+        {
             return true;
         }
 
         if let Some(_guard) = self.lock.try_lock_exclusive_guard() {
-            *self.waker.get() = waker.clone();
+            self.waker
+                .with_mut(|shared_waker| *shared_waker = waker.clone());
             return true;
         }
 
+        sync::yield_now();
         waker.wake_by_ref();
         false
     }
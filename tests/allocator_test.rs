@@ -0,0 +1,276 @@
+#![cfg(feature = "allocator-api2")]
+
+use allocator_api2::alloc::{AllocError, Allocator, Global};
+use futures::future::poll_fn;
+use std::alloc::Layout;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::thread;
+use unicycle::bitset::{Flat, Layered, RawBitSet};
+use unicycle::pin_slab::{PinSlab, Reuse};
+use unicycle::{FuturesUnordered, WakeOrder};
+
+#[derive(Default)]
+struct Counts {
+    allocations: AtomicUsize,
+    live: AtomicUsize,
+}
+
+/// An allocator which counts allocations made through it.
+#[derive(Default, Clone)]
+struct Counting(Arc<Counts>);
+
+impl Counting {
+    fn allocations(&self) -> usize {
+        self.0.allocations.load(Ordering::SeqCst)
+    }
+
+    fn live(&self) -> usize {
+        self.0.live.load(Ordering::SeqCst)
+    }
+}
+
+unsafe impl Allocator for Counting {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocations.fetch_add(1, Ordering::SeqCst);
+        self.0.live.fetch_add(1, Ordering::SeqCst);
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.0.live.fetch_sub(1, Ordering::SeqCst);
+        Global.deallocate(ptr, layout)
+    }
+}
+
+#[test]
+fn test_pin_slab_allocates_slots_in() {
+    let alloc = Counting::default();
+    let mut slab = PinSlab::new_in(alloc.clone());
+
+    for n in 0..100 {
+        slab.insert(n);
+    }
+
+    // One allocation per slot: 16 + 16 + 32 + 64 entries, and one for the
+    // table of slots.
+    assert_eq!(5, alloc.allocations());
+    assert_eq!(5, alloc.live());
+
+    let copy = slab.clone();
+    assert_eq!(10, alloc.live());
+
+    drop(slab);
+    drop(copy);
+    assert_eq!(0, alloc.live());
+}
+
+#[test]
+fn test_vacant_keys_allocate_in() {
+    let alloc = Counting::default();
+    let mut slab = PinSlab::new_in(alloc.clone());
+    slab.set_reuse(Reuse::Lowest);
+
+    for n in 0..16 {
+        slab.insert(n);
+    }
+
+    // The first slot and the table of slots.
+    assert_eq!(2, alloc.live());
+
+    for key in (0..16).rev() {
+        assert!(slab.remove(key));
+    }
+
+    // The heap of vacant keys.
+    assert_eq!(3, alloc.live());
+    assert_eq!(0, slab.insert(0));
+    assert_eq!(1, slab.insert(1));
+
+    let copy = slab.clone();
+    assert_eq!(6, alloc.live());
+
+    drop(slab);
+    drop(copy);
+    assert_eq!(0, alloc.live());
+}
+
+#[tokio::test]
+async fn test_spin_counts_allocate_in() {
+    let alloc = Counting::default();
+    let mut futures = FuturesUnordered::new_in(alloc.clone());
+    futures.set_spin_threshold(Some(1));
+
+    futures.push(poll_fn(|cx| {
+        cx.waker().wake_by_ref();
+        Poll::<()>::Pending
+    }));
+
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    let live = alloc.live();
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    assert_eq!(live, alloc.live());
+    assert_eq!(Some(2), futures.spin_count(0));
+
+    futures.set_spin_threshold(None);
+    assert_eq!(live - 1, alloc.live());
+
+    drop(futures);
+    assert_eq!(0, alloc.live());
+}
+
+#[tokio::test]
+async fn test_futures_unordered_allocates_in() {
+    let alloc = Counting::default();
+    let mut futures = FuturesUnordered::new_in(alloc.clone());
+    // Both wake sets are allocated up front.
+    assert_eq!(2, alloc.live());
+
+    let mut tasks = Vec::new();
+
+    for n in 0..100 {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        futures.push(async move { rx.await.unwrap() + n });
+        tasks.push(tx);
+    }
+
+    assert!(alloc.live() > 2);
+
+    let sender = thread::spawn(move || {
+        for tx in tasks {
+            tx.send(1).unwrap();
+        }
+    });
+
+    let mut sum = 0;
+
+    while let Some(value) = futures.next().await {
+        sum += value;
+    }
+
+    sender.join().unwrap();
+    assert_eq!((1..=100).sum::<i32>(), sum);
+
+    drop(futures);
+    assert_eq!(0, alloc.live());
+}
+
+#[tokio::test]
+async fn test_wakers_keep_allocator_alive() {
+    let alloc = Counting::default();
+    let mut futures = FuturesUnordered::new_in(alloc.clone());
+    let waker = Arc::new(Mutex::new(None::<Waker>));
+
+    futures.push({
+        let waker = waker.clone();
+
+        poll_fn(move |cx| {
+            *waker.lock().unwrap() = Some(cx.waker().clone());
+            Poll::<()>::Pending
+        })
+    });
+
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    drop(futures);
+
+    let waker = waker.lock().unwrap().take().unwrap();
+    // The waker still references the shared wake set.
+    assert!(alloc.live() > 0);
+
+    thread::spawn(move || waker.wake()).join().unwrap();
+    assert_eq!(0, alloc.live());
+}
+
+#[tokio::test]
+async fn test_compact_releases_storage_in() {
+    let alloc = Counting::default();
+    let mut futures = FuturesUnordered::new_in(alloc.clone());
+    let waker = Arc::new(Mutex::new(None::<Waker>));
+
+    let task = |slot: Option<Arc<Mutex<Option<Waker>>>>| {
+        poll_fn(move |cx| {
+            if let Some(slot) = &slot {
+                *slot.lock().unwrap() = Some(cx.waker().clone());
+            }
+
+            Poll::<()>::Pending
+        })
+    };
+
+    for _ in 0..999 {
+        futures.push(task(None));
+    }
+
+    futures.push(task(Some(waker.clone())));
+
+    while !futures.pending_wakes().is_empty() {
+        assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    }
+
+    let live = alloc.live();
+
+    for index in 1..999 {
+        assert!(futures.try_remove(index).is_some());
+    }
+
+    futures.compact(|old, new| assert_eq!((999, 1), (old, new)));
+    let compacted = alloc.live();
+    assert!(compacted < live);
+
+    // The cell the waker references is kept around until it's dropped, and
+    // released the next time the collection is compacted.
+    let waker = waker.lock().unwrap().take().unwrap();
+    waker.wake_by_ref();
+    assert_eq!(vec![1], futures.pending_wakes());
+
+    futures.compact(|_, _| unreachable!());
+    assert_eq!(compacted, alloc.live());
+
+    drop(waker);
+    futures.compact(|_, _| unreachable!());
+    assert!(alloc.live() < compacted);
+
+    drop(futures);
+    assert_eq!(0, alloc.live());
+}
+
+fn bit_set_allocates_in<B>()
+where
+    B: RawBitSet,
+{
+    let alloc = Counting::default();
+    let mut set = B::new();
+
+    set.set(3, &alloc);
+    set.reserve(1 << 20, &alloc);
+    assert!(alloc.live() > 0);
+
+    // Safety: The set has only been grown through `alloc`.
+    unsafe { set.free(&alloc) };
+    assert_eq!(0, alloc.live());
+}
+
+#[test]
+fn test_bit_sets_allocate_in() {
+    bit_set_allocates_in::<Flat>();
+    bit_set_allocates_in::<Layered>();
+}
+
+#[test]
+fn test_wake_order_allocates_in() {
+    let alloc = Counting::default();
+    let mut futures = FuturesUnordered::<std::future::Pending<()>, _, _, _>::new_in(alloc.clone());
+    let live = alloc.live();
+
+    // Each wake set gets a record of wake order.
+    futures.set_wake_order(WakeOrder::Fifo { capacity: 16 });
+    assert_eq!(live + 2, alloc.live());
+
+    futures.set_wake_order(WakeOrder::Index);
+    assert_eq!(live, alloc.live());
+
+    drop(futures);
+    assert_eq!(0, alloc.live());
+}
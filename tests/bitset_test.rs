@@ -0,0 +1,206 @@
+use futures::channel::oneshot;
+use std::thread;
+use unicycle::allocator::Global;
+use unicycle::bitset::{Flat, Layered, RawBitSet};
+use unicycle::lock::DefaultLock;
+use unicycle::FuturesUnordered;
+
+fn drain_in_order<B>()
+where
+    B: RawBitSet,
+{
+    let mut set = B::new();
+    assert!(set.is_empty());
+
+    for index in [4096, 3, 64, 65, 0] {
+        set.set(index, &Global);
+    }
+
+    assert!(set.capacity() > 4096);
+    assert!(!set.is_empty());
+    assert!(set.test(64));
+    assert!(!set.test(1));
+    assert!(!set.test(1 << 20));
+
+    // Iterating doesn't clear any bits.
+    assert!(set.iter().eq([0, 3, 64, 65, 4096]));
+
+    // Dropping the iterator early leaves the remaining bits in the set.
+    assert_eq!(vec![0, 3], set.drain().take(2).collect::<Vec<_>>());
+    assert_eq!(vec![64, 65, 4096], set.drain().collect::<Vec<_>>());
+    assert!(set.is_empty());
+
+    // Safety: The set has only been grown through the global allocator.
+    unsafe { set.free(&Global) };
+    assert_eq!(0, set.capacity());
+}
+
+fn grow<B>()
+where
+    B: RawBitSet,
+{
+    let mut set = B::new();
+    set.set(3, &Global);
+    set.set(130, &Global);
+
+    // Growing the set by several orders of magnitude keeps existing bits.
+    set.reserve(1 << 20, &Global);
+    set.set_shared(1 << 19);
+    assert!(set.set((1 << 20) + 1, &Global));
+    assert!(!set.set_shared(130));
+
+    let expected = [3, 130, 1 << 19, (1 << 20) + 1];
+    assert!(set.iter().eq(expected));
+    assert!(set.drain().eq(expected));
+    assert!(set.is_empty());
+
+    // Safety: The set has only been grown through the global allocator.
+    unsafe { set.free(&Global) };
+}
+
+fn take<B>()
+where
+    B: RawBitSet,
+{
+    let mut set = B::new();
+
+    for index in [4097, 3, 5, 4096] {
+        set.set(index, &Global);
+    }
+
+    assert!(set.take(3));
+    assert!(!set.take(3));
+    assert!(!set.take(4));
+    assert!(!set.take(1 << 20));
+
+    // Bits sharing a word with a taken bit are still drained.
+    assert!(set.take(4096));
+    assert_eq!(vec![5, 4097], set.drain().collect::<Vec<_>>());
+
+    assert!(set.set(7, &Global));
+    assert!(!set.set(7, &Global));
+    assert!(set.take(7));
+    assert!(set.is_empty());
+
+    // Safety: The set has only been grown through the global allocator.
+    unsafe { set.free(&Global) };
+}
+
+fn truncate<B>()
+where
+    B: RawBitSet,
+{
+    let mut set = B::new();
+
+    for index in [3, 100, 4095, 4096, 1 << 20] {
+        set.set(index, &Global);
+    }
+
+    // Bits past the new capacity are cleared, including those which share a
+    // word with bits that are kept.
+    set.truncate(4090, &Global);
+    assert!(set.capacity() >= 4090);
+    assert!(set.capacity() < 1 << 20);
+    assert!(set.iter().eq([3, 100]));
+    assert!(!set.test(4095));
+
+    // The set grows again as needed.
+    assert!(set.set(1 << 20, &Global));
+    assert!(set.drain().eq([3, 100, 1 << 20]));
+    assert!(set.is_empty());
+
+    set.set(5, &Global);
+    set.truncate(0, &Global);
+    assert_eq!(0, set.capacity());
+    assert!(set.is_empty());
+
+    assert!(set.set(7, &Global));
+    assert!(set.drain().eq([7]));
+
+    // Safety: The set has only been grown through the global allocator.
+    unsafe { set.free(&Global) };
+}
+
+fn set_shared<B>()
+where
+    B: RawBitSet,
+{
+    let mut set = B::new();
+    set.reserve(1000, &Global);
+    assert!(set.capacity() >= 1000);
+
+    // Every bit is set twice, but only one of the racing threads sees it
+    // transition.
+    let first = thread::scope(|s| {
+        let threads = (0..4)
+            .map(|n| {
+                let set = &set;
+
+                s.spawn(move || {
+                    (n..2000)
+                        .step_by(4)
+                        .filter(|&n| set.set_shared(n % 1000))
+                        .count()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .sum::<usize>()
+    });
+
+    assert_eq!(1000, first);
+    assert!(set.drain().eq(0..1000));
+
+    // Safety: The set has only been grown through the global allocator.
+    unsafe { set.free(&Global) };
+}
+
+#[test]
+fn test_flat() {
+    drain_in_order::<Flat>();
+    grow::<Flat>();
+    take::<Flat>();
+    truncate::<Flat>();
+    set_shared::<Flat>();
+}
+
+#[test]
+fn test_layered() {
+    drain_in_order::<Layered>();
+    grow::<Layered>();
+    take::<Layered>();
+    truncate::<Layered>();
+    set_shared::<Layered>();
+}
+
+#[tokio::test]
+async fn test_flat_unordered() {
+    let mut futures = FuturesUnordered::<_, DefaultLock, Flat>::with_bit_set();
+    let mut senders = Vec::new();
+
+    for n in 0..1000u32 {
+        let (tx, rx) = oneshot::channel();
+        futures.push(rx);
+        senders.push((n, tx));
+    }
+
+    let sender = thread::spawn(move || {
+        for (n, tx) in senders.into_iter().step_by(7) {
+            tx.send(n).unwrap();
+        }
+    });
+
+    let mut sum = 0;
+
+    while let Some(value) = futures.next().await {
+        if let Ok(value) = value {
+            sum += value;
+        }
+    }
+
+    sender.join().unwrap();
+    assert_eq!((0..1000).step_by(7).sum::<u32>(), sum);
+}
@@ -0,0 +1,237 @@
+use futures::channel::oneshot;
+use futures::future::poll_fn;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use unicycle::FuturesUnordered;
+
+/// A task which never completes, and keeps the waker it was last polled with.
+fn keep_waker(slot: &Arc<Mutex<Option<Waker>>>) -> impl Future<Output = ()> + Unpin {
+    let slot = slot.clone();
+
+    poll_fn(move |cx| {
+        *slot.lock().unwrap() = Some(cx.waker().clone());
+        Poll::Pending
+    })
+}
+
+/// Poll the collection until none of its tasks are woken.
+async fn settle<T>(futures: &mut FuturesUnordered<T>)
+where
+    T: Future + Unpin,
+{
+    while !futures.pending_wakes().is_empty() {
+        assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    }
+}
+
+/// Take the waker a task was last polled with.
+fn take_waker(slot: &Arc<Mutex<Option<Waker>>>) -> Waker {
+    slot.lock().unwrap().take().expect("task was polled")
+}
+
+#[tokio::test]
+async fn test_compact_wakes_moved_tasks() {
+    let mut futures = FuturesUnordered::new();
+    let mut senders = HashMap::new();
+
+    for n in 0..256u32 {
+        let (tx, rx) = oneshot::channel();
+        let index = futures.push(rx);
+        senders.insert(index, (n, tx));
+    }
+
+    // Poll every task once, so that they all register a waker.
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+
+    for index in 0..250 {
+        assert!(futures.try_remove(index).is_some());
+        senders.remove(&index);
+    }
+
+    let mut moved = Vec::new();
+    futures.compact(|old, new| moved.push((old, new)));
+    assert_eq!(6, moved.len());
+
+    for (old, new) in moved {
+        let sender = senders.remove(&old).expect("moved task");
+        senders.insert(new, sender);
+    }
+
+    assert!(senders.keys().all(|index| *index < 6));
+
+    // The tasks are only registered with wakers for their old index at this
+    // point, which wake them at their new one.
+    for (_, (n, tx)) in senders {
+        tx.send(n).unwrap();
+    }
+
+    let mut values = Vec::new();
+
+    while let Some(value) = futures.next().await {
+        values.push(value.unwrap());
+    }
+
+    values.sort();
+    assert_eq!((250..256).collect::<Vec<_>>(), values);
+}
+
+#[tokio::test]
+async fn test_compact_reuses_freed_indexes() {
+    let mut futures = FuturesUnordered::new();
+    let mut senders = Vec::new();
+
+    for _ in 0..64 {
+        let (tx, rx) = oneshot::channel::<()>();
+        futures.push(rx);
+        senders.push(tx);
+    }
+
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+
+    for index in 0..63 {
+        assert!(futures.try_remove(index).is_some());
+    }
+
+    futures.compact(|old, new| assert_eq!((63, 0), (old, new)));
+
+    // New tasks are pushed right after the compacted ones, including at the
+    // old index of the moved task whose waker now wakes it at index 0.
+    for n in 1..64 {
+        let (tx, rx) = oneshot::channel();
+        assert_eq!(n, futures.push(rx));
+        senders.push(tx);
+    }
+
+    drop(senders);
+
+    let mut count = 0;
+
+    while let Some(value) = futures.next().await {
+        assert!(value.is_err());
+        count += 1;
+    }
+
+    assert_eq!(64, count);
+}
+
+#[tokio::test]
+async fn test_compact_forwards_stale_wakers() {
+    let mut futures = FuturesUnordered::new();
+    let slots = (0..130).map(|_| Arc::default()).collect::<Vec<_>>();
+
+    for slot in &slots {
+        futures.push(keep_waker(slot));
+    }
+
+    settle(&mut futures).await;
+
+    let removed = take_waker(&slots[1]);
+    assert!(futures.try_remove(0).is_some());
+    assert!(futures.try_remove(1).is_some());
+
+    let mut moved = Vec::new();
+    futures.compact(|old, new| moved.push((old, new)));
+    assert_eq!(vec![(129, 0), (128, 1)], moved);
+
+    // Nothing was woken, so compaction doesn't wake anything either.
+    assert!(futures.pending_wakes().is_empty());
+
+    take_waker(&slots[129]).wake();
+    assert_eq!(vec![0], futures.pending_wakes());
+
+    // The task that used to be at index 1 is gone, so its waker does nothing
+    // even though another task has taken its place.
+    removed.wake();
+    assert_eq!(vec![0], futures.pending_wakes());
+
+    take_waker(&slots[2]).wake();
+    assert_eq!(vec![0, 2], futures.pending_wakes());
+}
+
+#[tokio::test]
+async fn test_compact_moves_pending_wakes() {
+    let mut futures = FuturesUnordered::new();
+    let slots = (0..130).map(|_| Arc::default()).collect::<Vec<_>>();
+
+    for slot in &slots {
+        futures.push(keep_waker(slot));
+    }
+
+    settle(&mut futures).await;
+
+    take_waker(&slots[129]).wake();
+    take_waker(&slots[3]).wake();
+    take_waker(&slots[64]).wake();
+    assert_eq!(vec![3, 64, 129], futures.pending_wakes());
+
+    for index in 0..128 {
+        assert!(futures.try_remove(index).is_some());
+    }
+
+    let mut moved = Vec::new();
+    futures.compact(|old, new| moved.push((old, new)));
+    assert_eq!(vec![(129, 0), (128, 1)], moved);
+
+    // Only the wakeup of a task which is still around is moved along.
+    assert_eq!(vec![0], futures.pending_wakes());
+    settle(&mut futures).await;
+    assert!(futures.pending_wakes().is_empty());
+
+    // Wakers of tasks which were removed don't wake tasks that are pushed to
+    // their old index.
+    let stale = take_waker(&slots[100]);
+
+    for slot in &slots[2..101] {
+        futures.push(keep_waker(slot));
+    }
+
+    settle(&mut futures).await;
+    assert!(futures.pending_wakes().is_empty());
+
+    stale.wake();
+    assert!(futures.pending_wakes().is_empty());
+
+    take_waker(&slots[100]).wake();
+    assert_eq!(vec![100], futures.pending_wakes());
+}
+
+#[cfg(feature = "std")]
+#[tokio::test(flavor = "current_thread")]
+async fn test_local_compact_wakes_moved_tasks() {
+    use unicycle::LocalFuturesUnordered;
+
+    let mut futures = LocalFuturesUnordered::new();
+    let mut senders = Vec::new();
+
+    for n in 0..8u32 {
+        let (tx, rx) = oneshot::channel();
+        futures.push(rx);
+        senders.push((n, tx));
+    }
+
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+
+    for index in 0..4 {
+        assert!(futures.try_remove(index).is_some());
+    }
+
+    let mut moved = Vec::new();
+    futures.compact(|old, new| moved.push((old, new)));
+    assert_eq!(vec![(7, 0), (6, 1), (5, 2), (4, 3)], moved);
+    assert!(futures.pending_wakes().is_empty());
+
+    for (n, tx) in senders.drain(4..) {
+        tx.send(n).unwrap();
+    }
+
+    let mut values = Vec::new();
+
+    while let Some(value) = futures.next().await {
+        values.push(value.unwrap());
+    }
+
+    values.sort();
+    assert_eq!(vec![4, 5, 6, 7], values);
+}
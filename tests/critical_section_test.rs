@@ -0,0 +1,64 @@
+//! Tests for the critical section fallback, run on a host with:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg unicycle_critical_section" cargo test --no-default-features --features critical-section,futures-rs
+//! ```
+//!
+//! The cfg makes every atomic operation take a critical section, which is
+//! provided by the `std` implementation of critical-section.
+
+#![cfg(all(feature = "critical-section", unicycle_critical_section))]
+
+use futures::future::poll_fn;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::thread;
+use std::time::Duration;
+use unicycle::FuturesUnordered;
+
+#[tokio::test]
+async fn test_wakes_take_critical_sections() {
+    let mut futures = FuturesUnordered::new();
+    let waker = Arc::new(Mutex::new(None::<Waker>));
+    let polls = Arc::new(Mutex::new(0));
+
+    futures.push({
+        let waker = waker.clone();
+        let polls = polls.clone();
+
+        poll_fn(move |cx| {
+            *polls.lock().unwrap() += 1;
+            *waker.lock().unwrap() = Some(cx.waker().clone());
+            Poll::<()>::Pending
+        })
+    });
+
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    assert_eq!(1, *polls.lock().unwrap());
+
+    let waker = waker.lock().unwrap().take().unwrap();
+    let woken = Arc::new(AtomicBool::new(false));
+
+    let handle = critical_section::with(|_| {
+        let handle = thread::spawn({
+            let woken = woken.clone();
+
+            move || {
+                waker.wake();
+                woken.store(true, Ordering::SeqCst);
+            }
+        });
+
+        // The wake can't make progress while we hold the critical section.
+        thread::sleep(Duration::from_millis(100));
+        assert!(!woken.load(Ordering::SeqCst));
+        handle
+    });
+
+    handle.join().unwrap();
+    assert!(woken.load(Ordering::SeqCst));
+
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    assert_eq!(2, *polls.lock().unwrap());
+}
@@ -0,0 +1,71 @@
+use crossbeam::channel;
+use futures::future::poll_fn;
+use std::task::{Poll, Waker};
+use std::thread;
+use std::time::Duration;
+use tokio::time;
+use unicycle::FuturesUnordered;
+
+// Miri is orders of magnitude slower, so do less work there.
+const TASKS: usize = if cfg!(miri) { 8 } else { 64 };
+const WAKES: usize = if cfg!(miri) { 20 } else { 200 };
+const THREADS: usize = 4;
+
+/// Hammer the wake sets with wakeups from other threads while they're being
+/// swapped, and make sure that no wakeups are lost.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_cross_thread_wakes_are_not_lost() {
+    let (tx, rx) = channel::unbounded::<Waker>();
+
+    let wakers = (0..THREADS)
+        .map(|_| {
+            let rx = rx.clone();
+
+            thread::spawn(move || {
+                while let Ok(waker) = rx.recv() {
+                    waker.wake();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut futures = FuturesUnordered::new();
+
+    for index in 0..TASKS {
+        let tx = tx.clone();
+        let mut remaining = WAKES;
+
+        futures.push(poll_fn(move |cx| {
+            if remaining == 0 {
+                return Poll::Ready(index);
+            }
+
+            remaining -= 1;
+            tx.send(cx.waker().clone()).unwrap();
+            Poll::Pending
+        }));
+    }
+
+    drop(tx);
+
+    let mut completed = Vec::new();
+
+    let drive = async {
+        while let Some(index) = futures.next().await {
+            completed.push(index);
+        }
+    };
+
+    time::timeout(Duration::from_secs(30), drive)
+        .await
+        .expect("all tasks to complete");
+
+    completed.sort();
+    assert_eq!((0..TASKS).collect::<Vec<_>>(), completed);
+
+    drop(futures);
+
+    for waker in wakers {
+        waker.join().unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+#![cfg(all(feature = "std", feature = "futures-rs"))]
+
+use futures::future::poll_fn;
+use futures::stream::StreamExt;
+use futures::task::ArcWake;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+use tokio::time;
+use tokio_stream::iter;
+use unicycle::{LocalFuturesUnordered, LocalIndexedStreamsUnordered, LocalStreamsUnordered};
+
+#[tokio::test(flavor = "current_thread", start_paused = true)]
+async fn test_local_futures() {
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let mut futures = LocalFuturesUnordered::new();
+
+    for n in 0..4u64 {
+        let received = received.clone();
+
+        futures.push(async move {
+            time::sleep(Duration::from_millis((4 - n) * 10)).await;
+            received.borrow_mut().push(n);
+        });
+    }
+
+    while futures.next().await.is_some() {}
+
+    assert_eq!(vec![3, 2, 1, 0], *received.borrow());
+    assert!(futures.is_empty());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_local_streams_fairness() {
+    let mut streams = LocalStreamsUnordered::new();
+    streams.push(iter(vec![1, 2, 3, 4]));
+    streams.push(iter(vec![5, 6, 7, 8]));
+
+    let mut received = Vec::new();
+
+    while let Some(value) = streams.next().await {
+        received.push(value);
+    }
+
+    assert_eq!(vec![1, 5, 2, 6, 3, 7, 4, 8], received);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_local_indexed_streams() {
+    let mut streams = LocalIndexedStreamsUnordered::new();
+    streams.push(iter(vec![1, 2]));
+    streams.push(iter(vec![5, 6]));
+
+    let mut received = Vec::new();
+
+    while let Some(value) = streams.next().await {
+        received.push(value);
+    }
+
+    assert_eq!(
+        vec![
+            (0, Some(1)),
+            (1, Some(5)),
+            (0, Some(2)),
+            (1, Some(6)),
+            (0, None),
+            (1, None)
+        ],
+        received
+    );
+}
+
+/// Capture a cloned waker from a child of a local set.
+async fn capture_waker() -> Waker {
+    let mut futures = LocalFuturesUnordered::new();
+    let waker = Rc::new(RefCell::new(None));
+
+    futures.push(poll_fn({
+        let waker = waker.clone();
+
+        move |cx| {
+            *waker.borrow_mut() = Some(cx.waker().clone());
+            Poll::Ready(())
+        }
+    }));
+
+    assert_eq!(Some(()), futures.next().await);
+    let waker = waker.borrow_mut().take();
+    waker.expect("waker to be captured")
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_local_clone_and_drop_on_other_thread() {
+    let waker = capture_waker().await;
+
+    let result = thread::spawn(move || {
+        let clone = waker.clone();
+        drop(waker);
+        clone
+    })
+    .join();
+
+    let waker = result.expect("cloning on another thread");
+    assert!(thread::spawn(move || drop(waker)).join().is_ok());
+
+    let waker = capture_waker().await;
+    waker.wake_by_ref();
+    drop(waker);
+}
+
+struct CountWakes(AtomicUsize);
+
+impl ArcWake for CountWakes {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_local_wake_from_other_thread() {
+    let count = Arc::new(CountWakes(AtomicUsize::new(0)));
+    let parent = futures::task::waker(count.clone());
+    let mut cx = Context::from_waker(&parent);
+
+    let slot = Arc::new(Mutex::new(None::<Waker>));
+    let mut futures = LocalFuturesUnordered::new();
+
+    futures.push(poll_fn({
+        let slot = slot.clone();
+        let mut polls = 0;
+
+        move |cx| {
+            polls += 1;
+
+            if polls > 1 {
+                return Poll::Ready(polls);
+            }
+
+            *slot.lock().unwrap() = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }));
+
+    // The second poll registers the parent waker, since the first one only
+    // drains the newly pushed child.
+    assert!(futures.poll_next_unpin(&mut cx).is_pending());
+    assert!(futures.poll_next_unpin(&mut cx).is_pending());
+    assert!(futures.pending_wakes().is_empty());
+
+    let waker = slot.lock().unwrap().take().unwrap();
+    let before = count.0.load(Ordering::SeqCst);
+    thread::spawn(move || waker.wake()).join().unwrap();
+
+    // The wakeup is forwarded to the parent, and recorded for the next poll.
+    assert!(count.0.load(Ordering::SeqCst) > before);
+    assert_eq!(vec![0], futures.pending_wakes());
+    assert!(futures.is_woken(0));
+    assert_eq!(Poll::Ready(Some(2)), futures.poll_next_unpin(&mut cx));
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+use tokio::time;
+use unicycle::lock::{AtomicLock, RawLock};
+use unicycle::FuturesUnordered;
+
+async fn drive<L>() -> Vec<u32>
+where
+    L: RawLock,
+{
+    let mut futures = FuturesUnordered::<_, L>::with_lock();
+
+    for n in 0..4u32 {
+        futures.push(async move {
+            time::sleep(Duration::from_millis(u64::from(4 - n) * 10)).await;
+            n
+        });
+    }
+
+    let mut received = Vec::new();
+
+    while let Some(n) = futures.next().await {
+        received.push(n);
+    }
+
+    received
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_atomic_lock() {
+    assert_eq!(vec![3, 2, 1, 0], drive::<AtomicLock>().await);
+}
+
+#[cfg(feature = "parking-lot")]
+#[tokio::test(start_paused = true)]
+async fn test_mixed_locks() {
+    let (a, b) = tokio::join!(drive::<AtomicLock>(), drive::<parking_lot::RawRwLock>());
+    assert_eq!(vec![3, 2, 1, 0], a);
+    assert_eq!(vec![3, 2, 1, 0], b);
+}
@@ -0,0 +1,311 @@
+//! Model tests for the wake set protocol, run with:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --test loom_test --release
+//! ```
+//!
+//! The number of preemptions explored defaults to 3, or 2 for models with
+//! more than one waking thread, and can be changed with the
+//! `LOOM_MAX_PREEMPTIONS` environment variable.
+
+#![cfg(all(loom, feature = "futures-rs"))]
+
+use futures::future::{pending, poll_fn, Either};
+use futures::task::noop_waker;
+use loom::future::block_on;
+use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use loom::sync::{Arc, Mutex};
+use loom::thread;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use unicycle::{FuturesUnordered, PollNext, WakeOrder};
+
+/// Run the given model with a bounded number of preemptions.
+fn model<F>(f: F)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    model_with_preemptions(3, f);
+}
+
+/// Run the given model, exploring at most `preemptions` preemptions unless
+/// overriden through the environment.
+fn model_with_preemptions<F>(preemptions: usize, f: F)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let mut builder = loom::model::Builder::new();
+
+    if builder.preemption_bound.is_none() {
+        builder.preemption_bound = Some(preemptions);
+    }
+
+    builder.check(f);
+}
+
+/// A signal which is notified from another thread.
+#[derive(Default)]
+struct Signal {
+    ready: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Signal {
+    fn notify(&self) {
+        self.ready.store(true, Ordering::Release);
+
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Construct a future which completes with `value` once the signal is
+/// notified.
+fn wait<T>(signal: &Arc<Signal>, value: T) -> impl Future<Output = T> {
+    let signal = signal.clone();
+    let mut value = Some(value);
+
+    poll_fn(move |cx| {
+        if !signal.ready.load(Ordering::Acquire) {
+            *signal.waker.lock().unwrap() = Some(cx.waker().clone());
+
+            if !signal.ready.load(Ordering::Acquire) {
+                return Poll::Pending;
+            }
+        }
+
+        Poll::Ready(value.take().expect("polled after completion"))
+    })
+}
+
+/// Poll the set once with a waker which does nothing.
+fn poll_once<T>(set: &mut T) -> Poll<Option<T::Item>>
+where
+    T: Unpin + PollNext,
+{
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    Pin::new(set).poll_next(&mut cx)
+}
+
+/// Spawn a thread which notifies the given signal.
+fn notify(signal: &Arc<Signal>) -> thread::JoinHandle<()> {
+    let signal = signal.clone();
+    thread::spawn(move || signal.notify())
+}
+
+#[test]
+fn test_wake_from_other_thread() {
+    model(|| {
+        let signal = Arc::new(Signal::default());
+        let mut futures = FuturesUnordered::new();
+        futures.push(wait(&signal, 1));
+
+        let handle = notify(&signal);
+        assert_eq!(Some(1), block_on(futures.next()));
+        handle.join().unwrap();
+    });
+}
+
+#[test]
+fn test_wake_while_swapping_parent_waker() {
+    model(|| {
+        let signal = Arc::new(Signal::default());
+        let mut futures = FuturesUnordered::new();
+        futures.push(wait(&signal, 1));
+
+        // Register the child waker, and install a parent waker which will be
+        // swapped out while the child is being woken.
+        assert!(poll_once(&mut futures).is_pending());
+
+        let handle = notify(&signal);
+        assert_eq!(Some(1), block_on(futures.next()));
+        handle.join().unwrap();
+    });
+}
+
+#[test]
+fn test_concurrent_wakes() {
+    model_with_preemptions(2, || {
+        let a = Arc::new(Signal::default());
+        let b = Arc::new(Signal::default());
+
+        let mut futures = FuturesUnordered::new();
+        futures.push(wait(&a, 1));
+        futures.push(wait(&b, 2));
+        assert!(poll_once(&mut futures).is_pending());
+
+        let handles = [notify(&a), notify(&b)];
+
+        let mut received = [
+            block_on(futures.next()).unwrap(),
+            block_on(futures.next()).unwrap(),
+        ];
+
+        received.sort();
+        assert_eq!([1, 2], received);
+        assert_eq!(None, block_on(futures.next()));
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+}
+
+#[test]
+fn test_concurrent_fifo_wakes() {
+    model_with_preemptions(2, || {
+        let a = Arc::new(Signal::default());
+        let b = Arc::new(Signal::default());
+
+        // Only one of the wakeups fits in the queue, so the other one is only
+        // recorded in the bit set.
+        let mut futures = FuturesUnordered::new();
+        futures.set_wake_order(WakeOrder::Fifo { capacity: 1 });
+        futures.push(wait(&a, 1));
+        futures.push(wait(&b, 2));
+        assert!(poll_once(&mut futures).is_pending());
+
+        let handles = [notify(&a), notify(&b)];
+
+        let mut received = [
+            block_on(futures.next()).unwrap(),
+            block_on(futures.next()).unwrap(),
+        ];
+
+        received.sort();
+        assert_eq!([1, 2], received);
+        assert_eq!(None, block_on(futures.next()));
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
+}
+
+#[test]
+fn test_pending_wakes_during_wake() {
+    model(|| {
+        let signal = Arc::new(Signal::default());
+        let mut futures = FuturesUnordered::new();
+        futures.push(wait(&signal, 1));
+        assert!(poll_once(&mut futures).is_pending());
+
+        // Reading the active set doesn't block wakeups racing with us, which
+        // might or might not be observed.
+        let handle = notify(&signal);
+        let _ = futures.pending_wakes();
+        handle.join().unwrap();
+
+        assert_eq!(vec![0], futures.pending_wakes());
+        assert!(futures.is_woken(0));
+        assert_eq!(Some(1), block_on(futures.next()));
+        assert!(futures.pending_wakes().is_empty());
+    });
+}
+
+#[test]
+fn test_wake_during_reserve() {
+    model(|| {
+        let signal = Arc::new(Signal::default());
+
+        let mut futures = FuturesUnordered::new();
+        futures.push(Either::Left(wait(&signal, 0)));
+
+        // Fill up the wake sets, so that the next push needs to grow them.
+        for _ in 1..64 {
+            futures.push(Either::Right(pending()));
+        }
+
+        assert!(poll_once(&mut futures).is_pending());
+
+        let handle = notify(&signal);
+
+        // Growing the wake sets swaps and reserves them while the first task
+        // is being woken.
+        futures.push(Either::Right(pending()));
+
+        assert_eq!(Some(0), block_on(futures.next()));
+        handle.join().unwrap();
+    });
+}
+
+#[test]
+fn test_contended_wake_is_not_polled_twice_per_cycle() {
+    model(|| {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let waker = Arc::new(Mutex::new(None::<Waker>));
+
+        let mut futures = FuturesUnordered::<Box<dyn Future<Output = u32> + Unpin>>::new();
+
+        futures.push(Box::new({
+            let polls = polls.clone();
+            let waker = waker.clone();
+
+            poll_fn(move |cx| {
+                polls.fetch_add(1, Ordering::Relaxed);
+                *waker.lock().unwrap() = Some(cx.waker().clone());
+                Poll::Pending
+            })
+        }));
+
+        // Completes the second time it's polled.
+        futures.push(Box::new({
+            let mut ready = false;
+
+            poll_fn(move |cx| {
+                if std::mem::replace(&mut ready, true) {
+                    return Poll::Ready(1);
+                }
+
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            })
+        }));
+
+        futures.push(Box::new(poll_fn(|cx| {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })));
+
+        assert!(poll_once(&mut futures).is_pending());
+
+        // The first child is polled before the second one completes, which
+        // leaves the cycle with the third child still to be polled.
+        assert_eq!(Poll::Ready(Some(1)), poll_once(&mut futures));
+        assert_eq!(1, polls.load(Ordering::Relaxed));
+
+        let waker = waker.lock().unwrap().take().unwrap();
+        let handle = thread::spawn(move || waker.wake());
+
+        // Compacting locks the active set, so the wakeup might have to be
+        // recorded as pending.
+        futures.compact(|_, _| {});
+
+        // Finishing the cycle mustn't poll the first child again.
+        assert!(poll_once(&mut futures).is_pending());
+        assert_eq!(1, polls.load(Ordering::Relaxed));
+
+        handle.join().unwrap();
+
+        // The next cycle does.
+        assert!(poll_once(&mut futures).is_pending());
+        assert_eq!(2, polls.load(Ordering::Relaxed));
+    });
+}
+
+#[test]
+fn test_wake_during_drop() {
+    model(|| {
+        let signal = Arc::new(Signal::default());
+        let mut futures = FuturesUnordered::new();
+        futures.push(wait(&signal, 1));
+        assert!(poll_once(&mut futures).is_pending());
+
+        let handle = notify(&signal);
+        drop(futures);
+        handle.join().unwrap();
+    });
+}
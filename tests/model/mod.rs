@@ -0,0 +1,533 @@
+//! A reference model for checking sequences of operations against
+//! [FuturesUnordered], [StreamsUnordered] and [PinSlab].
+//!
+//! This is shared between `tests/model_test.rs` and the fuzz targets in
+//! `fuzz/`.
+//!
+//! The model checks that:
+//! * Every item is yielded exactly once, unless the child which produces it
+//!   has been removed or the set has been dropped.
+//! * No index is polled more than once by a single call to `poll_next`, and
+//!   only indexes which are occupied are polled.
+//! * `remove`, `len` and `is_empty` agree with the model.
+//! * `pending_wakes` only lists live children, which remain woken until they
+//!   are polled.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use futures::stream::Stream;
+use futures::task::noop_waker;
+use unicycle::pin_slab::{Growth, PinSlab, Reuse};
+use unicycle::{FuturesUnordered, PollNext, Sentinel, StreamsUnordered, Unordered, WakeOrder};
+
+/// An operation to perform.
+///
+/// Operations which refer to an existing child pick one by taking their
+/// argument modulo the number of live children, and do nothing if there are
+/// none.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(fuzzing, derive(arbitrary::Arbitrary))]
+pub enum Op {
+    /// Push a new child.
+    Push,
+    /// Remove the given index, which might not be occupied.
+    Remove(u8),
+    /// Make a child produce an item.
+    Complete(u8),
+    /// Make a child produce an item, and wake it from another thread.
+    CompleteFromThread(u8),
+    /// Wake a child without producing anything.
+    Wake(u8),
+    /// Wake a child from another thread without producing anything.
+    WakeFromThread(u8),
+    /// Call `poll_next` once.
+    Poll,
+    /// Wait for all threads to finish.
+    Join,
+    /// Compact the collection, moving children to the lowest free indexes.
+    Compact,
+    /// Switch to the wake order picked by the argument.
+    WakeOrder(u8),
+    /// Drop the collection and start over with a new one.
+    Drop,
+}
+
+/// State shared between a child and the model.
+#[derive(Default)]
+struct ChildState {
+    /// The index of the child, once pushed.
+    index: Option<usize>,
+    /// Items which are ready to be produced.
+    ready: VecDeque<u64>,
+    /// If the child should end once it's out of items.
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+type Shared = Arc<Mutex<ChildState>>;
+type PollLog = Arc<Mutex<Vec<usize>>>;
+
+/// A child future or stream which records when it's polled.
+struct Child {
+    state: Shared,
+    log: PollLog,
+}
+
+impl Child {
+    fn poll_child(&self, cx: &Context<'_>) -> Poll<Option<u64>> {
+        let mut state = self.state.lock().unwrap();
+        let index = state.index.expect("child polled before being pushed");
+        self.log.lock().unwrap().push(index);
+
+        if let Some(item) = state.ready.pop_front() {
+            return Poll::Ready(Some(item));
+        }
+
+        if state.closed {
+            return Poll::Ready(None);
+        }
+
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Future for Child {
+    type Output = u64;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.poll_child(cx) {
+            Poll::Ready(Some(item)) => Poll::Ready(item),
+            Poll::Ready(None) => panic!("future polled after completion"),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Stream for Child {
+    type Item = u64;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_child(cx)
+    }
+}
+
+/// Pick a wake order, where small queues are picked often so that they
+/// overflow.
+fn wake_order(n: u8) -> WakeOrder {
+    match n % 4 {
+        0 => WakeOrder::Index,
+        n => WakeOrder::Fifo {
+            capacity: usize::from(n - 1) * 4,
+        },
+    }
+}
+
+/// Wake the given child, if it has registered a waker.
+fn wake(state: &Shared) {
+    let waker = state.lock().unwrap().waker.take();
+
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// Pick a live child using the argument of an operation.
+fn pick<T>(live: &BTreeMap<usize, T>, n: u8) -> Option<usize> {
+    if live.is_empty() {
+        return None;
+    }
+
+    live.keys().nth(usize::from(n) % live.len()).copied()
+}
+
+/// Pick an index to remove using the argument of an operation, which might
+/// be vacant.
+fn pick_any<T>(live: &BTreeMap<usize, T>, n: u8) -> usize {
+    usize::from(n) % (live.keys().last().map_or(0, |n| n + 1) + 2)
+}
+
+/// A child which is live in the model.
+struct Entry {
+    state: Shared,
+    /// Items the child has been told to produce and which have not yet been
+    /// yielded, in order.
+    expected: VecDeque<u64>,
+    /// Whether the child has been told to produce anything.
+    produced: bool,
+}
+
+/// A model of an unordered set.
+struct Model<S>
+where
+    S: Sentinel,
+{
+    set: Unordered<Child, S>,
+    /// Constructor for new sets.
+    new_set: fn() -> Unordered<Child, S>,
+    log: PollLog,
+    /// Children are futures which complete after producing one item, as
+    /// opposed to streams which produce any number of items.
+    once: bool,
+    live: BTreeMap<usize, Entry>,
+    /// Items which have been yielded.
+    yielded: HashSet<u64>,
+    /// Items which are never going to be yielded, because their child has been
+    /// removed or dropped.
+    lost: HashSet<u64>,
+    /// The child which produced each item.
+    owners: HashMap<u64, usize>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl<S> Model<S>
+where
+    S: Sentinel,
+    Unordered<Child, S>: PollNext<Item = u64>,
+{
+    fn new(new_set: fn() -> Unordered<Child, S>, once: bool) -> Self {
+        Self {
+            set: new_set(),
+            new_set,
+            log: PollLog::default(),
+            once,
+            live: BTreeMap::new(),
+            yielded: HashSet::new(),
+            lost: HashSet::new(),
+            owners: HashMap::new(),
+            threads: Vec::new(),
+        }
+    }
+
+    fn apply(&mut self, op: Op) {
+        match op {
+            Op::Push => self.push(),
+            Op::Remove(n) => self.remove(n),
+            Op::Complete(n) => self.produce(n, false),
+            Op::CompleteFromThread(n) => self.produce(n, true),
+            Op::Wake(n) => self.wake(n, false),
+            Op::WakeFromThread(n) => self.wake(n, true),
+            Op::Poll => {
+                let _ = self.poll();
+            }
+            Op::Join => self.join(),
+            Op::Compact => self.compact(),
+            Op::WakeOrder(n) => self.set.set_wake_order(wake_order(n)),
+            Op::Drop => self.drop_set(),
+        }
+
+        assert_eq!(self.live.is_empty(), self.set.is_empty());
+
+        // Wakeups from other threads might arrive in between, but woken
+        // children stay woken until they're polled.
+        for index in self.set.pending_wakes() {
+            assert!(self.live.contains_key(&index), "vacant {index} woken");
+            assert!(self.set.is_woken(index), "{index} no longer woken");
+        }
+    }
+
+    fn push(&mut self) {
+        let state = Shared::default();
+
+        let index = self.set.push(Child {
+            state: state.clone(),
+            log: self.log.clone(),
+        });
+
+        assert!(!self.live.contains_key(&index), "index {index} reused");
+        state.lock().unwrap().index = Some(index);
+
+        self.live.insert(
+            index,
+            Entry {
+                state,
+                expected: VecDeque::new(),
+                produced: false,
+            },
+        );
+    }
+
+    fn remove(&mut self, n: u8) {
+        let index = pick_any(&self.live, n);
+        let removed = self.set.remove_with(index, |_| ()).is_some();
+        assert_eq!(self.live.contains_key(&index), removed);
+
+        if let Some(entry) = self.live.remove(&index) {
+            self.lost.extend(entry.expected);
+        }
+    }
+
+    /// Tell a child to produce a new item.
+    fn produce(&mut self, n: u8, thread: bool) {
+        let Some(index) = pick(&self.live, n) else {
+            return;
+        };
+
+        let item = self.owners.len() as u64;
+        let entry = self.live.get_mut(&index).unwrap();
+
+        if self.once && entry.produced {
+            return;
+        }
+
+        entry.expected.push_back(item);
+        entry.produced = true;
+        self.owners.insert(item, index);
+
+        // NB: the item is queued up immediately so that items are produced in
+        // order even if they're woken from different threads.
+        entry.state.lock().unwrap().ready.push_back(item);
+        let state = entry.state.clone();
+        self.wake_with(state, thread);
+    }
+
+    fn wake(&mut self, n: u8, thread: bool) {
+        if let Some(index) = pick(&self.live, n) {
+            let state = self.live[&index].state.clone();
+            self.wake_with(state, thread);
+        }
+    }
+
+    fn wake_with(&mut self, state: Shared, thread: bool) {
+        if thread {
+            self.threads.push(thread::spawn(move || wake(&state)));
+        } else {
+            wake(&state);
+        }
+    }
+
+    fn join(&mut self) {
+        for thread in self.threads.drain(..) {
+            thread.join().unwrap();
+        }
+    }
+
+    /// Compact the set, and move the children in the model along with it.
+    fn compact(&mut self) {
+        let mut moved = Vec::new();
+        self.set.compact(|old, new| moved.push((old, new)));
+
+        let entries = moved
+            .into_iter()
+            .map(|(old, new)| (new, self.live.remove(&old).expect("vacant index moved")))
+            .collect::<Vec<_>>();
+
+        for (new, entry) in entries {
+            entry.state.lock().unwrap().index = Some(new);
+
+            for item in &entry.expected {
+                self.owners.insert(*item, new);
+            }
+
+            assert!(
+                self.live.insert(new, entry).is_none(),
+                "moved to occupied {new}"
+            );
+        }
+
+        assert!(self.live.keys().copied().eq(0..self.live.len()));
+    }
+
+    /// Call `poll_next` once and check the outcome.
+    fn poll(&mut self) -> Poll<Option<u64>> {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let output = PollNext::poll_next(Pin::new(&mut self.set), &mut cx);
+        let polled = mem::take(&mut *self.log.lock().unwrap());
+
+        let mut seen = HashSet::new();
+
+        for index in polled {
+            assert!(seen.insert(index), "index {index} polled twice");
+            assert!(self.live.contains_key(&index), "vacant {index} polled");
+        }
+
+        if let Poll::Ready(Some(item)) = output {
+            assert!(self.yielded.insert(item), "item {item} yielded twice");
+
+            let index = self.owners[&item];
+            let entry = self.live.get_mut(&index).expect("item from removed child");
+            assert_eq!(Some(item), entry.expected.pop_front(), "item out of order");
+
+            // A future is gone once it has completed.
+            if self.once {
+                self.live.remove(&index);
+            }
+        }
+
+        output
+    }
+
+    /// Drop the set, while any threads might still be waking its children.
+    fn drop_set(&mut self) {
+        self.set = (self.new_set)();
+
+        for (_, entry) in mem::take(&mut self.live) {
+            self.lost.extend(entry.expected);
+        }
+
+        self.join();
+    }
+
+    /// Finish the model by draining the set and checking that every item has
+    /// been accounted for.
+    fn finish(mut self) {
+        self.join();
+
+        let indexes = self.live.keys().copied().collect::<Vec<_>>();
+
+        for index in indexes {
+            if self.once {
+                // Make sure every future has something to complete with.
+                let n = self.live.keys().position(|i| *i == index).unwrap();
+                self.produce(n as u8, false);
+            } else {
+                let state = &self.live[&index].state;
+                state.lock().unwrap().closed = true;
+                wake(state);
+            }
+        }
+
+        // Every item should be yielded after a couple of polls each. If a
+        // wakeup has been lost, we'll never poll the child again.
+        let budget = 4 * (self.owners.len() + self.live.len()) + 16;
+
+        for _ in 0..budget {
+            if let Poll::Ready(None) = self.poll() {
+                break;
+            }
+        }
+
+        assert!(self.set.is_empty(), "set was not drained");
+
+        for item in self.owners.keys() {
+            assert!(
+                self.yielded.contains(item) != self.lost.contains(item),
+                "item {item} was neither yielded nor lost"
+            );
+        }
+    }
+}
+
+/// Run the given operations against a [FuturesUnordered], where each future
+/// completes with a single item.
+pub fn futures(ops: &[Op]) {
+    let mut model = Model::new(FuturesUnordered::new, true);
+
+    for op in ops {
+        model.apply(*op);
+    }
+
+    model.finish();
+}
+
+/// Run the given operations against a [StreamsUnordered], where each stream
+/// produces any number of items.
+pub fn streams(ops: &[Op]) {
+    let mut model = Model::new(StreamsUnordered::new, false);
+
+    for op in ops {
+        model.apply(*op);
+    }
+
+    model.finish();
+}
+
+/// Run the given operations against a [PinSlab], using the growth and reuse
+/// policies picked by `policy`.
+pub fn pin_slab(policy: u8, ops: &[Op]) {
+    const REUSE: [Reuse; 3] = [Reuse::Lifo, Reuse::Fifo, Reuse::Lowest];
+
+    let growth = match policy % 4 {
+        0 => Growth::default(),
+        1 => Growth::fixed(1),
+        2 => Growth::fixed(4),
+        _ => Growth::geometric(2, 4),
+    };
+
+    let mut reuse = usize::from(policy / 4) % REUSE.len();
+    let new_slab = |reuse: usize| {
+        let mut slab = PinSlab::with_growth(growth);
+        slab.set_reuse(REUSE[reuse]);
+        slab
+    };
+
+    let mut slab = new_slab(reuse);
+    let mut live = BTreeMap::new();
+    // Free keys, in the order in which they are expected to be reused.
+    let mut free = VecDeque::new();
+    let mut next = 0u64;
+
+    for op in ops {
+        match *op {
+            Op::Push => {
+                let expected = free.pop_front().unwrap_or(live.len());
+                let key = slab.insert(next);
+                assert_eq!(expected, key, "{:?}", REUSE[reuse]);
+                assert!(live.insert(key, next).is_none(), "key {key} reused");
+                next += 1;
+            }
+            Op::Remove(n) => {
+                let key = pick_any(&live, n);
+                let value = live.remove(&key);
+                assert_eq!(value, slab.try_remove(key));
+
+                if value.is_some() {
+                    match REUSE[reuse] {
+                        Reuse::Lifo => free.push_front(key),
+                        Reuse::Fifo => free.push_back(key),
+                        _ => {
+                            let at = free.partition_point(|k| *k < key);
+                            free.insert(at, key);
+                        }
+                    }
+                }
+            }
+            Op::Complete(n) | Op::CompleteFromThread(n) => {
+                if let Some(key) = pick(&live, n) {
+                    *slab.get_mut(key).unwrap() += 1000;
+                    *live.get_mut(&key).unwrap() += 1000;
+                }
+            }
+            Op::Wake(..) | Op::WakeFromThread(..) | Op::Poll | Op::WakeOrder(..) => {
+                for (key, value) in &live {
+                    assert_eq!(Some(value), slab.get(*key));
+                }
+
+                assert!(slab.iter().eq(live.iter().map(|(k, v)| (*k, v))));
+            }
+            Op::Compact => {
+                let mut moved = Vec::new();
+                slab.compact(|old, new| moved.push((old, new)));
+
+                let values = moved
+                    .into_iter()
+                    .map(|(old, new)| (new, live.remove(&old).expect("vacant key moved")))
+                    .collect::<Vec<_>>();
+
+                live.extend(values);
+                free.clear();
+                assert!(live.keys().copied().eq(0..live.len()));
+            }
+            Op::Join => {
+                // Switching policies reuses free keys in ascending order.
+                reuse = (reuse + 1) % REUSE.len();
+                slab.set_reuse(REUSE[reuse]);
+                free.make_contiguous().sort_unstable();
+            }
+            Op::Drop => {
+                slab = new_slab(reuse);
+                live.clear();
+                free.clear();
+            }
+        }
+
+        assert_eq!(live.len(), slab.len());
+    }
+}
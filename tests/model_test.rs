@@ -0,0 +1,50 @@
+#![cfg(feature = "futures-rs")]
+
+mod model;
+
+use model::Op;
+use proptest::prelude::*;
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => Just(Op::Push),
+        2 => any::<u8>().prop_map(Op::Remove),
+        3 => any::<u8>().prop_map(Op::Complete),
+        2 => any::<u8>().prop_map(Op::CompleteFromThread),
+        1 => any::<u8>().prop_map(Op::Wake),
+        1 => any::<u8>().prop_map(Op::WakeFromThread),
+        4 => Just(Op::Poll),
+        1 => Just(Op::Join),
+        1 => Just(Op::Compact),
+        1 => any::<u8>().prop_map(Op::WakeOrder),
+        1 => Just(Op::Drop),
+    ]
+}
+
+fn ops() -> impl Strategy<Value = Vec<Op>> {
+    // Miri is orders of magnitude slower, so use shorter sequences there.
+    prop::collection::vec(op(), 0..if cfg!(miri) { 16 } else { 128 })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        cases: if cfg!(miri) { 4 } else { 256 },
+        failure_persistence: None,
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn test_futures_model(ops in ops()) {
+        model::futures(&ops);
+    }
+
+    #[test]
+    fn test_streams_model(ops in ops()) {
+        model::streams(&ops);
+    }
+
+    #[test]
+    fn test_pin_slab_model(policy in any::<u8>(), ops in ops()) {
+        model::pin_slab(policy, &ops);
+    }
+}
@@ -0,0 +1,145 @@
+use futures::channel::oneshot;
+use std::thread;
+use unicycle::FuturesUnordered;
+
+/// Construct a collection of receivers, and poll it until none of them are
+/// woken.
+async fn receivers(
+    len: usize,
+) -> (
+    FuturesUnordered<oneshot::Receiver<usize>>,
+    Vec<oneshot::Sender<usize>>,
+) {
+    let mut futures = FuturesUnordered::new();
+    let mut senders = Vec::new();
+
+    for _ in 0..len {
+        let (tx, rx) = oneshot::channel();
+        futures.push(rx);
+        senders.push(tx);
+    }
+
+    assert_eq!((0..len).collect::<Vec<_>>(), futures.pending_wakes());
+
+    while !futures.pending_wakes().is_empty() {
+        assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    }
+
+    (futures, senders)
+}
+
+#[tokio::test]
+async fn test_woken_but_not_polled() {
+    let (mut futures, mut senders) = receivers(8).await;
+    assert!((0..8).all(|index| !futures.is_woken(index)));
+
+    let tx5 = senders.remove(5);
+    let tx2 = senders.remove(2);
+    tx5.send(5).unwrap();
+    tx2.send(2).unwrap();
+
+    assert_eq!(vec![2, 5], futures.pending_wakes());
+    assert!(futures.is_woken(2));
+    assert!(!futures.is_woken(3));
+
+    // Children are drained in index order, so 5 is still waiting to be polled.
+    assert_eq!(Some(Ok(2)), futures.next().await);
+    assert_eq!(vec![5], futures.pending_wakes());
+    assert!(!futures.is_woken(2));
+    assert!(futures.is_woken(5));
+
+    // Wakeups of removed children are not reported.
+    assert!(futures.try_remove(5).is_some());
+    assert!(futures.pending_wakes().is_empty());
+    assert!(!futures.is_woken(5));
+}
+
+#[tokio::test]
+async fn test_wakes_from_other_threads() {
+    let (mut futures, senders) = receivers(64).await;
+
+    // Dropping a sender would also wake its receiver, so hold on to the ones
+    // which aren't used.
+    let (used, _unused): (Vec<_>, Vec<_>) = senders
+        .into_iter()
+        .enumerate()
+        .partition(|(index, _)| index % 3 == 0);
+
+    let sender = thread::spawn(move || {
+        for (index, tx) in used {
+            tx.send(index).unwrap();
+        }
+    });
+
+    sender.join().unwrap();
+
+    let expected = (0..64).step_by(3).collect::<Vec<_>>();
+    assert_eq!(expected, futures.pending_wakes());
+    assert!(expected.iter().all(|index| futures.is_woken(*index)));
+
+    let mut values = Vec::new();
+
+    for _ in 0..expected.len() {
+        values.push(futures.next().await.unwrap().unwrap());
+    }
+
+    assert_eq!(expected, values);
+    assert!(futures.pending_wakes().is_empty());
+}
+
+#[tokio::test]
+async fn test_read_while_waking() {
+    let (futures, senders) = receivers(64).await;
+    let futures = &futures;
+
+    // Reading only needs a shared reference, and doesn't hold up wakers.
+    thread::scope(|s| {
+        s.spawn(move || {
+            for (index, tx) in senders.into_iter().enumerate() {
+                tx.send(index).unwrap();
+            }
+        });
+
+        s.spawn(move || {
+            let mut seen = 0;
+
+            while seen < 64 {
+                let woken = futures.pending_wakes();
+                assert!(woken.len() >= seen);
+                seen = woken.len();
+            }
+        });
+    });
+
+    assert_eq!((0..64).collect::<Vec<_>>(), futures.pending_wakes());
+    assert!((0..64).all(|index| futures.is_woken(index)));
+}
+
+#[cfg(feature = "std")]
+#[tokio::test]
+async fn test_local_pending_wakes() {
+    use unicycle::LocalFuturesUnordered;
+
+    let mut futures = LocalFuturesUnordered::new();
+    let mut senders = Vec::new();
+
+    for _ in 0..4 {
+        let (tx, rx) = oneshot::channel();
+        futures.push(rx);
+        senders.push(tx);
+    }
+
+    assert_eq!(vec![0, 1, 2, 3], futures.pending_wakes());
+
+    while !futures.pending_wakes().is_empty() {
+        assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    }
+
+    senders.remove(1).send(1).unwrap();
+    assert_eq!(vec![1], futures.pending_wakes());
+    assert!(futures.is_woken(1));
+    assert!(!futures.is_woken(0));
+
+    assert_eq!(Some(Ok(1)), futures.next().await);
+    assert!(futures.pending_wakes().is_empty());
+}
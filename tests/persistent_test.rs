@@ -0,0 +1,48 @@
+use futures::future::{self, poll_fn};
+use futures::stream::StreamExt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time;
+use unicycle::FuturesUnordered;
+
+#[tokio::test]
+async fn test_persistent_waits_for_push() {
+    let futures = Arc::new(Mutex::new(FuturesUnordered::new()));
+    futures.lock().unwrap().set_persistent(true);
+
+    let task = tokio::spawn({
+        let futures = futures.clone();
+
+        async move {
+            let mut received = Vec::new();
+
+            while let Some(value) = poll_fn(|cx| futures.lock().unwrap().poll_next_unpin(cx)).await
+            {
+                received.push(value);
+            }
+
+            received
+        }
+    });
+
+    for n in 0..4 {
+        time::sleep(Duration::from_millis(10)).await;
+        futures.lock().unwrap().push(future::ready(n));
+    }
+
+    time::sleep(Duration::from_millis(10)).await;
+    futures.lock().unwrap().close();
+
+    let received = time::timeout(Duration::from_secs(5), task)
+        .await
+        .expect("task to complete")
+        .unwrap();
+
+    assert_eq!(vec![0, 1, 2, 3], received);
+}
+
+#[tokio::test]
+async fn test_non_persistent_terminates_when_empty() {
+    let mut futures = FuturesUnordered::<future::Ready<()>>::new();
+    assert_eq!(None, futures.next().await);
+}
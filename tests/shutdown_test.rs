@@ -0,0 +1,74 @@
+use futures::future;
+use futures::stream::FusedStream;
+use std::time::Duration;
+use tokio::time;
+use unicycle::FuturesUnordered;
+
+#[tokio::test]
+async fn test_close_drains_remaining() {
+    let mut futures = FuturesUnordered::new();
+    futures.push(future::ready(1));
+    futures.push(future::ready(2));
+
+    // An open, empty set is not terminated.
+    assert!(!futures.is_terminated());
+
+    futures.close();
+    assert!(futures.try_push(future::ready(3)).is_err());
+    assert!(!futures.is_terminated());
+
+    let mut received = Vec::new();
+
+    while let Some(value) = futures.next().await {
+        received.push(value);
+    }
+
+    received.sort();
+    assert_eq!(vec![1, 2], received);
+    assert!(futures.is_terminated());
+}
+
+#[tokio::test]
+async fn test_empty_open_set_not_terminated() {
+    let mut futures = FuturesUnordered::<future::Ready<()>>::new();
+    assert!(!futures.is_terminated());
+    futures.close();
+    assert!(futures.is_terminated());
+}
+
+#[tokio::test]
+async fn test_shutdown_drops_stragglers() {
+    let mut futures = FuturesUnordered::<future::BoxFuture<'static, ()>>::new();
+
+    futures.push(Box::pin(future::ready(())));
+    let a = futures.push(Box::pin(future::pending()));
+    futures.push(Box::pin(time::sleep(Duration::from_millis(10))));
+    let b = futures.push(Box::pin(future::pending()));
+
+    let dropped = futures
+        .shutdown(time::sleep(Duration::from_millis(100)))
+        .await;
+
+    assert_eq!(vec![a, b], dropped);
+    assert!(futures.is_empty());
+    assert!(futures.is_terminated());
+}
+
+#[tokio::test]
+async fn test_shutdown_completes_before_deadline() {
+    let mut futures = FuturesUnordered::new();
+    futures.push(future::ready(1));
+    futures.push(future::ready(2));
+
+    let dropped = futures.shutdown(future::pending::<()>()).await;
+    assert!(dropped.is_empty());
+    assert!(futures.is_terminated());
+}
+
+#[test]
+#[should_panic]
+fn test_push_closed_panics() {
+    let mut futures = FuturesUnordered::new();
+    futures.close();
+    futures.push(future::ready(()));
+}
@@ -0,0 +1,70 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use unicycle::FuturesUnordered;
+
+/// A future which wakes itself while it is being polled as long as `spin` is
+/// set.
+struct MaybeSpinner<'a> {
+    spin: &'a Cell<bool>,
+}
+
+impl Future for MaybeSpinner<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Wake through a clone, since that's what most leaf futures do.
+        if self.spin.get() {
+            let waker = cx.waker().clone();
+            waker.wake();
+        }
+
+        Poll::Pending
+    }
+}
+
+#[tokio::test]
+async fn test_detect_spinners() {
+    let spin = Cell::new(true);
+    let quiet = Cell::new(false);
+
+    let mut futures = FuturesUnordered::new();
+    futures.set_spin_threshold(Some(3));
+
+    let b = futures.push(MaybeSpinner { spin: &quiet });
+    let a = futures.push(MaybeSpinner { spin: &spin });
+
+    for _ in 0..3 {
+        assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    }
+
+    // Three consecutive cycles doesn't exceed the threshold.
+    assert_eq!(Some(3), futures.spin_count(a));
+    assert_eq!(Some(0), futures.spin_count(b));
+    assert!(futures.spinners().is_empty());
+
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    assert_eq!(vec![a], futures.spinners());
+
+    // Once it stops spinning, the streak is broken.
+    spin.set(false);
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    assert_eq!(Some(0), futures.spin_count(a));
+    assert!(futures.spinners().is_empty());
+}
+
+#[tokio::test]
+async fn test_spinners_disabled() {
+    let spin = Cell::new(true);
+
+    let mut futures = FuturesUnordered::new();
+    let a = futures.push(MaybeSpinner { spin: &spin });
+
+    for _ in 0..8 {
+        assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    }
+
+    assert_eq!(Some(0), futures.spin_count(a));
+    assert!(futures.spinners().is_empty());
+}
@@ -0,0 +1,132 @@
+#![cfg(feature = "stats")]
+
+use futures::future::poll_fn;
+use futures::stream::StreamExt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use unicycle::FuturesUnordered;
+
+/// A future which wakes itself a given number of times before completing.
+struct Countdown(usize);
+
+impl Future for Countdown {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.0 == 0 {
+            return Poll::Ready(());
+        }
+
+        self.0 -= 1;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let mut futures = FuturesUnordered::new();
+    let a = futures.push(Countdown(3));
+    let b = futures.push(Countdown(10));
+
+    poll_fn(|cx| {
+        // Drive three cycles.
+        for _ in 0..3 {
+            assert!(futures.poll_next_unpin(cx).is_pending());
+        }
+
+        Poll::Ready(())
+    })
+    .await;
+
+    let set = futures.stats();
+    assert_eq!(3, set.cycles);
+    // Growing the wake sets when pushing the first task swaps it into the
+    // active set, so it's only polled from the second cycle onwards.
+    assert_eq!(5, set.polled);
+    assert_eq!(2, set.last_cycle_polled);
+    assert_eq!(0, set.spurious_wakes);
+    // One swap to grow the wake sets when pushing, and one for each cycle
+    // after the first which drains the alternate set directly.
+    assert_eq!(3, set.swaps);
+
+    let stats = futures.child_stats(b).expect("child b");
+    assert_eq!(3, stats.polls);
+    assert_eq!(3, stats.wakes);
+    assert!(stats.since_last_wake.is_some());
+
+    // Completing `a` makes its stats unavailable.
+    while futures.next().await.is_some() {}
+    assert!(futures.child_stats(a).is_none());
+}
+
+#[tokio::test]
+async fn test_stats_follow_compaction() {
+    let mut futures = FuturesUnordered::new();
+    let a = futures.push(Countdown(10));
+    let b = futures.push(Countdown(10));
+
+    poll_fn(|cx| {
+        for _ in 0..3 {
+            assert!(futures.poll_next_unpin(cx).is_pending());
+        }
+
+        Poll::Ready(())
+    })
+    .await;
+
+    let before = futures.child_stats(b).expect("child b");
+    assert!(futures.try_remove(a).is_some());
+
+    let mut moved = Vec::new();
+    futures.compact(|old, new| moved.push((old, new)));
+    assert_eq!(vec![(b, a)], moved);
+
+    let after = futures.child_stats(a).expect("moved child b");
+    assert_eq!(before.polls, after.polls);
+    assert_eq!(before.wakes, after.wakes);
+    assert!(futures.child_stats(b).is_none());
+}
+
+/// A future which hands out a clone of its waker the first time it's polled.
+struct Leak(Option<mpsc::Sender<Waker>>);
+
+impl Future for Leak {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(tx) = self.0.take() {
+            tx.send(cx.waker().clone()).unwrap();
+        }
+
+        Poll::Pending
+    }
+}
+
+#[tokio::test]
+async fn test_wakes_from_many_threads() {
+    let (tx, rx) = mpsc::channel();
+    let mut futures = FuturesUnordered::new();
+    let index = futures.push(Leak(Some(tx)));
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+
+    let waker = rx.recv().unwrap();
+    let before = futures.child_stats(index).expect("child").wakes;
+
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    waker.wake_by_ref();
+                }
+            });
+        }
+    });
+
+    let stats = futures.child_stats(index).expect("child");
+    assert_eq!(before + 4000, stats.wakes);
+    assert!(stats.since_last_wake.is_some());
+}
@@ -0,0 +1,175 @@
+#![cfg(all(feature = "testing", feature = "futures-rs"))]
+
+use std::task::Poll;
+use unicycle::testing::{FutureHandle, Harness, ManualFuture, ManualStream};
+use unicycle::{Futures, FuturesUnordered, PollNext, Sentinel, StreamsUnordered, Unordered};
+
+/// Step the harness until it no longer polls anything.
+fn settle<T, S>(harness: &mut Harness<T, S>)
+where
+    S: Sentinel,
+    Unordered<T, S>: PollNext,
+{
+    for _ in 0..8 {
+        if harness.step().polled.is_empty() {
+            return;
+        }
+    }
+
+    panic!("harness did not settle");
+}
+
+/// Construct a harness with `count` manual futures.
+fn futures(
+    count: usize,
+) -> (
+    Harness<ManualFuture<usize>, Futures>,
+    Vec<FutureHandle<usize>>,
+) {
+    let mut harness = Harness::new(FuturesUnordered::new());
+
+    let handles = (0..count)
+        .map(|n| {
+            let (future, handle) = ManualFuture::new();
+            assert_eq!(n, harness.push(future));
+            handle
+        })
+        .collect();
+
+    (harness, handles)
+}
+
+#[test]
+fn test_woken_children_are_polled_in_index_order() {
+    let (mut harness, handles) = futures(4);
+    settle(&mut harness);
+
+    handles[2].wake();
+    handles[0].wake();
+    handles[3].complete(3);
+
+    let step = harness.step();
+    assert_eq!(vec![0, 2, 3], step.polled);
+    assert_eq!(Poll::Ready(Some(3)), step.output);
+
+    assert_eq!(1, handles[1].polls());
+    assert_eq!(2, handles[2].polls());
+    settle(&mut harness);
+}
+
+#[test]
+fn test_spurious_wake() {
+    let (mut harness, handles) = futures(1);
+    settle(&mut harness);
+    assert!(handles[0].is_registered());
+
+    handles[0].wake();
+    assert!(!handles[0].is_registered());
+
+    let step = harness.step();
+    assert_eq!(vec![0], step.polled);
+    assert!(step.output.is_pending());
+    assert_eq!(2, handles[0].polls());
+    assert!(handles[0].is_registered());
+}
+
+#[test]
+fn test_parent_is_woken_once_per_child_wake() {
+    let (mut harness, handles) = futures(2);
+    settle(&mut harness);
+    harness.waker().take_wakes();
+
+    handles[0].complete(0);
+    assert_eq!(1, harness.waker().take_wakes());
+    handles[1].complete(1);
+    assert_eq!(1, harness.waker().take_wakes());
+
+    let step = harness.step();
+    assert_eq!(vec![0], step.polled);
+    assert_eq!(Poll::Ready(Some(0)), step.output);
+
+    let step = harness.step();
+    assert_eq!(vec![1], step.polled);
+    assert_eq!(Poll::Ready(Some(1)), step.output);
+
+    assert_eq!(Poll::Ready(None), harness.step().output);
+}
+
+#[test]
+fn test_streams_are_polled_fairly() {
+    let mut harness = Harness::new(StreamsUnordered::new());
+    let (a, a_handle) = ManualStream::new();
+    let (b, b_handle) = ManualStream::new();
+    let a = harness.push(a);
+    let b = harness.push(b);
+
+    for n in 0..3 {
+        a_handle.send(n);
+        b_handle.send(10 + n);
+    }
+
+    let mut polled = Vec::new();
+    let mut received = Vec::new();
+
+    for _ in 0..6 {
+        let step = harness.step();
+        polled.extend(step.polled);
+
+        if let Poll::Ready(Some(value)) = step.output {
+            received.push(value);
+        }
+    }
+
+    assert_eq!(vec![b, a, b, a, b, a], polled);
+    assert_eq!(vec![10, 0, 11, 1, 12, 2], received);
+
+    a_handle.close();
+    b_handle.close();
+
+    for _ in 0..8 {
+        if harness.step().output == Poll::Ready(None) {
+            break;
+        }
+    }
+
+    assert!(harness.get_ref().is_empty());
+}
+
+#[test]
+fn test_handle_wakes_without_holding_lock() {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Wake, Waker};
+
+    /// A waker which inspects the handle of the child it belongs to.
+    struct Inspect {
+        handle: FutureHandle<u32>,
+        registered: Mutex<Vec<bool>>,
+    }
+
+    impl Wake for Inspect {
+        fn wake(self: Arc<Self>) {
+            let registered = self.handle.is_registered();
+            self.registered.lock().unwrap().push(registered);
+        }
+    }
+
+    let (future, handle) = ManualFuture::new();
+
+    let inspect = Arc::new(Inspect {
+        handle,
+        registered: Mutex::new(Vec::new()),
+    });
+
+    let waker = Waker::from(inspect.clone());
+    let mut future = pin!(future);
+    assert!(future
+        .as_mut()
+        .poll(&mut Context::from_waker(&waker))
+        .is_pending());
+
+    inspect.handle.wake();
+    inspect.handle.complete(42);
+    assert_eq!(vec![false], *inspect.registered.lock().unwrap());
+}
@@ -0,0 +1,120 @@
+#![cfg(feature = "tracing")]
+
+use std::future::ready;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use unicycle::FuturesUnordered;
+
+#[derive(Default)]
+struct Recorded {
+    /// (name, parent name, index) of every span created.
+    spans: Vec<(&'static str, Option<&'static str>, Option<u64>)>,
+    /// Values of `drained` recorded by events.
+    drained: Vec<u64>,
+}
+
+#[derive(Clone, Default)]
+struct Recorder {
+    next: Arc<AtomicU64>,
+    names: Arc<Mutex<Vec<&'static str>>>,
+    recorded: Arc<Mutex<Recorded>>,
+}
+
+struct Find(&'static str, Option<u64>);
+
+impl Visit for Find {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == self.0 {
+            self.1 = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let mut find = Find("index", None);
+        attrs.record(&mut find);
+
+        let mut names = self.names.lock().unwrap();
+
+        let parent = attrs.parent().map(|id| names[id.into_u64() as usize - 1]);
+
+        self.recorded
+            .lock()
+            .unwrap()
+            .spans
+            .push((attrs.metadata().name(), parent, find.1));
+
+        names.push(attrs.metadata().name());
+        Id::from_u64(self.next.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut find = Find("drained", None);
+        event.record(&mut find);
+
+        if let Some(drained) = find.1 {
+            self.recorded.lock().unwrap().drained.push(drained);
+        }
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[tokio::test]
+async fn test_child_spans() {
+    let recorder = Recorder::default();
+    let _guard = tracing::subscriber::set_default(recorder.clone());
+
+    let mut futures = FuturesUnordered::new();
+    futures.push_with_span(ready(1), tracing::info_span!("request"));
+    futures.push(ready(2));
+
+    let mut received = Vec::new();
+
+    while let Some(value) = futures.next().await {
+        received.push(value);
+    }
+
+    received.sort();
+    assert_eq!(vec![1, 2], received);
+
+    let recorded = recorder.recorded.lock().unwrap();
+
+    assert!(recorded.spans.contains(&("poll", Some("request"), Some(0))));
+    assert!(recorded.spans.contains(&("poll", None, Some(1))));
+}
+
+#[tokio::test]
+async fn test_cycle_events() {
+    let recorder = Recorder::default();
+    let _guard = tracing::subscriber::set_default(recorder.clone());
+
+    let mut futures = FuturesUnordered::new();
+
+    for _ in 0..4 {
+        futures.push(futures::future::pending::<()>());
+    }
+
+    // All pushed tasks are drained over the first two cycles.
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+
+    let recorded = recorder.recorded.lock().unwrap();
+    assert_eq!(4u64, recorded.drained.iter().sum());
+}
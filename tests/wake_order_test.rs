@@ -0,0 +1,212 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use unicycle::{FuturesUnordered, WakeOrder};
+
+type Slot = Arc<Mutex<Option<Waker>>>;
+
+/// A future which records that it was polled and holds on to its waker.
+struct Probe {
+    index: usize,
+    polled: Arc<Mutex<Vec<usize>>>,
+    waker: Slot,
+}
+
+impl Future for Probe {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.polled.lock().unwrap().push(self.index);
+        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+struct Harness {
+    futures: FuturesUnordered<Probe>,
+    polled: Arc<Mutex<Vec<usize>>>,
+    wakers: Vec<Slot>,
+}
+
+impl Harness {
+    /// Construct a collection of `len` probes and poll each of them once, so
+    /// that they all hold a waker.
+    async fn new(order: WakeOrder, len: usize) -> Self {
+        let mut futures = FuturesUnordered::new();
+        futures.set_wake_order(order);
+
+        let polled = Arc::new(Mutex::new(Vec::new()));
+        let mut wakers = Vec::new();
+
+        for index in 0..len {
+            let waker = Slot::default();
+
+            futures.push(Probe {
+                index,
+                polled: polled.clone(),
+                waker: waker.clone(),
+            });
+
+            wakers.push(waker);
+        }
+
+        let mut harness = Self {
+            futures,
+            polled,
+            wakers,
+        };
+
+        let mut polled = Vec::new();
+
+        while polled.len() < len {
+            polled.extend(harness.cycle().await);
+        }
+
+        polled.sort();
+        assert_eq!((0..len).collect::<Vec<_>>(), polled);
+        harness
+    }
+
+    fn wake(&self, index: usize) {
+        self.wakers[index]
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .wake_by_ref();
+    }
+
+    /// Run a single polling cycle and return the children polled in order.
+    async fn cycle(&mut self) -> Vec<usize> {
+        assert!(futures::poll!(Box::pin(self.futures.next())).is_pending());
+        std::mem::take(&mut *self.polled.lock().unwrap())
+    }
+}
+
+#[tokio::test]
+async fn test_fifo_polls_in_wake_order() {
+    let mut harness = Harness::new(WakeOrder::Fifo { capacity: 16 }, 8).await;
+
+    for index in [5, 2, 7, 2, 0, 5] {
+        harness.wake(index);
+    }
+
+    // Repeated wakeups only cause a single poll.
+    assert_eq!(vec![5, 2, 7, 0], harness.cycle().await);
+
+    harness.wake(1);
+    harness.wake(6);
+    assert_eq!(vec![1, 6], harness.cycle().await);
+}
+
+#[tokio::test]
+async fn test_fifo_overflow_falls_back_to_index_order() {
+    let mut harness = Harness::new(WakeOrder::Fifo { capacity: 2 }, 8).await;
+
+    for index in [6, 3, 7, 1, 4] {
+        harness.wake(index);
+    }
+
+    assert_eq!(vec![6, 3, 1, 4, 7], harness.cycle().await);
+
+    // The record is reset for the next cycle.
+    harness.wake(5);
+    harness.wake(0);
+    assert_eq!(vec![5, 0], harness.cycle().await);
+}
+
+#[tokio::test]
+async fn test_fifo_repeated_wakeups_take_a_single_slot() {
+    let mut harness = Harness::new(WakeOrder::Fifo { capacity: 3 }, 8).await;
+
+    // A child which keeps waking itself ahead of the others doesn't push them
+    // out of the record.
+    for _ in 0..4 {
+        harness.wake(5);
+    }
+
+    harness.wake(7);
+    harness.wake(5);
+    harness.wake(2);
+    assert_eq!(vec![5, 7, 2], harness.cycle().await);
+
+    for _ in 0..4 {
+        harness.wake(5);
+        harness.wake(3);
+    }
+
+    harness.wake(1);
+    assert_eq!(vec![5, 3, 1], harness.cycle().await);
+}
+
+#[tokio::test]
+async fn test_index_order() {
+    let mut harness = Harness::new(WakeOrder::Index, 8).await;
+
+    for index in [5, 2, 7, 0] {
+        harness.wake(index);
+    }
+
+    assert_eq!(vec![0, 2, 5, 7], harness.cycle().await);
+}
+
+#[tokio::test]
+async fn test_switch_wake_order() {
+    let mut harness = Harness::new(WakeOrder::Index, 4).await;
+
+    // Wakeups from before the switch are still polled.
+    harness.wake(3);
+    harness.wake(1);
+    harness
+        .futures
+        .set_wake_order(WakeOrder::Fifo { capacity: 4 });
+    assert_eq!(
+        WakeOrder::Fifo { capacity: 4 },
+        harness.futures.wake_order()
+    );
+    assert_eq!(vec![1, 3], harness.cycle().await);
+
+    harness.wake(3);
+    harness.wake(1);
+    assert_eq!(vec![3, 1], harness.cycle().await);
+}
+
+#[cfg(feature = "std")]
+#[tokio::test]
+async fn test_local_fifo_polls_in_wake_order() {
+    use futures::future::poll_fn;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use unicycle::LocalFuturesUnordered;
+
+    let polled = Rc::new(RefCell::new(Vec::new()));
+    let wakers = Rc::new(RefCell::new(vec![None::<Waker>; 4]));
+
+    let mut futures = LocalFuturesUnordered::new();
+    futures.set_wake_order(WakeOrder::Fifo { capacity: 8 });
+
+    for index in 0..4 {
+        let polled = polled.clone();
+        let wakers = wakers.clone();
+
+        futures.push(poll_fn(move |cx| {
+            polled.borrow_mut().push(index);
+            wakers.borrow_mut()[index] = Some(cx.waker().clone());
+            Poll::<()>::Pending
+        }));
+    }
+
+    while polled.borrow().len() < 4 {
+        assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    }
+
+    polled.take();
+
+    for index in [2, 0, 2, 3] {
+        wakers.borrow()[index].as_ref().unwrap().wake_by_ref();
+    }
+
+    assert!(futures::poll!(Box::pin(futures.next())).is_pending());
+    assert_eq!(vec![2, 0, 3], polled.take());
+}
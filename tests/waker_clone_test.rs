@@ -0,0 +1,138 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+use unicycle::FuturesUnordered;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// A future which clones its waker a number of times when polled, and checks
+/// that all clones are considered equivalent.
+struct Cloner {
+    wakers: Vec<Waker>,
+    allocations: Option<usize>,
+}
+
+impl Future for Cloner {
+    type Output = usize;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(allocations) = self.allocations {
+            return Poll::Ready(allocations);
+        }
+
+        let before = ALLOCATIONS.load(Ordering::SeqCst);
+
+        for _ in 0..self.wakers.capacity() {
+            let waker = cx.waker().clone();
+            self.wakers.push(waker);
+        }
+
+        let after = ALLOCATIONS.load(Ordering::SeqCst);
+
+        for w in &self.wakers {
+            assert!(w.will_wake(&self.wakers[0]));
+        }
+
+        let waker = self.wakers.pop().unwrap();
+        self.allocations = Some(after - before);
+        waker.wake();
+        self.wakers.clear();
+        Poll::Pending
+    }
+}
+
+#[test]
+fn test_waker_clone_does_not_allocate() {
+    let mut futures = FuturesUnordered::new();
+
+    for _ in 0..4 {
+        futures.push(Cloner {
+            wakers: Vec::with_capacity(128),
+            allocations: None,
+        });
+    }
+
+    let results = futures::executor::block_on(async {
+        let mut results = Vec::new();
+
+        while let Some(allocations) = futures.next().await {
+            results.push(allocations);
+        }
+
+        results
+    });
+
+    assert_eq!(vec![0, 0, 0, 0], results);
+}
+
+/// A future which stores its waker the first time it's polled, and records
+/// whether the waker it's polled with later is considered the same.
+struct Remember {
+    waker: Option<Waker>,
+    polls: usize,
+}
+
+impl Future for Remember {
+    type Output = bool;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.polls += 1;
+
+        if let Some(waker) = &self.waker {
+            if self.polls > 3 {
+                return Poll::Ready(waker.will_wake(cx.waker()));
+            }
+
+            if !waker.will_wake(cx.waker()) {
+                return Poll::Ready(false);
+            }
+        } else {
+            self.waker = Some(cx.waker().clone());
+        }
+
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[test]
+fn test_will_wake_stable_across_polls() {
+    let mut futures = FuturesUnordered::new();
+
+    for _ in 0..4 {
+        futures.push(Remember {
+            waker: None,
+            polls: 0,
+        });
+    }
+
+    let results = futures::executor::block_on(async {
+        let mut results = Vec::new();
+
+        while let Some(stable) = futures.next().await {
+            results.push(stable);
+        }
+
+        results
+    });
+
+    assert_eq!(vec![true, true, true, true], results);
+}